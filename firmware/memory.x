@@ -1,7 +1,9 @@
 MEMORY
 {
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+  /* The upper 128K of flash hold the game ROM, see ROM_BASE in main.rs. The
+     link fails if the firmware outgrows the lower half. */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 128K
   RAM : ORIGIN = 0x20000000, LENGTH = 40K
 }
 
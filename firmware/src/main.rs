@@ -0,0 +1,119 @@
+#![no_std]
+#![no_main]
+#![feature(default_alloc_error_handler, alloc_error_handler)]
+
+extern crate alloc;
+
+use panic_halt as _;
+
+use alloc::rc::Rc;
+use alloc_cortex_m::CortexMHeap;
+use core::cell::Cell;
+use core::fmt::{self, Write};
+use cortex_m_rt::entry;
+
+use gb::console::Console;
+use gb::debugger::Debugger;
+use gb::diagnostics::{Diagnostics, Level, WriteSink};
+use gb::gdb::GdbStub;
+use gb::header::CartridgeHeader;
+use gb::Gameboy;
+use stm32f3_discovery::stm32f3xx_hal::prelude::*;
+
+mod peripherals;
+
+use peripherals::Usart;
+
+#[global_allocator]
+static ALLOCATOR: CortexMHeap = CortexMHeap::empty();
+
+/// Games are flashed separately to the upper half of flash, see the README.
+const ROM_BASE: usize = 0x0802_0000;
+const ROM_CAPACITY: usize = 0x2_0000;
+
+/// T-cycles run between polls of USART1, short enough that received bytes
+/// don't overrun its single byte of receive buffer.
+const USART_POLL: u32 = 456 * 8;
+
+/// Diagnostics output, dropped while GDB owns USART1 as it would corrupt packets.
+struct DiagOutput {
+    usart: Usart,
+    muted: Rc<Cell<bool>>,
+}
+
+impl fmt::Write for DiagOutput {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.muted.get() {
+            return Ok(());
+        }
+        self.usart.write_str(s)
+    }
+}
+
+fn flashed_rom() -> &'static [u8] {
+    let mut flash: &'static [u8] =
+        unsafe { core::slice::from_raw_parts(ROM_BASE as *const u8, ROM_CAPACITY) };
+    // The header gives the actual length, the loader rejects anything that doesn't fit
+    let len = CartridgeHeader::parse(&mut flash)
+        .ok()
+        .and_then(|header| header.rom_len())
+        .unwrap_or(ROM_CAPACITY)
+        .min(ROM_CAPACITY);
+    &flash[..len]
+}
+
+#[entry]
+fn main() -> ! {
+    let peripherals = peripherals::init();
+
+    unsafe { ALLOCATOR.init(cortex_m_rt::heap_start() as usize, 0x8000) };
+
+    let mut usart = peripherals.usart;
+    let mut usart_rx = peripherals.usart_rx;
+    let gdb_attached = Rc::new(Cell::new(false));
+    let diag = Diagnostics::new(WriteSink::new(DiagOutput {
+        usart: usart.clone(),
+        muted: gdb_attached.clone(),
+    }))
+    .with_level(Level::Warn);
+
+    let gameboy = match Gameboy::new(flashed_rom(), diag) {
+        Ok(gameboy) => gameboy,
+        Err(err) => {
+            // panic_halt doesn't print anything, say why before halting
+            let _ = write!(usart, "Can't run the flashed ROM: {}\r\n", err);
+            panic!("no valid ROM flashed");
+        }
+    };
+
+    // The game runs right away. USART1 serves the debugger console, until a
+    // GDB packet arrives and the GDB stub takes over until GDB detaches.
+    let mut debugger = Debugger::new(gameboy);
+    let mut console = Console::new();
+    let mut gdb = GdbStub::new();
+    console.set_running(true);
+    loop {
+        while let Ok(byte) = usart_rx.read() {
+            if gdb.claims(byte, &console) {
+                let _ = gdb.input(byte, &mut debugger, &mut usart);
+                if gdb_attached.get() && !gdb.is_attached() {
+                    // Detaching lets the game run on
+                    console.set_running(true);
+                }
+                gdb_attached.set(gdb.is_attached());
+            } else {
+                let _ = console.input(byte, &mut debugger, &mut usart);
+            }
+        }
+
+        if gdb.is_attached() {
+            if gdb.is_running() {
+                let event = debugger.run(USART_POLL);
+                let _ = gdb.report(event, &mut usart);
+            }
+        } else if console.is_running() {
+            let event = debugger.run(USART_POLL);
+            let _ = console.report(event, &mut debugger, &mut usart);
+        }
+    }
+}
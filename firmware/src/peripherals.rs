@@ -0,0 +1,55 @@
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::fmt;
+use stm32f3_discovery::stm32f3xx_hal::{
+    block,
+    pac::{self, USART1},
+    prelude::*,
+    serial::{Rx, Serial, Tx},
+};
+
+pub struct Peripherals {
+    pub usart: Usart,
+    pub usart_rx: Rx<USART1>,
+}
+
+pub fn init() -> Peripherals {
+    let dp = pac::Peripherals::take().unwrap();
+
+    let mut flash = dp.FLASH.constrain();
+    let mut rcc = dp.RCC.constrain();
+
+    let mut gpioc = dp.GPIOC.split(&mut rcc.ahb);
+
+    let clocks = rcc
+        .cfgr
+        .use_hse(8u32.mhz())
+        .sysclk(72u32.mhz())
+        .pclk1(24u32.mhz())
+        .freeze(&mut flash.acr);
+
+    let tx = gpioc.pc4.into_af7(&mut gpioc.moder, &mut gpioc.afrl);
+    let rx = gpioc.pc5.into_af7(&mut gpioc.moder, &mut gpioc.afrl);
+    let (tx, rx) =
+        Serial::usart1(dp.USART1, (tx, rx), 115_200.bps(), clocks, &mut rcc.apb2).split();
+
+    Peripherals {
+        usart: Usart(Rc::new(RefCell::new(tx))),
+        usart_rx: rx,
+    }
+}
+
+/// Blocking text output on USART1 (PC4 TX, PC5 RX, 115200 8N1). Clones share
+/// the transmitter, so diagnostics and the console can both print.
+#[derive(Clone)]
+pub struct Usart(Rc<RefCell<Tx<USART1>>>);
+
+impl fmt::Write for Usart {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut tx = self.0.borrow_mut();
+        for byte in s.bytes() {
+            block!(tx.write(byte)).map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+//! Measures emulation speed on the host.
+//!
+//! Usage: `cargo run --release -p gb --example bench [ROM] [FRAMES]`
+//!
+//! Without a ROM, a small built-in program copying memory through a call-heavy
+//! loop is used, which keeps the CPU and bus busy without needing test ROMs.
+
+use gb::diagnostics::Diagnostics;
+use gb::Gameboy;
+use std::time::Instant;
+
+#[rustfmt::skip]
+const PROGRAM: [u8; 0x17] = [
+    0x31, 0xFF, 0xDF, // LD SP, 0xDFFF
+    0x21, 0x00, 0xC0, // loop: LD HL, 0xC000
+    0x11, 0x00, 0x02, // LD DE, 0x0200
+    0x06, 0x00,       // LD B, 0x00
+    0x1A,             // copy: LD A, [DE]
+    0x13,             // INC DE
+    0xA8,             // XOR B
+    0x22,             // LD [HL+], A
+    0xCD, 0x50, 0x01, // CALL sub
+    0x05,             // DEC B
+    0x20, 0xF6,       // JR NZ, copy
+    0x18, 0xEC,       // JR loop
+];
+
+#[rustfmt::skip]
+const SUBROUTINE: [u8; 6] = [
+    0xC5,       // sub: PUSH BC
+    0xCB, 0x39, // SRL C
+    0x81,       // ADD A, C
+    0xC1,       // POP BC
+    0xC9,       // RET
+];
+
+fn builtin_rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x100 + PROGRAM.len()].copy_from_slice(&PROGRAM);
+    rom[0x150..0x150 + SUBROUTINE.len()].copy_from_slice(&SUBROUTINE);
+    for (i, byte) in rom[0x200..0x300].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    // Without a valid header checksum the ROM is rejected
+    rom[0x014D] = rom[0x0134..0x014D]
+        .iter()
+        .fold(0u8, |x, byte| x.wrapping_sub(*byte).wrapping_sub(1));
+    rom
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let rom = match args.next() {
+        Some(path) => std::fs::read(&path).expect("failed to read ROM"),
+        None => builtin_rom(),
+    };
+    let frames: u32 = args
+        .next()
+        .map(|frames| frames.parse().expect("invalid frame count"))
+        .unwrap_or(600);
+
+    let mut gameboy = Gameboy::new(rom, Diagnostics::default()).expect("invalid ROM");
+
+    let start = Instant::now();
+    #[cfg(target_arch = "x86_64")]
+    let start_tsc = unsafe { core::arch::x86_64::_rdtsc() };
+
+    let mut done = 0;
+    while done < frames {
+        if gameboy.run_frame().outcome == gb::Outcome::FrameReady {
+            done += 1;
+        }
+    }
+
+    let elapsed = start.elapsed();
+    println!(
+        "{} frames in {:.3?}, {:.1} us/frame, {:.1}x real time",
+        frames,
+        elapsed,
+        elapsed.as_secs_f64() * 1e6 / frames as f64,
+        frames as f64 / 59.73 / elapsed.as_secs_f64()
+    );
+    #[cfg(target_arch = "x86_64")]
+    {
+        let cycles = unsafe { core::arch::x86_64::_rdtsc() } - start_tsc;
+        println!("{} host TSC cycles/frame", cycles / frames as u64);
+    }
+}
@@ -0,0 +1,187 @@
+//! Runs blargg's test ROMs headlessly and records the results.
+//!
+//! Usage: `cargo run --release -p gb --example blargg DIR [--timeout SECS] [--write FILE]`
+//!
+//! `DIR` is a checkout of blargg's tests, e.g. <https://github.com/retrio/gb-test-roms>.
+//! Every suite is run from its single ROMs if it has them, otherwise from its
+//! combined ROM. A ROM passes or fails as soon as it says so on the serial port
+//! or through the signature at $A001 and the result code at $A000, and fails
+//! after `SECS` emulated seconds (120 by default) otherwise.
+//!
+//! With `--write`, the `## blargg` section of `FILE` (e.g. `passing_tests.md`)
+//! is replaced by the results, the rest of the file is kept.
+
+mod common;
+
+use gb::diagnostics::Diagnostics;
+use gb::{Gameboy, Outcome};
+use std::path::{Path, PathBuf};
+
+const SUITES: [&str; 8] = [
+    "cpu_instrs",
+    "instr_timing",
+    "mem_timing",
+    "mem_timing-2",
+    "interrupt_time",
+    "oam_bug",
+    "dmg_sound",
+    "halt_bug",
+];
+
+/// M-cycles per emulated second.
+const CYCLES_PER_SECOND: u64 = 1 << 20;
+
+/// Written to $A001..=$A003 once the result code at $A000 is valid.
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+/// $A000 while the test is still running.
+const RUNNING: u8 = 0x80;
+
+enum Verdict {
+    Passed,
+    Failed(String),
+}
+
+/// The ROMs of `suite` in `dir`, in the order they run.
+fn suite_roms(dir: &Path, suite: &str) -> Vec<PathBuf> {
+    let suite_dir = dir.join(suite);
+    for singles in ["individual", "rom_singles"] {
+        if let Ok(entries) = std::fs::read_dir(suite_dir.join(singles)) {
+            let mut roms: Vec<PathBuf> = entries
+                .map(|entry| entry.expect("failed to read test directory").path())
+                .filter(|path| path.extension().is_some_and(|ext| ext == "gb"))
+                .collect();
+            roms.sort();
+            return roms;
+        }
+    }
+    // halt_bug.gb sits next to the suite directories
+    let combined = vec![suite_dir.join(suite), dir.join(suite)];
+    combined
+        .into_iter()
+        .map(|path| path.with_extension("gb"))
+        .filter(|path| path.exists())
+        .take(1)
+        .collect()
+}
+
+/// The text the test ROM left after its result code, NUL-terminated.
+fn result_text(gameboy: &mut Gameboy) -> String {
+    (0xA004..0xC000)
+        .map(|addr| gameboy.peek(addr))
+        .take_while(|&byte| byte != 0x0)
+        .map(|byte| byte as char)
+        .collect()
+}
+
+fn run(rom: Vec<u8>, timeout: u64) -> Verdict {
+    let mut gameboy = match Gameboy::new(rom, Diagnostics::default()) {
+        Ok(gameboy) => gameboy,
+        Err(err) => return Verdict::Failed(format!("not loaded: {}", err)),
+    };
+
+    let mut serial = String::new();
+    while gameboy.cycles() < timeout * CYCLES_PER_SECOND {
+        match gameboy.run_frame().outcome {
+            Outcome::SerialByte(val) => {
+                serial.push(val as char);
+                if serial.contains("Passed") {
+                    return Verdict::Passed;
+                }
+                // Wait for the rest of the line, e.g. "Failed #2"
+                if let Some(at) = serial.find("Failed") {
+                    if serial[at..].ends_with('\n') {
+                        return Verdict::Failed(serial[at..].trim().into());
+                    }
+                }
+            }
+            Outcome::FrameReady => {
+                let signature = [
+                    gameboy.peek(0xA001),
+                    gameboy.peek(0xA002),
+                    gameboy.peek(0xA003),
+                ];
+                let code = gameboy.peek(0xA000);
+                if signature == SIGNATURE && code != RUNNING {
+                    return match code {
+                        0x00 => Verdict::Passed,
+                        code => Verdict::Failed(format!(
+                            "result {:02X}: {}",
+                            code,
+                            result_text(&mut gameboy).trim()
+                        )),
+                    };
+                }
+            }
+            Outcome::LockedUp => return Verdict::Failed("CPU locked up".into()),
+            Outcome::Completed
+            | Outcome::Breakpoint(_)
+            | Outcome::SoftwareBreakpoint(_)
+            | Outcome::Watchpoint(_) => {}
+        }
+    }
+    match serial.find("Failed") {
+        Some(at) => Verdict::Failed(serial[at..].trim().into()),
+        None => Verdict::Failed(format!("timed out after {} s", timeout)),
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let dir = args
+        .next()
+        .expect("usage: blargg DIR [--timeout SECS] [--write FILE]");
+    let mut timeout = 120;
+    let mut write = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--timeout" => {
+                timeout = args
+                    .next()
+                    .and_then(|secs| secs.parse().ok())
+                    .expect("--timeout takes a number of seconds")
+            }
+            "--write" => write = Some(args.next().expect("--write takes a file")),
+            _ => panic!("unknown argument {}", arg),
+        }
+    }
+
+    let mut section = String::from("## blargg\n");
+    let mut passed = 0;
+    let mut failed = 0;
+    for suite in SUITES {
+        let roms = suite_roms(Path::new(&dir), suite);
+        if roms.is_empty() {
+            continue;
+        }
+        section.push_str(&format!("### {}\n", suite));
+        for path in roms {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            let rom = std::fs::read(&path).expect("failed to read ROM");
+            match run(rom, timeout) {
+                Verdict::Passed => {
+                    println!("PASS {}/{}", suite, name);
+                    section.push_str(&format!("- [x] {}\n", name));
+                    passed += 1;
+                }
+                Verdict::Failed(reason) => {
+                    let reason = reason.replace('\n', " ");
+                    println!("FAIL {}/{}: {}", suite, name, reason);
+                    section.push_str(&format!("- [ ] {} ({})\n", name, reason));
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if passed + failed == 0 {
+        eprintln!("No test ROMs found in {}", dir);
+        std::process::exit(2);
+    }
+    println!("{} passed, {} failed", passed, failed);
+
+    if let Some(file) = write {
+        common::write_section(&file, "## blargg\n", &section);
+    }
+    if failed != 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,67 @@
+//! Shared by the test ROM runners.
+
+/// Replaces the section starting with `heading` up to the next `## ` heading,
+/// or appends it if `results` doesn't have it yet.
+pub fn replace_section(results: &str, heading: &str, section: &str) -> String {
+    let start = match results.find(heading) {
+        Some(start) => start,
+        None if results.trim().is_empty() => return section.into(),
+        None => return format!("{}\n{}", results.trim_end(), section),
+    };
+    let after = start + heading.len();
+    let end = results[after..]
+        .find("\n## ")
+        .map_or(results.len(), |end| after + end + 1);
+    format!("{}{}{}", &results[..start], section, &results[end..])
+}
+
+/// Rewrites the section of the results file `file` starting with `heading`.
+pub fn write_section(file: &str, heading: &str, section: &str) {
+    let results = std::fs::read_to_string(file).unwrap_or_default();
+    std::fs::write(file, replace_section(&results, heading, section))
+        .expect("failed to write results");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESULTS: &str = "# Results\n## blargg\n- [ ] old\n## mooneye\n- [x] keep\n";
+
+    #[test]
+    fn replaces_a_section_in_the_middle() {
+        assert_eq!(
+            replace_section(RESULTS, "## blargg\n", "## blargg\n- [x] new\n"),
+            "# Results\n## blargg\n- [x] new\n## mooneye\n- [x] keep\n"
+        );
+    }
+
+    #[test]
+    fn replaces_the_last_section() {
+        assert_eq!(
+            replace_section(RESULTS, "## mooneye\n", "## mooneye\n- [ ] new\n"),
+            "# Results\n## blargg\n- [ ] old\n## mooneye\n- [ ] new\n"
+        );
+    }
+
+    #[test]
+    fn replaces_subsections_too() {
+        let results = "## blargg\n### cpu_instrs\n- [ ] a\n### halt_bug\n- [ ] b\n## mooneye\n";
+        assert_eq!(
+            replace_section(results, "## blargg\n", "## blargg\n- [x] c\n"),
+            "## blargg\n- [x] c\n## mooneye\n"
+        );
+    }
+
+    #[test]
+    fn appends_missing_sections() {
+        assert_eq!(
+            replace_section("# Results\n\n", "## mooneye\n", "## mooneye\n- [x] a\n"),
+            "# Results\n## mooneye\n- [x] a\n"
+        );
+        assert_eq!(
+            replace_section("", "## mooneye\n", "## mooneye\n"),
+            "## mooneye\n"
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Debugs a ROM from the terminal.
+//!
+//! Usage: `cargo run --release -p gb --example debug ROM`
+//!
+//! Starts with the machine stopped at the entry point, type `help` for the
+//! commands. `continue` runs until a breakpoint or watchpoint hits, Ctrl-C quits.
+//!
+//! Labels from the symbol file next to the ROM, e.g. `game.sym` for `game.gb`,
+//! are shown and can be used in place of addresses.
+
+use gb::console::Console;
+use gb::debugger::Debugger;
+use gb::diagnostics::Diagnostics;
+use gb::symbols::Symbols;
+use gb::Gameboy;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Collects console output, it's written to stdout once a command is done.
+#[derive(Default)]
+struct Output(String);
+
+impl std::fmt::Write for Output {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0.push_str(s);
+        Ok(())
+    }
+}
+
+impl Output {
+    fn flush(&mut self) {
+        if self.0.is_empty() {
+            return;
+        }
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(self.0.replace("\r\n", "\n").as_bytes())
+            .and_then(|_| stdout.flush())
+            .expect("failed to write to stdout");
+        self.0.clear();
+    }
+}
+
+fn main() {
+    let path = std::env::args().nth(1).expect("usage: debug ROM");
+    let rom = std::fs::read(&path).expect("failed to read ROM");
+    let gameboy = Gameboy::new(rom, Diagnostics::default()).expect("invalid ROM");
+
+    let mut debugger = Debugger::new(gameboy);
+    let mut console = Console::new();
+    if let Ok(text) = std::fs::read_to_string(Path::new(&path).with_extension("sym")) {
+        console.set_symbols(Symbols::parse(&text));
+    }
+    let mut out = Output::default();
+    console
+        .execute("dis", &mut debugger, &mut out)
+        .and_then(|_| std::fmt::Write::write_str(&mut out, "> "))
+        .unwrap();
+    out.flush();
+
+    for line in std::io::stdin().lock().lines() {
+        let line = line.expect("failed to read stdin");
+        console.execute(&line, &mut debugger, &mut out).unwrap();
+        while console.is_running() {
+            let event = debugger.run_frame();
+            console.report(event, &mut debugger, &mut out).unwrap();
+            // Serial output shows up while running
+            out.flush();
+        }
+        std::fmt::Write::write_str(&mut out, "> ").unwrap();
+        out.flush();
+    }
+}
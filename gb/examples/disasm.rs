@@ -0,0 +1,56 @@
+//! Disassembles a ROM linearly.
+//!
+//! Usage: `cargo run -p gb --example disasm ROM [ADDR] [COUNT]`
+//!
+//! Starts at `ADDR` (hex, 0100 by default) and prints `COUNT` instructions, 32
+//! by default. Data is disassembled as if it was code.
+//!
+//! If there is a symbol file next to the ROM, e.g. `game.sym` for `game.gb`,
+//! its labels are shown and `ADDR` can be one of them. Only banks 0 and 1 are
+//! mapped, labels in other banks are refused.
+
+use gb::diagnostics::Diagnostics;
+use gb::symbols::Symbols;
+use gb::Gameboy;
+use std::path::Path;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().expect("usage: disasm ROM [ADDR] [COUNT]");
+    let rom = std::fs::read(&path).expect("failed to read ROM");
+    let symbols = std::fs::read_to_string(Path::new(&path).with_extension("sym"))
+        .map(|text| Symbols::parse(&text))
+        .unwrap_or_default();
+    let mut gameboy = Gameboy::new(rom, Diagnostics::default()).expect("invalid ROM");
+    let mut addr = args
+        .next()
+        .map(|addr| match symbols.parse_addr(&addr, &gameboy) {
+            Ok(addr) => addr,
+            Err(err) => panic!("invalid address: {}", err),
+        })
+        .unwrap_or(0x0100);
+    let count: usize = args
+        .next()
+        .map(|count| count.parse().expect("invalid instruction count"))
+        .unwrap_or(32);
+
+    for _ in 0..count {
+        let instr = gameboy.disassemble(addr);
+        let bytes: Vec<String> = instr
+            .bytes()
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        if let Some(label) = symbols.label(gameboy.rom_bank(addr), addr) {
+            println!("{}:", label);
+        }
+        let labels = |target| symbols.label(gameboy.rom_bank(target), target);
+        println!(
+            "{:04X}  {:<8}  {}",
+            addr,
+            bytes.join(" "),
+            instr.labelled(&labels)
+        );
+        addr = instr.next_addr();
+    }
+}
@@ -0,0 +1,66 @@
+//! Serves a ROM to GDB over TCP.
+//!
+//! Usage: `cargo run --release -p gb --example gdb ROM [PORT]`
+//!
+//! Waits for GDB on `PORT` (2159 by default) with the machine stopped at the
+//! entry point, then connect with e.g.
+//! `gdb-multiarch -ex "set architecture z80" -ex "target remote :2159"`.
+//! Exits once GDB disconnects.
+
+use gb::debugger::Debugger;
+use gb::diagnostics::Diagnostics;
+use gb::gdb::GdbStub;
+use gb::Gameboy;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpListener;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().expect("usage: gdb ROM [PORT]");
+    let rom = std::fs::read(&path).expect("failed to read ROM");
+    let port: u16 = args
+        .next()
+        .map(|port| port.parse().expect("invalid port"))
+        .unwrap_or(2159);
+
+    let gameboy = Gameboy::new(rom, Diagnostics::default()).expect("invalid ROM");
+    let mut debugger = Debugger::new(gameboy);
+    let mut stub = GdbStub::new();
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).expect("failed to listen");
+    println!("Waiting for GDB on port {}", port);
+    let (mut stream, _) = listener.accept().expect("failed to accept GDB");
+    stream
+        .set_nodelay(true)
+        .expect("failed to configure socket");
+
+    let mut out = String::new();
+    let mut buf = [0; 1024];
+    loop {
+        // Only wait for GDB while it has the machine stopped
+        stream
+            .set_nonblocking(stub.is_running())
+            .expect("failed to configure socket");
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => {
+                for &byte in &buf[..len] {
+                    stub.input(byte, &mut debugger, &mut out).unwrap();
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(err) => panic!("failed to read from GDB: {}", err),
+        }
+
+        if stub.is_running() {
+            let event = debugger.run_frame();
+            stub.report(event, &mut out).unwrap();
+        }
+        if !out.is_empty() {
+            stream
+                .write_all(out.as_bytes())
+                .expect("failed to write to GDB");
+            out.clear();
+        }
+    }
+}
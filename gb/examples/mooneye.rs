@@ -0,0 +1,247 @@
+//! Runs mooneye's test ROMs on every emulated model they're meant for.
+//!
+//! Usage: `cargo run --release -p gb --example mooneye DIR [--timeout SECS] [--write FILE]`
+//!
+//! `DIR` holds the built mooneye test suite, the ROMs in its `acceptance`,
+//! `emulator-only` and `misc` directories are run. A test is done once it
+//! executes LD B, B: it passed if B, C, D, E, H and L then hold the Fibonacci
+//! numbers 3, 5, 8, 13, 21 and 34, and failed otherwise. Tests that don't get
+//! there within `SECS` emulated seconds (20 by default) fail.
+//!
+//! The models a test is meant for follow from its name, e.g. `boot_regs-dmgABC`
+//! or `unused_hwio-C`. Tests without a suffix run on every model, tests only
+//! meant for models that aren't emulated, e.g. `-sgb` or `-dmg0`, are skipped.
+//!
+//! With `--write`, the `## mooneye` section of `FILE` is replaced by the results.
+
+mod common;
+
+use gb::diagnostics::Diagnostics;
+use gb::{Gameboy, Model, Outcome};
+use std::path::{Path, PathBuf};
+
+const SUITES: [&str; 3] = ["acceptance", "emulator-only", "misc"];
+
+/// M-cycles per emulated second.
+const CYCLES_PER_SECOND: u64 = 1 << 20;
+
+/// B, C, D, E, H and L of a passing test.
+const PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+
+enum Verdict {
+    Passed,
+    Failed(String),
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries {
+        let path = entry.expect("failed to read test directory").path();
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|ext| ext == "gb") {
+            roms.push(path);
+        }
+    }
+}
+
+/// Which of the emulated models a test named `name` is meant for.
+fn models(name: &str) -> Vec<Model> {
+    let suffix = match name.rsplit_once('-') {
+        Some((_, suffix)) => suffix,
+        None => return vec![Model::Dmg, Model::Cgb],
+    };
+
+    let mut dmg = false;
+    let mut cgb = false;
+    let mut rest = suffix;
+    while let Some(first) = rest.chars().next() {
+        if first.is_ascii_uppercase() {
+            // Model groups: G is DMG and MGB, S the SGBs, C is CGB, AGB and AGS, A the AGBs
+            match first {
+                'G' => dmg = true,
+                'C' => cgb = true,
+                _ => {}
+            }
+            rest = &rest[1..];
+            continue;
+        }
+
+        // A model, followed by the SoC revisions the test passes on, e.g. `dmgABC` or `sgb2`
+        let len = rest
+            .find(|c: char| !c.is_ascii_lowercase() && !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if len == 0 {
+            // Not a model suffix after all
+            return vec![Model::Dmg, Model::Cgb];
+        }
+        let (model, after) = rest.split_at(len);
+        let revisions_len = after
+            .find(|c: char| !c.is_ascii_uppercase())
+            .unwrap_or(after.len());
+        let (revisions, after) = after.split_at(revisions_len);
+        // Revision 0 boots into a different state than the emulated later revisions
+        match model {
+            "dmg" => dmg |= revisions.is_empty() || revisions.contains('C'),
+            "cgb" => cgb |= revisions.is_empty() || revisions.contains('E'),
+            _ => {}
+        }
+        rest = after;
+    }
+
+    let mut models = Vec::new();
+    if dmg {
+        models.push(Model::Dmg);
+    }
+    if cgb {
+        models.push(Model::Cgb);
+    }
+    models
+}
+
+fn run(rom: Vec<u8>, model: Model, timeout: u64) -> Verdict {
+    let mut gameboy = match Gameboy::with_model(rom, model, Diagnostics::default()) {
+        Ok(gameboy) => gameboy,
+        Err(err) => return Verdict::Failed(format!("not loaded: {}", err)),
+    };
+    gameboy.set_break_on_ld_b_b(true);
+
+    while gameboy.cycles() < timeout * CYCLES_PER_SECOND {
+        match gameboy.run_frame().outcome {
+            Outcome::SoftwareBreakpoint(_) => {
+                let r = gameboy.registers();
+                let regs = [r.b, r.c, r.d, r.e, r.h, r.l];
+                return if regs == PASSED {
+                    Verdict::Passed
+                } else {
+                    Verdict::Failed(format!(
+                        "B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X}",
+                        r.b, r.c, r.d, r.e, r.h, r.l
+                    ))
+                };
+            }
+            Outcome::LockedUp => return Verdict::Failed("CPU locked up".into()),
+            _ => {}
+        }
+    }
+    Verdict::Failed(format!("timed out after {} s", timeout))
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let dir = args
+        .next()
+        .expect("usage: mooneye DIR [--timeout SECS] [--write FILE]");
+    let mut timeout = 20;
+    let mut write = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--timeout" => {
+                timeout = args
+                    .next()
+                    .and_then(|secs| secs.parse().ok())
+                    .expect("--timeout takes a number of seconds")
+            }
+            "--write" => write = Some(args.next().expect("--write takes a file")),
+            _ => panic!("unknown argument {}", arg),
+        }
+    }
+
+    let mut section = String::from("## mooneye\n");
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+    for suite in SUITES {
+        let suite_dir = Path::new(&dir).join(suite);
+        let mut roms = Vec::new();
+        find_roms(&suite_dir, &mut roms);
+        if roms.is_empty() {
+            continue;
+        }
+        roms.sort();
+
+        let mut results = String::new();
+        for path in roms {
+            let name = path
+                .strip_prefix(&suite_dir)
+                .unwrap()
+                .with_extension("")
+                .to_string_lossy()
+                .into_owned();
+            let models = models(&name);
+            if models.is_empty() {
+                skipped += 1;
+                continue;
+            }
+
+            let rom = std::fs::read(&path).expect("failed to read ROM");
+            for model in models {
+                match run(rom.clone(), model, timeout) {
+                    Verdict::Passed => {
+                        println!("PASS {}/{} ({:?})", suite, name, model);
+                        results.push_str(&format!("- [x] {} ({:?})\n", name, model));
+                        passed += 1;
+                    }
+                    Verdict::Failed(reason) => {
+                        println!("FAIL {}/{} ({:?}): {}", suite, name, model, reason);
+                        results.push_str(&format!("- [ ] {} ({:?}, {})\n", name, model, reason));
+                        failed += 1;
+                    }
+                }
+            }
+        }
+        if !results.is_empty() {
+            section.push_str(&format!("### {}\n{}", suite, results));
+        }
+    }
+    if passed + failed == 0 {
+        eprintln!("No test ROMs found in {}", dir);
+        std::process::exit(2);
+    }
+    println!(
+        "{} passed, {} failed, {} skipped as no emulated model is targeted",
+        passed, failed, skipped
+    );
+
+    if let Some(file) = write {
+        common::write_section(&file, "## mooneye\n", &section);
+    }
+    if failed != 0 {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tests_without_a_suffix_run_everywhere() {
+        assert_eq!(models("ei_sequence"), [Model::Dmg, Model::Cgb]);
+        assert_eq!(models("boot_div"), [Model::Dmg, Model::Cgb]);
+    }
+
+    #[test]
+    fn model_groups() {
+        assert_eq!(models("di_timing-GS"), [Model::Dmg]);
+        assert_eq!(models("unused_hwio-C"), [Model::Cgb]);
+        assert_eq!(models("hblank_ly_scx_timing-GC"), [Model::Dmg, Model::Cgb]);
+        assert_eq!(models("boot_hwio-S"), []);
+    }
+
+    #[test]
+    fn models_and_revisions() {
+        assert_eq!(models("boot_regs-dmgABC"), [Model::Dmg]);
+        assert_eq!(models("boot_regs-cgb"), [Model::Cgb]);
+        assert_eq!(models("boot_hwio-dmgABCmgb"), [Model::Dmg]);
+        assert_eq!(models("boot_div-cgbABCDE"), [Model::Cgb]);
+        // Revision 0 and the SGBs and MGB aren't emulated
+        assert_eq!(models("boot_div-dmg0"), []);
+        assert_eq!(models("boot_regs-sgb2"), []);
+        assert_eq!(models("boot_regs-mgb"), []);
+        assert_eq!(models("boot_div-cgb0"), []);
+    }
+}
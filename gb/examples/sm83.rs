@@ -0,0 +1,301 @@
+//! Runs the SM83 single-step test vectors against the CPU.
+//!
+//! Usage: `cargo run --release -p gb --example sm83 DIR [OPCODE...]`
+//!
+//! `DIR` holds the vectors from <https://github.com/SingleStepTests/sm83>, one
+//! JSON file per opcode (`00.json`, ..., `cb ff.json`). Every test sets up the
+//! registers and RAM, executes one instruction on a flat 64 KiB bus and checks
+//! the registers, RAM and the access done in every M-cycle. Naming opcodes, e.g.
+//! `cb 37`, only runs those files.
+
+use gb::bus::{Bus, Stop};
+use gb::cpu::{Cpu, Ime, Registers};
+use gb::Model;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Tests printed in full per opcode, the rest are only counted.
+const REPORTED_PER_OPCODE: usize = 1;
+
+#[derive(Deserialize)]
+struct Test {
+    name: String,
+    initial: State,
+    #[serde(rename = "final")]
+    expected: State,
+    cycles: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct State {
+    a: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    f: u8,
+    h: u8,
+    l: u8,
+    pc: u16,
+    sp: u16,
+    #[serde(default)]
+    ime: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+impl State {
+    fn registers(&self) -> Registers {
+        Registers {
+            a: self.a,
+            f: self.f,
+            b: self.b,
+            c: self.c,
+            d: self.d,
+            e: self.e,
+            h: self.h,
+            l: self.l,
+            sp: self.sp,
+            pc: self.pc,
+            ime: if self.ime == 0 {
+                Ime::Disabled
+            } else {
+                Ime::Enabled
+            },
+        }
+    }
+}
+
+/// What the CPU did with the bus during one M-cycle.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Cycle {
+    Read(u16, u8),
+    Write(u16, u8),
+    Internal,
+}
+
+impl Cycle {
+    /// Vectors list cycles as `[addr, value, "rwm"]` with `-` for inactive
+    /// pins, or `null` for internal ones.
+    fn parse(value: &Value) -> Option<Self> {
+        let cycle = match value.as_array() {
+            Some(cycle) => cycle,
+            None => return value.is_null().then_some(Cycle::Internal),
+        };
+        let addr = cycle.first()?.as_u64()? as u16;
+        let val = cycle.get(1).and_then(Value::as_u64).unwrap_or(0) as u8;
+        let pins = cycle.get(2)?.as_str()?;
+        Some(if pins.starts_with('r') {
+            Cycle::Read(addr, val)
+        } else if pins.get(1..2) == Some("w") {
+            Cycle::Write(addr, val)
+        } else {
+            Cycle::Internal
+        })
+    }
+}
+
+impl fmt::Debug for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Cycle::Read(addr, val) => write!(f, "R {:04X}={:02X}", addr, val),
+            Cycle::Write(addr, val) => write!(f, "W {:04X}={:02X}", addr, val),
+            Cycle::Internal => write!(f, "-"),
+        }
+    }
+}
+
+/// 64 KiB of RAM with nothing mapped, logging every M-cycle.
+struct FlatBus {
+    mem: Box<[u8; 0x10000]>,
+    cycles: Vec<Cycle>,
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.mem[addr as usize];
+        self.cycles.push(Cycle::Read(addr, val));
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        self.mem[addr as usize] = val;
+        self.cycles.push(Cycle::Write(addr, val));
+    }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        self.mem[addr as usize]
+    }
+
+    fn tick(&mut self) {
+        self.cycles.push(Cycle::Internal);
+    }
+
+    fn tick_stopped(&mut self) {
+        self.cycles.push(Cycle::Internal);
+    }
+
+    // The vectors run STOP on a DMG with no buttons held
+    fn stop(&mut self) -> Stop {
+        Stop::Stopped
+    }
+
+    fn is_stopped(&self) -> bool {
+        false
+    }
+
+    // Interrupts aren't covered by the vectors, IE and IF are plain RAM here
+    fn pending_interrupts(&self) -> u8 {
+        0x0
+    }
+
+    fn acknowledge_interrupt(&mut self, _mask: u8) {}
+}
+
+/// Runs `test`, describing every difference to the expected final state.
+fn run(test: &Test) -> Vec<String> {
+    let mut bus = FlatBus {
+        mem: Box::new([0; 0x10000]),
+        cycles: Vec::new(),
+    };
+    for &(addr, val) in &test.initial.ram {
+        bus.mem[addr as usize] = val;
+    }
+    let mut cpu = Cpu::new(bus, Model::Dmg);
+    cpu.set_registers(&test.initial.registers());
+    cpu.step();
+
+    let mut errors = Vec::new();
+    let regs = cpu.registers();
+    let expected = test.expected.registers();
+    let r8 = [
+        ("A", regs.a, expected.a),
+        ("B", regs.b, expected.b),
+        ("C", regs.c, expected.c),
+        ("D", regs.d, expected.d),
+        ("E", regs.e, expected.e),
+        ("H", regs.h, expected.h),
+        ("L", regs.l, expected.l),
+    ];
+    for (name, got, expected) in r8 {
+        if got != expected {
+            errors.push(format!("{}: {:02X}, expected {:02X}", name, got, expected));
+        }
+    }
+    if regs.f != expected.f {
+        errors.push(format!(
+            "F: {}, expected {}",
+            flags(regs.f),
+            flags(expected.f)
+        ));
+    }
+    for (name, got, expected) in [("SP", regs.sp, expected.sp), ("PC", regs.pc, expected.pc)] {
+        if got != expected {
+            errors.push(format!("{}: {:04X}, expected {:04X}", name, got, expected));
+        }
+    }
+    // EI's effect is delayed by an instruction, the vectors already count it as enabled
+    if (regs.ime == Ime::Disabled) != (expected.ime == Ime::Disabled) {
+        errors.push(format!("IME: {:?}, expected {:?}", regs.ime, expected.ime));
+    }
+
+    let bus = cpu.bus();
+    for &(addr, val) in &test.expected.ram {
+        let got = bus.mem[addr as usize];
+        if got != val {
+            errors.push(format!("[{:04X}]: {:02X}, expected {:02X}", addr, got, val));
+        }
+    }
+
+    let expected: Option<Vec<Cycle>> = test.cycles.iter().map(Cycle::parse).collect();
+    match expected {
+        Some(expected) if bus.cycles != expected => {
+            errors.push(format!("cycles: {:?}, expected {:?}", bus.cycles, expected))
+        }
+        Some(_) => {}
+        None => errors.push("unrecognized cycle format".into()),
+    }
+
+    errors
+}
+
+/// `Z-H-` style flags, `-` for cleared ones.
+fn flags(f: u8) -> String {
+    "ZNHC"
+        .chars()
+        .enumerate()
+        .map(|(i, flag)| if f & (0x80 >> i) != 0x0 { flag } else { '-' })
+        .collect()
+}
+
+fn vector_files(dir: &Path, opcodes: &[String]) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .expect("failed to read vector directory")
+        .map(|entry| entry.expect("failed to read vector directory").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter(|path| {
+            let stem = path.file_stem().unwrap().to_string_lossy();
+            opcodes.is_empty() || opcodes.iter().any(|op| stem.eq_ignore_ascii_case(op))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let dir = args
+        .next()
+        .expect("usage: sm83 DIR [OPCODE...], DIR holds the SM83 JSON vectors");
+    let opcodes: Vec<String> = args.collect();
+
+    let files = vector_files(Path::new(&dir), &opcodes);
+    if files.is_empty() {
+        eprintln!("No vectors found in {}", dir);
+        std::process::exit(2);
+    }
+
+    let mut failing = Vec::new();
+    let mut total = 0;
+    let mut failed = 0;
+    for path in &files {
+        let opcode = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let json = std::fs::read(path).expect("failed to read vectors");
+        let tests: Vec<Test> = serde_json::from_slice(&json)
+            .unwrap_or_else(|err| panic!("invalid vectors in {}: {}", path.display(), err));
+
+        let mut opcode_failed = 0;
+        for test in &tests {
+            let errors = run(test);
+            if errors.is_empty() {
+                continue;
+            }
+            if opcode_failed < REPORTED_PER_OPCODE {
+                println!("FAIL {}", test.name);
+                for error in errors {
+                    println!("    {}", error);
+                }
+            }
+            opcode_failed += 1;
+        }
+
+        total += tests.len();
+        failed += opcode_failed;
+        if opcode_failed != 0 {
+            failing.push(format!("{} ({}/{})", opcode, opcode_failed, tests.len()));
+        }
+    }
+
+    println!(
+        "{} of {} tests passed, {} of {} opcodes passed",
+        total - failed,
+        total,
+        files.len() - failing.len(),
+        files.len()
+    );
+    if !failing.is_empty() {
+        println!("Failing opcodes: {}", failing.join(", "));
+        std::process::exit(1);
+    }
+}
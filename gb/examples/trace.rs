@@ -0,0 +1,69 @@
+//! Writes a Gameboy Doctor trace of a ROM to stdout.
+//!
+//! Usage: `cargo run --release -p gb --example trace ROM [INSTRUCTIONS] [--labels] > trace.log`
+//!
+//! Stops after `INSTRUCTIONS` instructions, 10 million by default, which is
+//! more than any of blargg's cpu_instrs ROMs needs. Serial output goes to stderr.
+//!
+//! With `--labels`, every instruction at a label in the symbol file next to the
+//! ROM, e.g. `game.sym` for `game.gb`, is preceded by a `label:` line. That
+//! makes the log easier to read, but it no longer diffs against Gameboy Doctor's.
+
+use gb::diagnostics::Diagnostics;
+use gb::symbols::Symbols;
+use gb::trace::{TraceLine, TraceSink};
+use gb::{Gameboy, Outcome};
+use std::io::{BufWriter, Stdout, Write};
+use std::path::Path;
+
+struct StdoutSink {
+    out: BufWriter<Stdout>,
+    symbols: Symbols,
+}
+
+impl TraceSink for StdoutSink {
+    fn trace(&mut self, line: &TraceLine) {
+        if let Some(label) = self.symbols.label(line.bank, line.regs.pc) {
+            writeln!(self.out, "{}:", label).expect("failed to write trace");
+        }
+        writeln!(self.out, "{}", line).expect("failed to write trace");
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let labels = args.iter().any(|arg| arg == "--labels");
+    args.retain(|arg| arg != "--labels");
+    let mut args = args.into_iter();
+    let path = args
+        .next()
+        .expect("usage: trace ROM [INSTRUCTIONS] [--labels], writes the trace to stdout");
+    let rom = std::fs::read(&path).expect("failed to read ROM");
+    let instructions: u64 = args
+        .next()
+        .map(|count| count.parse().expect("invalid instruction count"))
+        .unwrap_or(10_000_000);
+    let symbols = if labels {
+        let sym = Path::new(&path).with_extension("sym");
+        let text = std::fs::read_to_string(&sym).expect("failed to read symbol file");
+        Symbols::parse(&text)
+    } else {
+        Symbols::new()
+    };
+
+    let mut gameboy = Gameboy::new(rom, Diagnostics::default()).expect("invalid ROM");
+    gameboy.start_trace(StdoutSink {
+        out: BufWriter::new(std::io::stdout()),
+        symbols,
+    });
+
+    for _ in 0..instructions {
+        match gameboy.step_instruction().outcome {
+            Outcome::SerialByte(val) => eprint!("{}", val as char),
+            Outcome::LockedUp => break,
+            _ => {}
+        }
+    }
+    // Drops the sink, flushing what is left of the trace
+    gameboy.stop_trace();
+}
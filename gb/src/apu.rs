@@ -0,0 +1,251 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+/// Read-back masks for 0xFF10..=0xFF2F, unused bits always read as 1.
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // unused
+];
+
+/// Offset of NRx0 from 0xFF10 for each channel.
+const CHANNEL_BASE: [usize; 4] = [0x00, 0x05, 0x0A, 0x0F];
+const NR52: usize = 0x16;
+
+/// The APU's registers, its 512 Hz frame sequencer and the channels' length
+/// counters. No sound is generated yet.
+pub struct Apu {
+    regs: [u8; 0x20],
+    wave_ram: [u8; 0x10],
+    frame_step: u8,
+    lengths: [u16; 4],
+    enabled: [bool; 4],
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        let mut regs = [0; 0x20];
+        regs[NR52] = 0x80;
+        Self {
+            regs,
+            wave_ram: [0; 0x10],
+            frame_step: 0,
+            lengths: [0; 4],
+            enabled: [false; 4],
+        }
+    }
+
+    /// Advances the APU by one M-cycle given the divider before and after the cycle.
+    pub fn tick(&mut self, old_div: u16, new_div: u16) {
+        // The frame sequencer is clocked by falling edges of DIV bit 4
+        if !self.powered() || old_div & 0x1000 == 0 || new_div & 0x1000 != 0 {
+            return;
+        }
+
+        if self.frame_step & 0x1 == 0x0 {
+            for (ch, base) in CHANNEL_BASE.iter().enumerate() {
+                let length_enabled = self.regs[base + 4] & 0x40 == 0x40;
+                if length_enabled && self.lengths[ch] > 0 {
+                    self.lengths[ch] -= 1;
+                    if self.lengths[ch] == 0 {
+                        self.enabled[ch] = false;
+                    }
+                }
+            }
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    pub fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0xFF30..=0xFF3F => self.wave_ram[addr - 0xFF30] = val,
+            0xFF26 => {
+                if val & 0x80 == 0x0 {
+                    // Powering off clears every register
+                    self.regs = [0; 0x20];
+                    self.enabled = [false; 4];
+                } else if !self.powered() {
+                    self.frame_step = 0;
+                }
+                self.regs[NR52] = val & 0x80;
+            }
+            0xFF10..=0xFF25 if self.powered() => {
+                let reg = addr - 0xFF10;
+                self.regs[reg] = val;
+                // Each channel has five registers, NR50 and NR51 come after them
+                let ch = reg / 5;
+                if ch < 4 {
+                    self.write_channel(ch, reg % 5, val);
+                }
+            }
+            0xFF10..=0xFF2F => {}
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0xFF30..=0xFF3F => self.wave_ram[addr - 0xFF30],
+            0xFF26 => {
+                let status = self
+                    .enabled
+                    .iter()
+                    .enumerate()
+                    .fold(0, |acc, (ch, &on)| acc | ((on as u8) << ch));
+                self.regs[NR52] | READ_MASKS[NR52] | status
+            }
+            0xFF10..=0xFF2F => self.regs[addr - 0xFF10] | READ_MASKS[addr - 0xFF10],
+            _ => unreachable!(),
+        }
+    }
+
+    fn write_channel(&mut self, ch: usize, reg: usize, val: u8) {
+        match reg {
+            0x1 => self.lengths[ch] = Self::max_length(ch) - (val & Self::length_mask(ch)) as u16,
+            0x2 if ch != 2 && val & 0xF8 == 0x0 => self.enabled[ch] = false,
+            0x0 if ch == 2 && val & 0x80 == 0x0 => self.enabled[ch] = false,
+            0x4 if val & 0x80 == 0x80 => {
+                self.enabled[ch] = self.dac_enabled(ch);
+                if self.lengths[ch] == 0 {
+                    self.lengths[ch] = Self::max_length(ch);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn dac_enabled(&self, ch: usize) -> bool {
+        if ch == 2 {
+            self.regs[CHANNEL_BASE[ch]] & 0x80 == 0x80
+        } else {
+            self.regs[CHANNEL_BASE[ch] + 2] & 0xF8 != 0x0
+        }
+    }
+
+    fn max_length(ch: usize) -> u16 {
+        if ch == 2 {
+            256
+        } else {
+            64
+        }
+    }
+
+    fn length_mask(ch: usize) -> u8 {
+        if ch == 2 {
+            0xFF
+        } else {
+            0x3F
+        }
+    }
+
+    fn powered(&self) -> bool {
+        self.regs[NR52] & 0x80 == 0x80
+    }
+}
+
+impl Snapshot for Apu {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.regs);
+        w.write_bytes(&self.wave_ram);
+        w.write_u8(self.frame_step);
+        for (&length, &enabled) in self.lengths.iter().zip(self.enabled.iter()) {
+            w.write_u16(length);
+            w.write_bool(enabled);
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_bytes(&mut self.regs)?;
+        r.read_bytes(&mut self.wave_ram)?;
+        self.frame_step = r.read_u8()? % 8;
+        for ch in 0..4 {
+            self.lengths[ch] = r.read_u16()?.min(Self::max_length(ch));
+            self.enabled[ch] = r.read_bool()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A falling edge of DIV bit 4, which clocks the frame sequencer.
+    fn step(apu: &mut Apu) {
+        apu.tick(0x1000, 0x2000);
+    }
+
+    /// Channel 1 playing with its length counter at `length`.
+    fn playing(length: u8) -> Apu {
+        let mut apu = Apu::new();
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF11, 64 - length);
+        apu.write(0xFF14, 0xC0);
+        apu
+    }
+
+    #[test]
+    fn lengths_count_on_every_other_step() {
+        let mut apu = playing(2);
+        assert_eq!(apu.read(0xFF26) & 0x1, 0x1);
+        // Steps 0 and 2 clock lengths, 1 doesn't
+        step(&mut apu);
+        assert_eq!(apu.read(0xFF26) & 0x1, 0x1);
+        step(&mut apu);
+        assert_eq!(apu.read(0xFF26) & 0x1, 0x1);
+        step(&mut apu);
+        assert_eq!(apu.read(0xFF26) & 0x1, 0x0);
+    }
+
+    #[test]
+    fn only_falling_edges_step() {
+        let mut apu = playing(1);
+        apu.tick(0x0000, 0x1000);
+        apu.tick(0x1000, 0x1004);
+        apu.tick(0x0FFC, 0x0000);
+        assert_eq!(apu.read(0xFF26) & 0x1, 0x1);
+        step(&mut apu);
+        assert_eq!(apu.read(0xFF26) & 0x1, 0x0);
+    }
+
+    #[test]
+    fn triggers_need_the_dac() {
+        let mut apu = Apu::new();
+        apu.write(0xFF12, 0x00);
+        apu.write(0xFF14, 0x80);
+        assert_eq!(apu.read(0xFF26) & 0x1, 0x0);
+        // Turning the DAC off stops a playing channel
+        let mut apu = playing(10);
+        apu.write(0xFF12, 0x00);
+        assert_eq!(apu.read(0xFF26) & 0x1, 0x0);
+    }
+
+    #[test]
+    fn power_off_clears_and_locks_registers() {
+        let mut apu = playing(10);
+        apu.write(0xFF26, 0x00);
+        assert_eq!(apu.read(0xFF26), 0x70);
+        assert_eq!(apu.read(0xFF12), 0x00);
+        apu.write(0xFF12, 0xF0);
+        assert_eq!(apu.read(0xFF12), 0x00);
+        // Wave RAM stays accessible
+        apu.write(0xFF30, 0x12);
+        assert_eq!(apu.read(0xFF30), 0x12);
+
+        apu.write(0xFF26, 0x80);
+        apu.write(0xFF12, 0xF0);
+        assert_eq!(apu.read(0xFF12), 0xF0);
+    }
+
+    #[test]
+    fn unused_bits_read_as_set() {
+        let apu = Apu::new();
+        assert_eq!(apu.read(0xFF10), 0x80);
+        assert_eq!(apu.read(0xFF11), 0x3F);
+        assert_eq!(apu.read(0xFF14), 0xBF);
+        assert_eq!(apu.read(0xFF26), 0xF0);
+        assert_eq!(apu.read(0xFF27), 0xFF);
+    }
+}
@@ -0,0 +1,54 @@
+//! The interface between the CPU and the rest of the machine.
+
+/// What STOP turns into, decided by the joypad and KEY1.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// A selected button is held and an interrupt is pending, nothing happens.
+    Nop,
+    /// A selected button is held, the CPU halts instead.
+    Halt,
+    /// KEY1 was armed, the CGB switched speed and reset DIV.
+    SpeedSwitch,
+    /// The system clock stopped and DIV was reset, until a button is pressed.
+    Stopped,
+}
+
+/// The CPU's view of the address space.
+///
+/// Every access takes exactly one M-cycle, which is how the rest of the machine
+/// gets advanced in lock-step with the CPU. Implementations are owned by the
+/// `Cpu` and called statically, so accesses cost no more than a function call.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+    /// Reads without spending an M-cycle or any other side effect, for tracers and debuggers.
+    fn peek(&mut self, addr: u16) -> u8;
+    /// The ROM bank mapped at `addr`, for tracers and debuggers. `None` outside
+    /// of ROM or without any banking.
+    fn rom_bank(&self, _addr: u16) -> Option<u16> {
+        None
+    }
+    /// An M-cycle spent on internal work without touching the bus.
+    fn tick(&mut self);
+    /// An M-cycle spent while a CGB speed switch settles. The system clock is
+    /// stopped meanwhile, so DIV and everything clocked by it don't advance.
+    fn tick_stopped(&mut self);
+
+    /// Lets time pass while the CPU is halted, until an interrupt is pending or
+    /// the owner has something to report. Returns the M-cycles spent, at least one.
+    fn idle(&mut self) -> u32 {
+        self.tick();
+        1
+    }
+
+    /// Carries out STOP's side effects on the rest of the machine.
+    fn stop(&mut self) -> Stop;
+    /// Whether the system clock is stopped, the CPU then only idles.
+    fn is_stopped(&self) -> bool;
+
+    /// Interrupts that are both requested in IF and enabled in IE, bit 0
+    /// (VBlank) to 4 (Joypad). Takes no time, the CPU samples these lines continuously.
+    fn pending_interrupts(&self) -> u8;
+    /// Clears the IF bits in `mask` once the CPU has dispatched that interrupt.
+    fn acknowledge_interrupt(&mut self, mask: u8);
+}
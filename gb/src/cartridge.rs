@@ -0,0 +1,368 @@
+use crate::diagnostics::{Diagnostics, Event};
+use crate::header::CartridgeHeader;
+use crate::rom::RomSource;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter, ROM_ID_LEN};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+use num_traits::cast::FromPrimitive;
+
+/// Why a ROM can't be loaded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// The ROM ends before the end of its header.
+    Truncated { len: usize },
+    /// The cartridge type at 0x0147 isn't a known mapper.
+    UnknownMapper(u8),
+    /// The mapper is known but not emulated.
+    UnsupportedMapper(u8),
+    /// The ROM size code at 0x0148 isn't a known size.
+    UnknownRomSize(u8),
+    /// The ROM's length doesn't match the size in its header.
+    SizeMismatch { expected: usize, actual: usize },
+    /// The header checksum at 0x014D doesn't match the header, the boot ROM would lock up.
+    BadHeaderChecksum { expected: u8, actual: u8 },
+    /// The global checksum at 0x014E doesn't match the ROM, only reported when asked for.
+    BadGlobalChecksum { expected: u16, actual: u16 },
+    /// The cartridge RAM declared in the header doesn't fit into the heap.
+    OutOfMemory { ram_len: usize },
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            CartridgeError::Truncated { len } => {
+                write!(f, "ROM is {:X} bytes long, too short for a header", len)
+            }
+            CartridgeError::UnknownMapper(code) => write!(f, "Unknown cartridge type {:X}", code),
+            CartridgeError::UnsupportedMapper(code) => {
+                write!(f, "Unsupported cartridge type {:X}", code)
+            }
+            CartridgeError::UnknownRomSize(code) => write!(f, "Unknown ROM size code {:X}", code),
+            CartridgeError::SizeMismatch { expected, actual } => write!(
+                f,
+                "ROM is {:X} bytes long, header says {:X}",
+                actual, expected
+            ),
+            CartridgeError::BadHeaderChecksum { expected, actual } => write!(
+                f,
+                "Header checksum is {:X}, header sums to {:X}",
+                expected, actual
+            ),
+            CartridgeError::BadGlobalChecksum { expected, actual } => write!(
+                f,
+                "Global checksum is {:X}, ROM sums to {:X}",
+                expected, actual
+            ),
+            CartridgeError::OutOfMemory { ram_len } => {
+                write!(
+                    f,
+                    "Not enough memory for {:X} bytes of cartridge RAM",
+                    ram_len
+                )
+            }
+        }
+    }
+}
+
+/// The banking hardware of the supported cartridge types.
+#[derive(Copy, Clone, Debug)]
+enum Mapper {
+    RomOnly,
+    Mbc1,
+}
+
+pub struct Cartridge {
+    mapper: Mapper,
+    rom: Box<dyn RomSource>,
+    header: CartridgeHeader,
+    id: [u8; ROM_ID_LEN],
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// MBC1 BANK1, the lower 5 bits of the ROM bank number.
+    bank_lo: u8,
+    /// MBC1 BANK2, the RAM bank or upper 2 bits of the ROM bank number.
+    bank_hi: u8,
+    /// MBC1 banking mode, BANK2 also applies to 0x0000..=0x3FFF and RAM when set.
+    mode: bool,
+}
+
+impl Cartridge {
+    pub fn load(mut rom: Box<dyn RomSource>) -> Result<Self, CartridgeError> {
+        let header = CartridgeHeader::parse(&mut *rom)?;
+        header.verify(rom.len())?;
+
+        let code = header.cartridge_type;
+        let (mapper, has_ram) = match FromPrimitive::from_u8(code) {
+            Some(CartridgeType::RomOnly) => (Mapper::RomOnly, false),
+            Some(CartridgeType::Mbc1) => (Mapper::Mbc1, false),
+            Some(CartridgeType::Mbc1Ram | CartridgeType::Mbc1RamBattery) => (Mapper::Mbc1, true),
+            Some(_) => return Err(CartridgeError::UnsupportedMapper(code)),
+            None => return Err(CartridgeError::UnknownMapper(code)),
+        };
+
+        let mut id = [0; ROM_ID_LEN];
+        id[..16].copy_from_slice(&header.raw_title);
+        id[16] = header.header_checksum;
+        id[17..].copy_from_slice(&header.global_checksum.to_be_bytes());
+        // Games without RAM often declare some anyway, and up to 128 KiB of it
+        // doesn't fit into the firmware's heap
+        let ram_len = if has_ram {
+            header.ram_len().unwrap_or(0)
+        } else {
+            0
+        };
+        let mut ram = Vec::new();
+        ram.try_reserve_exact(ram_len)
+            .map_err(|_| CartridgeError::OutOfMemory { ram_len })?;
+        ram.resize(ram_len, 0);
+
+        Ok(Self {
+            mapper,
+            rom,
+            header,
+            id,
+            ram,
+            ram_enabled: false,
+            bank_lo: 1,
+            bank_hi: 0,
+            mode: false,
+        })
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    /// Identifies the ROM by its title and checksums.
+    pub fn id(&self) -> [u8; ROM_ID_LEN] {
+        self.id
+    }
+
+    pub fn read(&mut self, addr: usize) -> u8 {
+        match self.mapper {
+            Mapper::RomOnly => match addr {
+                0x0000..=0x7FFF => self.rom.read(addr),
+                _ => 0xFF,
+            },
+            Mapper::Mbc1 => match addr {
+                0x0000..=0x7FFF => {
+                    let bank = self.rom_bank(addr) as usize;
+                    self.rom.read(bank * 0x4000 + (addr & 0x3FFF))
+                }
+                _ => match self.ram_offset(addr) {
+                    Some(offset) => self.ram[offset],
+                    None => 0xFF,
+                },
+            },
+        }
+    }
+
+    /// The ROM bank mapped at `addr`, which must be below 0x8000.
+    pub fn rom_bank(&self, addr: usize) -> u16 {
+        let bank = match self.mapper {
+            Mapper::RomOnly => return (addr >> 14) as u16,
+            Mapper::Mbc1 if addr < 0x4000 => {
+                if self.mode {
+                    self.bank_hi << 5
+                } else {
+                    0
+                }
+            }
+            Mapper::Mbc1 => (self.bank_hi << 5) | self.bank_lo,
+        };
+        let banks = (self.rom.len() / 0x4000).max(1);
+        (bank as usize % banks) as u16
+    }
+
+    pub fn write(&mut self, addr: usize, val: u8, diag: &mut Diagnostics) {
+        match self.mapper {
+            // Nothing to write to, the value just goes nowhere
+            Mapper::RomOnly => diag.emit(Event::UnsupportedMapperWrite {
+                addr: addr as u16,
+                val,
+            }),
+            Mapper::Mbc1 => match addr {
+                0x0000..=0x1FFF => self.ram_enabled = val & 0xF == 0xA,
+                0x2000..=0x3FFF => self.bank_lo = (val & 0x1F).max(1),
+                0x4000..=0x5FFF => self.bank_hi = val & 0x3,
+                0x6000..=0x7FFF => self.mode = val & 0x1 == 0x1,
+                _ => {
+                    if let Some(offset) = self.ram_offset(addr) {
+                        self.ram[offset] = val;
+                    }
+                }
+            },
+        }
+    }
+
+    fn ram_offset(&self, addr: usize) -> Option<usize> {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return None;
+        }
+        let bank = if self.mode { self.bank_hi as usize } else { 0 };
+        Some((bank * 0x2000 + addr - 0xA000) % self.ram.len())
+    }
+}
+
+impl Snapshot for Cartridge {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_bool(self.ram_enabled);
+        w.write_u8(self.bank_lo);
+        w.write_u8(self.bank_hi);
+        w.write_bool(self.mode);
+        w.write_bytes(&self.ram);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.ram_enabled = r.read_bool()?;
+        self.bank_lo = r.read_u8()?;
+        self.bank_hi = r.read_u8()?;
+        self.mode = r.read_bool()?;
+        r.read_bytes(&mut self.ram)
+    }
+}
+
+#[derive(FromPrimitive, Debug)]
+enum CartridgeType {
+    RomOnly = 0x00,
+    Mbc1 = 0x01,
+    Mbc1Ram = 0x02,
+    Mbc1RamBattery = 0x03,
+    Mbc2 = 0x05,
+    Mbc2Battery = 0x06,
+    RomRam = 0x08,
+    RomRamBattery = 0x09,
+    Mmm01 = 0x0B,
+    Mmm01Ram = 0x0C,
+    Mmm01RamBattery = 0x0D,
+    Mbc3TimerBattery = 0x0F,
+    Mbc3TimerRamBattery = 0x10,
+    Mbc3 = 0x11,
+    Mbc3Ram = 0x12,
+    Mbc3RamBattery = 0x13,
+    Mbc5 = 0x19,
+    Mbc5Ram = 0x1A,
+    Mbc5RamBattery = 0x1B,
+    Mbc5Rumble = 0x1C,
+    MbcRumbleRam = 0x1D,
+    MbcRumbleRamBattery = 0x1E,
+    Mbc6 = 0x20,
+    Mbc7SensorRumbleRamBattery = 0x22,
+    PocketCamera = 0xFC,
+    BandaiTama5 = 0xFD,
+    HuC3 = 0xFE,
+    HuC1RamBattery = 0xFF,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use alloc::vec;
+
+    fn load(rom: Vec<u8>) -> Result<Cartridge, CartridgeError> {
+        Cartridge::load(Box::new(rom))
+    }
+
+    fn with_header(cartridge_type: u8, banks: usize, ram_size: u8) -> Vec<u8> {
+        let mut rom = testing::rom(cartridge_type, banks);
+        rom[0x0149] = ram_size;
+        testing::fix_header_checksum(&mut rom);
+        rom
+    }
+
+    #[test]
+    fn rejects_unknown_and_unsupported_mappers() {
+        assert_eq!(
+            load(testing::rom(0x04, 2)).err(),
+            Some(CartridgeError::UnknownMapper(0x04))
+        );
+        assert_eq!(
+            load(testing::rom(0x19, 2)).err(),
+            Some(CartridgeError::UnsupportedMapper(0x19))
+        );
+    }
+
+    #[test]
+    fn rejects_damaged_headers() {
+        assert_eq!(
+            load(vec![0; 0x100]).err(),
+            Some(CartridgeError::Truncated { len: 0x100 })
+        );
+
+        let mut rom = testing::rom(0x00, 2);
+        rom[0x014D] = rom[0x014D].wrapping_add(1);
+        assert!(matches!(
+            load(rom).err(),
+            Some(CartridgeError::BadHeaderChecksum { .. })
+        ));
+
+        let mut rom = testing::rom(0x01, 4);
+        rom.truncate(0x8000);
+        assert_eq!(
+            load(rom).err(),
+            Some(CartridgeError::SizeMismatch {
+                expected: 0x1_0000,
+                actual: 0x8000,
+            })
+        );
+    }
+
+    #[test]
+    fn only_allocates_ram_for_cartridges_with_ram() {
+        // Declared, but neither cartridge has any
+        assert!(load(with_header(0x00, 2, 0x04)).unwrap().ram.is_empty());
+        assert!(load(with_header(0x01, 4, 0x04)).unwrap().ram.is_empty());
+
+        assert_eq!(load(with_header(0x02, 4, 0x03)).unwrap().ram.len(), 0x8000);
+        assert_eq!(load(with_header(0x03, 4, 0x02)).unwrap().ram.len(), 0x2000);
+    }
+
+    #[test]
+    fn mbc1_ram_needs_enabling() {
+        let mut cart = load(with_header(0x03, 4, 0x02)).unwrap();
+        let mut diag = Diagnostics::default();
+        cart.write(0xA000, 0x42, &mut diag);
+        assert_eq!(cart.read(0xA000), 0xFF);
+
+        cart.write(0x0000, 0x0A, &mut diag);
+        cart.write(0xA000, 0x42, &mut diag);
+        assert_eq!(cart.read(0xA000), 0x42);
+
+        cart.write(0x0000, 0x00, &mut diag);
+        assert_eq!(cart.read(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn mbc1_rom_banks() {
+        let mut rom = testing::rom(0x01, 8);
+        for bank in 0..8 {
+            rom[bank * 0x4000 + 0x1000] = bank as u8;
+        }
+        let mut cart = load(rom).unwrap();
+        let mut diag = Diagnostics::default();
+
+        assert_eq!(cart.rom_bank(0x4000), 1);
+        cart.write(0x2000, 0x05, &mut diag);
+        assert_eq!(cart.rom_bank(0x4000), 5);
+        assert_eq!(cart.read(0x5000), 5);
+
+        // Bank 0 can't be selected for 0x4000..=0x7FFF
+        cart.write(0x2000, 0x00, &mut diag);
+        assert_eq!(cart.rom_bank(0x4000), 1);
+
+        // Bank numbers wrap around at the ROM size
+        cart.write(0x2000, 0x0B, &mut diag);
+        assert_eq!(cart.rom_bank(0x4000), 3);
+        assert_eq!(cart.read(0x5000), 3);
+        assert_eq!(cart.rom_bank(0x0000), 0);
+    }
+
+    #[test]
+    fn rom_only_banks_are_fixed() {
+        let cart = load(testing::rom(0x00, 2)).unwrap();
+        assert_eq!(cart.rom_bank(0x0000), 0);
+        assert_eq!(cart.rom_bank(0x7FFF), 1);
+    }
+}
@@ -0,0 +1,417 @@
+//! A text command interface to a [`Debugger`], for serial consoles and terminals.
+//!
+//! Commands are typed one per line, `help` lists them. The console doesn't run
+//! the machine by itself: front ends run frames while [`Console::is_running`]
+//! and pass every [`DebugEvent`] to [`Console::report`], which prints why the
+//! machine stopped. Output lines end in `\r\n` for serial terminals.
+//!
+//! With [`Symbols`] loaded, addresses can be given as labels and disassembly
+//! and backtraces show them.
+
+use crate::debugger::{parse_number, Access, DebugEvent, Debugger, ParseError, Reg, Watchpoint};
+use crate::symbols::Symbols;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+/// Longer input lines are cut off.
+const MAX_LINE: usize = 80;
+
+/// Frames shown by `bt`.
+const MAX_FRAMES: usize = 16;
+
+const HELP: &str = "\
+break SPEC     (b)  add a breakpoint, e.g. `b 01:4000 if A == 3` or `b Main`\r
+watch SPEC     (w)  add a watchpoint, e.g. `w C000-C0FF rw`\r
+delete ID      (d)  remove a breakpoint or watchpoint\r
+list           (l)  list breakpoints and watchpoints\r
+continue       (c)  resume running\r
+pause          (p)  stop running\r
+step           (s)  step into\r
+next           (n)  step over calls\r
+finish         (f)  step out of the current function\r
+regs           (r)  show registers\r
+bt                  show the return addresses on the stack\r
+set REG VAL         patch a register, e.g. `set HL C000`\r
+x ADDR [LEN]        dump LEN bytes of memory, 10 by default\r
+poke ADDR VAL...    patch memory\r
+dis [ADDR] [N]      disassemble N instructions, 8 from PC by default\r
+Numbers are hex, ids are decimal. Labels work in place of addresses.\r
+";
+
+#[derive(Default)]
+pub struct Console {
+    line: String,
+    running: bool,
+    symbols: Symbols,
+}
+
+impl Console {
+    /// A console with the machine stopped.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn set_running(&mut self, running: bool) {
+        self.running = running;
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Whether part of a line has been typed.
+    pub fn has_input(&self) -> bool {
+        !self.line.is_empty()
+    }
+
+    /// Takes one byte typed into a serial terminal, echoing it, and executes the
+    /// line once it's complete.
+    pub fn input(&mut self, byte: u8, debugger: &mut Debugger, out: &mut dyn Write) -> fmt::Result {
+        match byte {
+            b'\r' | b'\n' => {
+                if byte == b'\r' {
+                    out.write_str("\r\n")?;
+                }
+                let line = core::mem::take(&mut self.line);
+                self.execute(&line, debugger, out)
+            }
+            // Backspace and DEL
+            0x08 | 0x7F => {
+                if self.line.pop().is_some() {
+                    out.write_str("\x08 \x08")?;
+                }
+                Ok(())
+            }
+            b' '..=b'~' if self.line.len() < MAX_LINE => {
+                self.line.push(byte as char);
+                out.write_char(byte as char)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Executes a single command line.
+    pub fn execute(
+        &mut self,
+        line: &str,
+        debugger: &mut Debugger,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        let line = line.trim();
+        let (command, args) = match line.split_once(' ') {
+            Some((command, args)) => (command, args.trim()),
+            None => (line, ""),
+        };
+
+        match command {
+            "" => Ok(()),
+            "b" | "break" => match self.symbols.parse_breakpoint(args) {
+                Ok(bp) => {
+                    let id = debugger.add_breakpoint(bp);
+                    write!(out, "Breakpoint {}: {}\r\n", id, bp)
+                }
+                Err(err) => write!(out, "error: {}\r\n", err),
+            },
+            "w" | "watch" => match args.parse::<Watchpoint>() {
+                Ok(wp) => {
+                    let id = debugger.add_watchpoint(wp);
+                    write!(out, "Watchpoint {}: {}\r\n", id, wp)
+                }
+                Err(err) => write!(out, "error: {}\r\n", err),
+            },
+            "d" | "delete" => match args.parse() {
+                Ok(id) if debugger.remove_breakpoint(id) || debugger.remove_watchpoint(id) => {
+                    Ok(())
+                }
+                _ => write!(out, "error: no breakpoint or watchpoint {}\r\n", args),
+            },
+            "l" | "list" => {
+                for (id, bp) in debugger.breakpoints() {
+                    write!(out, "{:>3}  break {}", id, bp)?;
+                    if let Some(label) = self.symbols.label(bp.bank, bp.addr) {
+                        write!(out, " ({})", label)?;
+                    }
+                    out.write_str("\r\n")?;
+                }
+                for (id, wp) in debugger.watchpoints() {
+                    write!(out, "{:>3}  watch {}\r\n", id, wp)?;
+                }
+                Ok(())
+            }
+            "c" | "continue" => {
+                debugger.cancel_step();
+                self.running = true;
+                Ok(())
+            }
+            "p" | "pause" => {
+                debugger.cancel_step();
+                self.running = false;
+                self.show_location(debugger, out)
+            }
+            // Steps run like `continue` until the debugger reports them done
+            "s" | "step" => {
+                debugger.step_into();
+                self.running = true;
+                Ok(())
+            }
+            "n" | "next" => {
+                debugger.step_over();
+                self.running = true;
+                Ok(())
+            }
+            "f" | "finish" => {
+                debugger.step_out();
+                self.running = true;
+                Ok(())
+            }
+            "r" | "regs" => show_registers(debugger, out),
+            "bt" | "backtrace" => {
+                let pc = debugger.registers().pc;
+                self.show_frame(0, pc, debugger, out)?;
+                for (depth, ret) in (1..).zip(debugger.backtrace(MAX_FRAMES)) {
+                    self.show_frame(depth, ret, debugger, out)?;
+                }
+                Ok(())
+            }
+            "set" => {
+                let parsed = args.split_once(' ').and_then(|(reg, val)| {
+                    Some((reg.parse::<Reg>().ok()?, parse_number(val).ok()?))
+                });
+                match parsed {
+                    Some((reg, val)) => {
+                        debugger.set_register(reg, val);
+                        show_registers(debugger, out)
+                    }
+                    None => write!(out, "error: usage is `set REG VAL`\r\n"),
+                }
+            }
+            "x" => {
+                let mut args = args.split_whitespace();
+                let addr = args
+                    .next()
+                    .map(|addr| self.symbols.parse_addr(addr, debugger.gameboy()));
+                match (addr, args.next().map_or(Ok(0x10), parse_number)) {
+                    (Some(Ok(addr)), Ok(len)) => dump(debugger, addr, len, out),
+                    (Some(Err(err @ ParseError::UnmappedBank(_))), _) => {
+                        write!(out, "error: {}\r\n", err)
+                    }
+                    _ => write!(out, "error: usage is `x ADDR [LEN]`\r\n"),
+                }
+            }
+            "poke" => {
+                let mut args = args.split_whitespace().map(parse_number);
+                let addr = match args.next() {
+                    Some(Ok(addr)) => addr,
+                    _ => return write!(out, "error: usage is `poke ADDR VAL...`\r\n"),
+                };
+                let vals: Option<Vec<u8>> = args
+                    .map(|val| val.ok().filter(|&val| val <= 0xFF).map(|val| val as u8))
+                    .collect();
+                match vals {
+                    Some(vals) => {
+                        for (offset, &val) in vals.iter().enumerate() {
+                            debugger.poke(addr.wrapping_add(offset as u16), val);
+                        }
+                        Ok(())
+                    }
+                    None => write!(out, "error: values must be bytes\r\n"),
+                }
+            }
+            "dis" => {
+                let mut args = args.split_whitespace();
+                let addr = args.next().map_or(Ok(debugger.registers().pc), |addr| {
+                    self.symbols.parse_addr(addr, debugger.gameboy())
+                });
+                let count = args.next().map_or(Ok(8), parse_number);
+                match (addr, count) {
+                    (Err(err @ ParseError::UnmappedBank(_)), _) => {
+                        write!(out, "error: {}\r\n", err)
+                    }
+                    (Ok(mut addr), Ok(count)) => {
+                        for _ in 0..count {
+                            self.show_instruction(debugger, addr, out)?;
+                            addr = debugger.disassemble(addr).next_addr();
+                        }
+                        Ok(())
+                    }
+                    _ => write!(out, "error: usage is `dis [ADDR] [N]`\r\n"),
+                }
+            }
+            "h" | "help" => out.write_str(HELP),
+            _ => write!(out, "error: unknown command `{}`, try `help`\r\n", command),
+        }
+    }
+
+    /// Prints why the machine stopped, if it did. Serial output is printed as is.
+    pub fn report(
+        &mut self,
+        event: DebugEvent,
+        debugger: &mut Debugger,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        match event {
+            DebugEvent::Completed | DebugEvent::FrameReady => return Ok(()),
+            DebugEvent::SerialByte(val) => return out.write_char(val as char),
+            DebugEvent::Stepped => {}
+            DebugEvent::LockedUp => out.write_str("CPU locked up\r\n")?,
+            DebugEvent::SoftwareBreakpoint(_) => out.write_str("LD B, B\r\n")?,
+            DebugEvent::Breakpoint(id) => write!(out, "Breakpoint {}\r\n", id)?,
+            DebugEvent::Watchpoint { id, hit } => {
+                write!(out, "Watchpoint {}: ", id)?;
+                match hit.access {
+                    Access::Read => write!(out, "read ${:02X} from {:04X}\r\n", hit.val, hit.addr)?,
+                    Access::Write => write!(out, "wrote ${:02X} to {:04X}\r\n", hit.val, hit.addr)?,
+                    Access::Execute => write!(out, "executing {:04X}\r\n", hit.addr)?,
+                }
+            }
+        }
+        self.running = false;
+        self.show_location(debugger, out)
+    }
+
+    fn show_location(&self, debugger: &mut Debugger, out: &mut dyn Write) -> fmt::Result {
+        let pc = debugger.registers().pc;
+        self.show_instruction(debugger, pc, out)
+    }
+
+    /// Prints `[BANK:]ADDR  INSTRUCTION`, after the label at `addr` if there is one.
+    fn show_instruction(
+        &self,
+        debugger: &mut Debugger,
+        addr: u16,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        let instr = debugger.disassemble(addr);
+        let gameboy = debugger.gameboy();
+        let bank = gameboy.rom_bank(addr);
+        if let Some(label) = self.symbols.label(bank, addr) {
+            write!(out, "{}:\r\n", label)?;
+        }
+        if let Some(bank) = bank {
+            write!(out, "{:02X}:", bank)?;
+        }
+        let labels = |target| self.symbols.label(gameboy.rom_bank(target), target);
+        write!(out, "{:04X}  {}\r\n", addr, instr.labelled(&labels))
+    }
+
+    /// Prints `#DEPTH  [BANK:]ADDR  LABEL+OFFSET`.
+    fn show_frame(
+        &self,
+        depth: usize,
+        addr: u16,
+        debugger: &Debugger,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        write!(out, "#{:<3} ", depth)?;
+        let bank = debugger.gameboy().rom_bank(addr);
+        if let Some(bank) = bank {
+            write!(out, "{:02X}:", bank)?;
+        }
+        write!(out, "{:04X}", addr)?;
+        if let Some(location) = self.symbols.nearest(bank, addr) {
+            write!(out, "  {}", location)?;
+        }
+        out.write_str("\r\n")
+    }
+}
+
+fn show_registers(debugger: &Debugger, out: &mut dyn Write) -> fmt::Result {
+    let r = debugger.registers();
+    let flag = |bit: u8, name: char| if r.f & bit != 0 { name } else { '-' };
+    write!(
+        out,
+        "AF={:02X}{:02X} BC={:02X}{:02X} DE={:02X}{:02X} HL={:02X}{:02X} SP={:04X} PC={:04X} {}{}{}{} IME={:?}\r\n",
+        r.a,
+        r.f,
+        r.b,
+        r.c,
+        r.d,
+        r.e,
+        r.h,
+        r.l,
+        r.sp,
+        r.pc,
+        flag(0x80, 'Z'),
+        flag(0x40, 'N'),
+        flag(0x20, 'H'),
+        flag(0x10, 'C'),
+        r.ime
+    )
+}
+
+/// Prints `len` bytes from `addr`, 16 per line.
+fn dump(debugger: &mut Debugger, addr: u16, len: u16, out: &mut dyn Write) -> fmt::Result {
+    for line in (0..len).step_by(0x10) {
+        let start = addr.wrapping_add(line);
+        write!(out, "{:04X} ", start)?;
+        for offset in line..len.min(line.saturating_add(0x10)) {
+            write!(out, " {:02X}", debugger.peek(addr.wrapping_add(offset)))?;
+        }
+        out.write_str("\r\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, CODE};
+
+    #[test]
+    fn steps_run_until_reported() {
+        let mut debugger = Debugger::new(testing::gameboy(&[]));
+        let mut console = Console::new();
+        let mut out = String::new();
+        console.execute("s", &mut debugger, &mut out).unwrap();
+        assert!(console.is_running());
+        assert_eq!(debugger.registers().pc, 0x0100);
+
+        let event = debugger.run(u32::MAX);
+        console.report(event, &mut debugger, &mut out).unwrap();
+        assert!(!console.is_running());
+        assert_eq!(debugger.registers().pc, CODE);
+        assert!(out.ends_with("0150  NOP\r\n"), "{:?}", out);
+    }
+
+    #[test]
+    fn pause_cancels_steps() {
+        let mut debugger = Debugger::new(testing::gameboy(&[]));
+        let mut console = Console::new();
+        let mut out = String::new();
+        // Never finishes, nothing returns
+        console.execute("f", &mut debugger, &mut out).unwrap();
+        debugger.run(100);
+        console.execute("p", &mut debugger, &mut out).unwrap();
+        assert!(!console.is_running());
+
+        console.execute("s", &mut debugger, &mut out).unwrap();
+        console.execute("c", &mut debugger, &mut out).unwrap();
+        assert_eq!(debugger.run(100), DebugEvent::Completed);
+    }
+
+    #[test]
+    fn refuses_labels_in_unmapped_banks() {
+        let mut debugger = Debugger::new(testing::gameboy(&[]));
+        let mut console = Console::new();
+        console.set_symbols(Symbols::parse("01:4000 BankOne\n02:4000 BankTwo\n"));
+        let mut out = String::new();
+        console
+            .execute("x BankTwo", &mut debugger, &mut out)
+            .unwrap();
+        console
+            .execute("dis BankTwo", &mut debugger, &mut out)
+            .unwrap();
+        assert_eq!(out, "error: ROM bank 02 isn't mapped\r\n".repeat(2));
+
+        out.clear();
+        console
+            .execute("x BankOne 2", &mut debugger, &mut out)
+            .unwrap();
+        assert_eq!(out, "4000  00 00\r\n");
+    }
+}
@@ -0,0 +1,1545 @@
+//! The SM83 CPU.
+//!
+//! The CPU is generic over the [`Bus`] it runs on. The [`Gameboy`](crate::Gameboy)
+//! runs it on the whole machine, host tools can run it on anything else, e.g.
+//! a flat 64 KiB RAM to check single instructions against test vectors.
+
+use crate::bus::{Bus, Stop};
+use crate::decode::{
+    AccOp, AluOp, CbInstr, Cond, Instr, R16Mem, R16Stack, ShiftOp, CB_OPCODES, OPCODES, R16, R8,
+};
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use crate::trace::{TraceLine, TraceSink};
+use crate::Model;
+use alloc::boxed::Box;
+
+/// M-cycles the CPU is paused for while a CGB speed switch settles, with DIV frozen.
+const SPEED_SWITCH_CYCLES: u32 = 2050;
+
+pub struct Cpu<B: Bus> {
+    af: Register,
+    bc: Register,
+    de: Register,
+    hl: Register,
+    sp: u16,
+    pc: u16,
+    ime: Ime,
+    /// Waiting in HALT for an interrupt.
+    halted: bool,
+    /// The next opcode fetch doesn't increment PC.
+    halt_bug: bool,
+    /// An illegal opcode hung the CPU until power-off.
+    locked_up: bool,
+    /// M-cycles spent on the current step.
+    cycles: u32,
+    /// LD B, B is reported as a software breakpoint.
+    break_on_ld_b_b: bool,
+    /// An LD B, B executed while `break_on_ld_b_b` was set.
+    hit_ld_b_b: bool,
+    tracer: Option<Box<dyn TraceSink>>,
+    bus: B,
+}
+
+#[derive(Copy, Clone)]
+enum Flag {
+    Z = 0b1000_0000,
+    N = 0b0100_0000,
+    H = 0b0010_0000,
+    C = 0b0001_0000,
+}
+
+/// The interrupt master enable. EI only takes effect after the instruction following it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ime {
+    Disabled,
+    /// Set by EI, becomes `Enabled` once the next instruction completes.
+    Pending,
+    Enabled,
+}
+
+/// The programmer-visible CPU state.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    /// Only the upper nibble exists, the lower one always reads as 0.
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub ime: Ime,
+}
+
+enum PcMode {
+    Step(u16),
+    Jump(u16),
+    RelJump(i8),
+}
+
+impl<B: Bus> Cpu<B> {
+    /// Starts with the registers the boot ROM of `model` leaves behind.
+    pub fn new(bus: B, model: Model) -> Self {
+        let (af, bc, de, hl) = match model {
+            Model::Dmg => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            Model::Cgb => (0x1180, 0x0000, 0xFF56, 0x000D),
+        };
+        Self {
+            af: Register::new(af),
+            bc: Register::new(bc),
+            de: Register::new(de),
+            hl: Register::new(hl),
+            sp: 0xFFFE,
+            pc: 0x100,
+            ime: Ime::Disabled,
+            halted: false,
+            halt_bug: false,
+            locked_up: false,
+            cycles: 0,
+            break_on_ld_b_b: false,
+            hit_ld_b_b: false,
+            tracer: None,
+            bus,
+        }
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers {
+            a: self.af[0],
+            f: self.af[1],
+            b: self.bc[0],
+            c: self.bc[1],
+            d: self.de[0],
+            e: self.de[1],
+            h: self.hl[0],
+            l: self.hl[1],
+            sp: self.sp,
+            pc: self.pc,
+            ime: self.ime,
+        }
+    }
+
+    /// Overwrites the registers, e.g. with a test's initial state. Bits 0-3 of F are dropped.
+    pub fn set_registers(&mut self, regs: &Registers) {
+        self.af[0] = regs.a;
+        self.af[1] = regs.f & 0xF0;
+        self.bc[0] = regs.b;
+        self.bc[1] = regs.c;
+        self.de[0] = regs.d;
+        self.de[1] = regs.e;
+        self.hl[0] = regs.h;
+        self.hl[1] = regs.l;
+        self.sp = regs.sp;
+        self.pc = regs.pc;
+        self.ime = regs.ime;
+    }
+
+    pub fn is_locked_up(&self) -> bool {
+        self.locked_up
+    }
+
+    /// Whether the CPU is waiting in HALT or STOP.
+    pub fn is_idle(&self) -> bool {
+        self.halted || self.bus.is_stopped()
+    }
+
+    /// Treats LD B, B as a software breakpoint, as mooneye's test ROMs do to signal they're done.
+    pub fn set_break_on_ld_b_b(&mut self, enabled: bool) {
+        self.break_on_ld_b_b = enabled;
+    }
+
+    /// Whether an LD B, B executed since the last call while breaking on it.
+    pub fn take_ld_b_b(&mut self) -> bool {
+        core::mem::replace(&mut self.hit_ld_b_b, false)
+    }
+
+    /// Logs the state before every instruction to `tracer`, or stops logging with `None`.
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn TraceSink>>) {
+        self.tracer = tracer;
+    }
+
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// Either idles while halted, dispatches a pending interrupt or executes the
+    /// instruction at PC. Returns the M-cycles taken.
+    pub fn step(&mut self) -> u32 {
+        self.cycles = 0;
+
+        // The rest of the machine keeps running
+        if self.locked_up {
+            self.tick();
+            return self.cycles;
+        }
+
+        if self.bus.is_stopped() {
+            self.cycles += self.bus.idle();
+            return self.cycles;
+        }
+
+        if self.halted {
+            if self.bus.pending_interrupts() == 0x0 {
+                self.cycles += self.bus.idle();
+                return self.cycles;
+            }
+            // Any pending interrupt wakes the CPU, even with IME off. Waking
+            // up takes an extra M-cycle before an interrupt can be dispatched.
+            self.halted = false;
+            if self.ime == Ime::Enabled {
+                self.tick();
+            }
+        }
+
+        if self.ime == Ime::Enabled && self.bus.pending_interrupts() != 0x0 {
+            self.dispatch_interrupt();
+            return self.cycles;
+        }
+
+        if self.tracer.is_some() {
+            self.trace();
+        }
+
+        let enable_ime = self.ime == Ime::Pending;
+
+        let opcode = self.get_instr();
+        if self.halt_bug {
+            // PC wasn't incremented past the opcode, so it is read again as the next byte
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+
+        let step = match OPCODES[opcode as usize] {
+            Instr::Nop => 1,
+            Instr::LdU16pSp => self.ld_u16p_sp(),
+            Instr::Stop => self.stop(),
+            Instr::Jr => self.jr(),
+            Instr::JrCond(cond) => self.jr_cond(cond),
+            Instr::LdR16U16(r) => self.ld_r16_u16(r),
+            Instr::AddHlR16(r) => self.add_hl_r16(r),
+            Instr::LdR16pA(r) => self.ld_r16p_a(r),
+            Instr::LdAR16p(r) => self.ld_a_r16p(r),
+            Instr::IncR16(r) => self.inc_r16(r),
+            Instr::DecR16(r) => self.dec_r16(r),
+            Instr::IncR8(r) => self.inc_r8(r),
+            Instr::DecR8(r) => self.dec_r8(r),
+            Instr::LdR8U8(r) => self.ld_r8_u8(r),
+            Instr::Acc(op) => self.acc(op),
+            Instr::Halt => self.halt(),
+            Instr::LdR8R8(R8::B, R8::B) => self.ld_b_b(),
+            Instr::LdR8R8(dst, src) => self.ld_r8_r8(dst, src),
+            Instr::AluR8(op, r) => self.alu_a_r8(op, r),
+            Instr::RetCond(cond) => self.ret_cond(cond),
+            Instr::LdhU8A => self.ld_io_u8_a(),
+            Instr::AddSpI8 => self.add_sp_i8(),
+            Instr::LdhAU8 => self.ld_a_io_u8(),
+            Instr::LdHlSpI8 => self.ld_hl_sp_i8(),
+            Instr::Pop(r) => self.pop_r16(r),
+            Instr::Ret => self.ret(),
+            Instr::Reti => self.reti(),
+            Instr::JpHl => self.jp_hl(),
+            Instr::LdSpHl => self.ld_sp_hl(),
+            Instr::JpCond(cond) => self.jp_cond(cond),
+            Instr::LdhCA => self.ld_io_c_a(),
+            Instr::LdU16pA => self.ld_u16p_a(),
+            Instr::LdhAC => self.ld_a_io_c(),
+            Instr::LdAU16p => self.ld_a_u16p(),
+            Instr::JpU16 => self.jp_u16(),
+            Instr::Prefix => self.cb(),
+            Instr::Di => self.di(),
+            Instr::Ei => self.ei(),
+            Instr::CallCond(cond) => self.call_cond(cond),
+            Instr::Push(r) => self.push_r16(r),
+            Instr::CallU16 => self.call_u16(),
+            Instr::AluU8(op) => self.alu_a_u8(op),
+            Instr::Rst(dest) => self.rst(dest),
+            Instr::Illegal => self.lock_up(),
+        };
+
+        self.set_pc(PcMode::Step(step));
+
+        // Unless the instruction after EI was DI
+        if enable_ime && self.ime == Ime::Pending {
+            self.ime = Ime::Enabled;
+        }
+
+        self.cycles
+    }
+
+    /// Calls the handler of the highest priority pending interrupt, taking 5 M-cycles.
+    fn dispatch_interrupt(&mut self) {
+        self.ime = Ime::Disabled;
+        self.tick();
+        self.tick();
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_word(self.sp, (self.pc >> 8) as u8);
+
+        // The interrupt is only chosen after the high byte is pushed. If that
+        // write went to IE and disabled it, another one or none is dispatched,
+        // and with none left PC ends up at 0x0000.
+        let pending = self.bus.pending_interrupts();
+        let dest = if pending == 0x0 {
+            0x0000
+        } else {
+            // Lower bits take priority, VBlank at 0x40 to Joypad at 0x60
+            let bit = pending.trailing_zeros() as u16;
+            self.bus.acknowledge_interrupt(0x1 << bit);
+            0x40 + (bit << 3)
+        };
+
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_word(self.sp, (self.pc & 0xFF) as u8);
+
+        self.tick();
+        self.set_pc(PcMode::Jump(dest));
+    }
+
+    fn ld_u16p_sp(&mut self) -> u16 {
+        let dest = self.read_dword(self.pc.wrapping_add(1));
+        self.write_dword(dest, self.sp);
+        3
+    }
+
+    fn jr(&mut self) -> u16 {
+        let offset = self.read_word(self.pc.wrapping_add(1)) as i8;
+        self.tick();
+        self.set_pc(PcMode::RelJump(offset));
+        2
+    }
+
+    fn jr_cond(&mut self, cond: Cond) -> u16 {
+        let offset = self.read_word(self.pc.wrapping_add(1)) as i8;
+        if self.condition(cond) {
+            self.tick();
+            self.set_pc(PcMode::RelJump(offset));
+        }
+        2
+    }
+
+    fn ld_r16_u16(&mut self, r: R16) -> u16 {
+        let val = self.read_dword(self.pc.wrapping_add(1));
+        self.set_r16(r, val);
+        3
+    }
+
+    fn add_hl_r16(&mut self, r: R16) -> u16 {
+        let val = *self.hl;
+        let rhs = self.r16(r);
+        self.set_flag(Flag::N, false);
+        self.set_flag(
+            Flag::H,
+            ((val & 0x0FFF) + (rhs & 0x0FFF)) & 0x1000 == 0x1000,
+        );
+        let (res, wrap) = val.overflowing_add(rhs);
+        self.set_flag(Flag::C, wrap);
+        *self.hl = res;
+        self.tick();
+        1
+    }
+
+    fn ld_r16p_a(&mut self, r: R16Mem) -> u16 {
+        let addr = self.r16_mem(r);
+        self.write_word(addr, self.af[0]);
+        1
+    }
+
+    fn ld_a_r16p(&mut self, r: R16Mem) -> u16 {
+        let addr = self.r16_mem(r);
+        self.af[0] = self.read_word(addr);
+        1
+    }
+
+    fn inc_r16(&mut self, r: R16) -> u16 {
+        self.set_r16(r, self.r16(r).wrapping_add(1));
+        self.tick();
+        1
+    }
+
+    fn dec_r16(&mut self, r: R16) -> u16 {
+        self.set_r16(r, self.r16(r).wrapping_sub(1));
+        self.tick();
+        1
+    }
+
+    fn inc_r8(&mut self, r: R8) -> u16 {
+        let val = self.r8(r);
+        self.set_flag(Flag::Z, val == 0xFF);
+        self.set_flag(Flag::N, false);
+        self.set_flag(Flag::H, (val & 0xF) == 0xF);
+        self.set_r8(r, val.wrapping_add(1));
+        1
+    }
+
+    fn dec_r8(&mut self, r: R8) -> u16 {
+        let val = self.r8(r);
+        self.set_flag(Flag::Z, val == 0x01);
+        self.set_flag(Flag::N, true);
+        self.set_flag(Flag::H, (val & 0xF) == 0x0);
+        self.set_r8(r, val.wrapping_sub(1));
+        1
+    }
+
+    fn ld_r8_u8(&mut self, r: R8) -> u16 {
+        let val = self.read_word(self.pc.wrapping_add(1));
+        self.set_r8(r, val);
+        2
+    }
+
+    fn acc(&mut self, op: AccOp) -> u16 {
+        let a = self.af[0];
+        match op {
+            AccOp::Rlca | AccOp::Rrca | AccOp::Rla | AccOp::Rra => {
+                self.set_flag(Flag::Z, false);
+                self.set_flag(Flag::N, false);
+                self.set_flag(Flag::H, false);
+
+                self.af[0] = match op {
+                    AccOp::Rlca => a.rotate_left(1),
+                    AccOp::Rrca => a.rotate_right(1),
+                    AccOp::Rla => (a << 1) | self.get_flag(Flag::C) as u8,
+                    _ => (a >> 1) | ((self.get_flag(Flag::C) as u8) << 7),
+                };
+
+                if op == AccOp::Rlca || op == AccOp::Rla {
+                    self.set_flag(Flag::C, a & 0x80 == 0x80);
+                } else {
+                    self.set_flag(Flag::C, a & 0x1 == 0x1);
+                }
+            }
+            AccOp::Daa => {
+                let mut u = 0;
+                if self.get_flag(Flag::H) || (!self.get_flag(Flag::N) && (a & 0xF) > 9) {
+                    u = 6;
+                }
+                if self.get_flag(Flag::C) || (!self.get_flag(Flag::N) && a > 0x99) {
+                    u |= 0x60;
+                    self.set_flag(Flag::C, true);
+                }
+
+                let res = if self.get_flag(Flag::N) {
+                    a.wrapping_sub(u)
+                } else {
+                    a.wrapping_add(u)
+                };
+
+                self.set_flag(Flag::Z, res == 0);
+                self.set_flag(Flag::H, false);
+                self.af[0] = res;
+            }
+            AccOp::Cpl => {
+                self.set_flag(Flag::N, true);
+                self.set_flag(Flag::H, true);
+                self.af[0] = !a
+            }
+            AccOp::Scf => {
+                self.set_flag(Flag::N, false);
+                self.set_flag(Flag::H, false);
+                self.set_flag(Flag::C, true);
+            }
+            AccOp::Ccf => {
+                self.set_flag(Flag::N, false);
+                self.set_flag(Flag::H, false);
+                let c = self.get_flag(Flag::C);
+                self.set_flag(Flag::C, !c);
+            }
+        }
+        1
+    }
+
+    /// STOP is followed by a byte it skips, unless an interrupt is pending or
+    /// it turns into a NOP. A speed switch with an interrupt pending glitches
+    /// the CPU on hardware, here it just behaves like without one.
+    fn stop(&mut self) -> u16 {
+        let len = if self.bus.pending_interrupts() == 0x0 {
+            2
+        } else {
+            1
+        };
+        match self.bus.stop() {
+            Stop::Nop => return 1,
+            Stop::Halt => self.halted = true,
+            Stop::SpeedSwitch => {
+                for _ in 0..SPEED_SWITCH_CYCLES {
+                    self.cycles += 1;
+                    self.bus.tick_stopped();
+                }
+            }
+            Stop::Stopped => {}
+        }
+        len
+    }
+
+    /// Illegal opcodes hang the CPU, PC stays on the opcode and interrupts are ignored.
+    fn lock_up(&mut self) -> u16 {
+        self.locked_up = true;
+        0
+    }
+
+    fn halt(&mut self) -> u16 {
+        if self.ime == Ime::Enabled || self.bus.pending_interrupts() == 0x0 {
+            self.halted = true;
+            return 1;
+        }
+
+        // DMG bug: with an interrupt pending but IME off, HALT doesn't halt
+        // and PC isn't incremented past it
+        if self.ime == Ime::Pending {
+            // After EI the interrupt is dispatched right away, so HALT is what it returns to
+            0
+        } else {
+            // Otherwise the following byte is read twice
+            self.halt_bug = true;
+            1
+        }
+    }
+
+    fn ld_r8_r8(&mut self, dst: R8, src: R8) -> u16 {
+        let val = self.r8(src);
+        self.set_r8(dst, val);
+        1
+    }
+
+    fn ld_b_b(&mut self) -> u16 {
+        self.hit_ld_b_b = self.break_on_ld_b_b;
+        1
+    }
+
+    fn alu_a_r8(&mut self, op: AluOp, r: R8) -> u16 {
+        let rhs = self.r8(r);
+        self.af[0] = self.alu(op, rhs);
+        1
+    }
+
+    fn ret_cond(&mut self, cond: Cond) -> u16 {
+        // The condition is checked in an extra M-cycle
+        self.tick();
+        if self.condition(cond) {
+            let dest = self.pop();
+            self.tick();
+            self.set_pc(PcMode::Jump(dest));
+            0
+        } else {
+            1
+        }
+    }
+
+    fn ld_io_u8_a(&mut self) -> u16 {
+        let offset = self.read_word(self.pc.wrapping_add(1)) as u16;
+        self.write_word(0xFF00 + offset, self.af[0]);
+        2
+    }
+
+    fn add_sp_i8(&mut self) -> u16 {
+        let val = self.read_word(self.pc.wrapping_add(1)) as i8 as u16;
+        let res = self.sp.wrapping_add(val);
+        self.set_flag(Flag::Z, false);
+        self.set_flag(Flag::N, false);
+        self.set_flag(Flag::H, (self.sp & 0xF) + (val & 0xF) > 0xF);
+        self.set_flag(Flag::C, (self.sp & 0xFF) + (val & 0xFF) > 0xFF);
+
+        // The 16-bit addition goes through the 8-bit ALU, a byte at a time
+        self.tick();
+        self.tick();
+        self.sp = res;
+        2
+    }
+
+    fn ld_a_io_u8(&mut self) -> u16 {
+        let offset = self.read_word(self.pc.wrapping_add(1)) as u16;
+        self.af[0] = self.read_word(0xFF00 + offset);
+        2
+    }
+
+    fn ld_hl_sp_i8(&mut self) -> u16 {
+        let val = self.read_word(self.pc.wrapping_add(1)) as i8 as u16;
+        let res = self.sp.wrapping_add(val);
+        self.set_flag(Flag::Z, false);
+        self.set_flag(Flag::N, false);
+        self.set_flag(Flag::H, (self.sp & 0xF) + (val & 0xF) > 0xF);
+        self.set_flag(Flag::C, (self.sp & 0xFF) + (val & 0xFF) > 0xFF);
+
+        self.tick();
+        *self.hl = res;
+        2
+    }
+
+    fn ld_sp_hl(&mut self) -> u16 {
+        self.tick();
+        self.sp = *self.hl;
+        1
+    }
+
+    fn jp_hl(&mut self) -> u16 {
+        let dest = *self.hl;
+        self.set_pc(PcMode::Jump(dest));
+        0
+    }
+
+    fn pop_r16(&mut self, r: R16Stack) -> u16 {
+        let val = self.pop();
+        match r {
+            R16Stack::Bc => *self.bc = val,
+            R16Stack::De => *self.de = val,
+            R16Stack::Hl => *self.hl = val,
+            // Edge case - POP AF does not write lower 4 bits of F
+            R16Stack::Af => *self.af = val & 0xFFF0,
+        }
+        1
+    }
+
+    fn ret(&mut self) -> u16 {
+        let dest = self.pop();
+        self.tick();
+        self.set_pc(PcMode::Jump(dest));
+        0
+    }
+
+    /// Unlike EI, enables interrupts right away.
+    fn reti(&mut self) -> u16 {
+        self.ime = Ime::Enabled;
+        let dest = self.pop();
+        self.tick();
+        self.set_pc(PcMode::Jump(dest));
+        0
+    }
+
+    fn jp_cond(&mut self, cond: Cond) -> u16 {
+        let dest = self.read_dword(self.pc.wrapping_add(1));
+        if self.condition(cond) {
+            self.tick();
+            self.set_pc(PcMode::Jump(dest));
+            0
+        } else {
+            3
+        }
+    }
+
+    fn ld_io_c_a(&mut self) -> u16 {
+        let offset = self.bc[1] as u16;
+        self.write_word(0xFF00 + offset, self.af[0]);
+        1
+    }
+
+    fn ld_u16p_a(&mut self) -> u16 {
+        let dest = self.read_dword(self.pc.wrapping_add(1));
+        self.write_word(dest, self.af[0]);
+        3
+    }
+
+    fn ld_a_io_c(&mut self) -> u16 {
+        let offset = self.bc[1] as u16;
+        self.af[0] = self.read_word(0xFF00 + offset);
+        1
+    }
+
+    fn ld_a_u16p(&mut self) -> u16 {
+        let src = self.read_dword(self.pc.wrapping_add(1));
+        self.af[0] = self.read_word(src);
+        3
+    }
+
+    fn jp_u16(&mut self) -> u16 {
+        let dest = self.read_dword(self.pc.wrapping_add(1));
+        self.tick();
+        self.set_pc(PcMode::Jump(dest));
+        0
+    }
+
+    fn cb(&mut self) -> u16 {
+        self.set_pc(PcMode::Step(1));
+        let opcode = self.get_instr();
+
+        match CB_OPCODES[opcode as usize] {
+            CbInstr::Shift(op, r) => {
+                let val = self.r8(r);
+                let res = match op {
+                    ShiftOp::Rlc => val.rotate_left(1),
+                    ShiftOp::Rrc => val.rotate_right(1),
+                    ShiftOp::Rl => (val << 1) | self.get_flag(Flag::C) as u8,
+                    ShiftOp::Rr => (val >> 1) | ((self.get_flag(Flag::C) as u8) << 7),
+                    ShiftOp::Sla => val << 1,
+                    ShiftOp::Sra => (val >> 1) | (val & 0x80),
+                    ShiftOp::Swap => val.rotate_right(4),
+                    ShiftOp::Srl => val >> 1,
+                };
+
+                match op {
+                    ShiftOp::Swap => self.set_flag(Flag::C, false),
+                    ShiftOp::Rlc | ShiftOp::Rl | ShiftOp::Sla => {
+                        self.set_flag(Flag::C, val & 0x80 == 0x80)
+                    }
+                    _ => self.set_flag(Flag::C, val & 0x1 == 0x1),
+                }
+                self.set_flag(Flag::Z, res == 0);
+                self.set_flag(Flag::N, false);
+                self.set_flag(Flag::H, false);
+
+                self.set_r8(r, res);
+            }
+            CbInstr::Bit(bit, r) => {
+                let val = self.r8(r);
+                self.set_flag(Flag::Z, (val & (0x1 << bit)) == 0x0);
+                self.set_flag(Flag::N, false);
+                self.set_flag(Flag::H, true);
+            }
+            CbInstr::Res(bit, r) => {
+                let val = self.r8(r);
+                self.set_r8(r, val & !(0x1 << bit));
+            }
+            CbInstr::Set(bit, r) => {
+                let val = self.r8(r);
+                self.set_r8(r, val | (0x1 << bit));
+            }
+        }
+        1
+    }
+
+    fn di(&mut self) -> u16 {
+        self.ime = Ime::Disabled;
+        1
+    }
+
+    fn ei(&mut self) -> u16 {
+        if self.ime == Ime::Disabled {
+            self.ime = Ime::Pending;
+        }
+        1
+    }
+
+    fn call_cond(&mut self, cond: Cond) -> u16 {
+        let dest = self.read_dword(self.pc.wrapping_add(1));
+        if self.condition(cond) {
+            self.tick();
+            self.push(self.pc.wrapping_add(3));
+            self.set_pc(PcMode::Jump(dest));
+            0
+        } else {
+            3
+        }
+    }
+
+    fn push_r16(&mut self, r: R16Stack) -> u16 {
+        self.tick();
+        let val = match r {
+            R16Stack::Bc => *self.bc,
+            R16Stack::De => *self.de,
+            R16Stack::Hl => *self.hl,
+            R16Stack::Af => *self.af,
+        };
+        self.push(val);
+        1
+    }
+
+    fn call_u16(&mut self) -> u16 {
+        let dest = self.read_dword(self.pc.wrapping_add(1));
+        self.tick();
+        self.push(self.pc.wrapping_add(3));
+        self.set_pc(PcMode::Jump(dest));
+        0
+    }
+
+    fn alu_a_u8(&mut self, op: AluOp) -> u16 {
+        let rhs = self.read_word(self.pc.wrapping_add(1));
+        self.af[0] = self.alu(op, rhs);
+        2
+    }
+
+    fn rst(&mut self, dest: u8) -> u16 {
+        self.tick();
+        self.push(self.pc.wrapping_add(1));
+        self.set_pc(PcMode::Jump(dest as u16));
+        0
+    }
+
+    fn alu(&mut self, op: AluOp, rhs: u8) -> u8 {
+        let a = self.af[0];
+        match op {
+            AluOp::Add => {
+                let (res, wrap) = a.overflowing_add(rhs);
+                self.set_flag(Flag::Z, res == 0);
+                self.set_flag(Flag::N, false);
+                self.set_flag(Flag::H, (a & 0xF) + (rhs & 0xF) > 0xF);
+                self.set_flag(Flag::C, wrap);
+                res
+            }
+            AluOp::Adc => {
+                let c = self.get_flag(Flag::C) as u8;
+                let (res, wrap_0) = a.overflowing_add(rhs);
+                let (res, wrap_1) = res.overflowing_add(c);
+                self.set_flag(Flag::Z, res == 0);
+                self.set_flag(Flag::N, false);
+                self.set_flag(Flag::H, (a & 0xF) + (rhs & 0xF) + c > 0xF);
+                self.set_flag(Flag::C, wrap_0 | wrap_1);
+                res
+            }
+            AluOp::Sub => {
+                let (res, wrap) = a.overflowing_sub(rhs);
+                self.set_flag(Flag::Z, res == 0);
+                self.set_flag(Flag::N, true);
+                self.set_flag(Flag::H, (a & 0xF) < (rhs & 0xF));
+                self.set_flag(Flag::C, wrap);
+                res
+            }
+            AluOp::Sbc => {
+                let c = self.get_flag(Flag::C) as u8;
+                let (res, wrap_0) = a.overflowing_sub(rhs);
+                let (res, wrap_1) = res.overflowing_sub(c);
+                self.set_flag(Flag::Z, res == 0);
+                self.set_flag(Flag::N, true);
+                self.set_flag(Flag::H, (a & 0xF) < (rhs & 0xF) + c);
+                self.set_flag(Flag::C, wrap_0 | wrap_1);
+                res
+            }
+            AluOp::And => {
+                let res = a & rhs;
+                self.set_flag(Flag::Z, res == 0);
+                self.set_flag(Flag::N, false);
+                self.set_flag(Flag::H, true);
+                self.set_flag(Flag::C, false);
+                res
+            }
+            AluOp::Xor => {
+                let res = a ^ rhs;
+                self.set_flag(Flag::Z, res == 0);
+                self.set_flag(Flag::N, false);
+                self.set_flag(Flag::H, false);
+                self.set_flag(Flag::C, false);
+                res
+            }
+            AluOp::Or => {
+                let res = a | rhs;
+                self.set_flag(Flag::Z, res == 0);
+                self.set_flag(Flag::N, false);
+                self.set_flag(Flag::H, false);
+                self.set_flag(Flag::C, false);
+                res
+            }
+            AluOp::Cp => {
+                let (res, wrap) = a.overflowing_sub(rhs);
+                self.set_flag(Flag::Z, res == 0);
+                self.set_flag(Flag::N, true);
+                self.set_flag(Flag::H, (a & 0xF) < (rhs & 0xF));
+                self.set_flag(Flag::C, wrap);
+                a
+            }
+        }
+    }
+
+    fn r8(&mut self, r: R8) -> u8 {
+        match r {
+            R8::B => self.bc[0],
+            R8::C => self.bc[1],
+            R8::D => self.de[0],
+            R8::E => self.de[1],
+            R8::H => self.hl[0],
+            R8::L => self.hl[1],
+            R8::HlInd => self.read_word(*self.hl),
+            R8::A => self.af[0],
+        }
+    }
+
+    fn set_r8(&mut self, r: R8, val: u8) {
+        match r {
+            R8::B => self.bc[0] = val,
+            R8::C => self.bc[1] = val,
+            R8::D => self.de[0] = val,
+            R8::E => self.de[1] = val,
+            R8::H => self.hl[0] = val,
+            R8::L => self.hl[1] = val,
+            R8::HlInd => self.write_word(*self.hl, val),
+            R8::A => self.af[0] = val,
+        }
+    }
+
+    fn r16(&self, r: R16) -> u16 {
+        match r {
+            R16::Bc => *self.bc,
+            R16::De => *self.de,
+            R16::Hl => *self.hl,
+            R16::Sp => self.sp,
+        }
+    }
+
+    fn set_r16(&mut self, r: R16, val: u16) {
+        match r {
+            R16::Bc => *self.bc = val,
+            R16::De => *self.de = val,
+            R16::Hl => *self.hl = val,
+            R16::Sp => self.sp = val,
+        }
+    }
+
+    /// The address `r` points to, stepping HL for `HlInc` and `HlDec`.
+    fn r16_mem(&mut self, r: R16Mem) -> u16 {
+        match r {
+            R16Mem::Bc => *self.bc,
+            R16Mem::De => *self.de,
+            R16Mem::HlInc => {
+                let old = *self.hl;
+                *self.hl = old.wrapping_add(1);
+                old
+            }
+            R16Mem::HlDec => {
+                let old = *self.hl;
+                *self.hl = old.wrapping_sub(1);
+                old
+            }
+        }
+    }
+
+    fn condition(&mut self, cond: Cond) -> bool {
+        match cond {
+            Cond::Nz => !self.get_flag(Flag::Z),
+            Cond::Z => self.get_flag(Flag::Z),
+            Cond::Nc => !self.get_flag(Flag::C),
+            Cond::C => self.get_flag(Flag::C),
+        }
+    }
+
+    fn push(&mut self, val: u16) {
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_word(self.sp, ((val & 0xFF00) >> 8) as u8);
+        self.sp = self.sp.wrapping_sub(1);
+        self.write_word(self.sp, (val & 0xFF) as u8);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let low = self.read_word(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+        let high = self.read_word(self.sp) as u16;
+        self.sp = self.sp.wrapping_add(1);
+
+        (high << 8) + low
+    }
+
+    fn set_pc(&mut self, mode: PcMode) {
+        match mode {
+            PcMode::Step(e) => self.pc = self.pc.wrapping_add(e),
+            PcMode::Jump(dest) => self.pc = dest,
+            PcMode::RelJump(dest) => self.pc = self.pc.wrapping_add(dest as u16),
+        }
+    }
+
+    fn get_instr(&mut self) -> u8 {
+        self.read_word(self.pc)
+    }
+
+    fn trace(&mut self) {
+        let mut pcmem = [0; 4];
+        for (offset, byte) in (0..).zip(pcmem.iter_mut()) {
+            *byte = self.bus.peek(self.pc.wrapping_add(offset));
+        }
+        let line = TraceLine {
+            regs: self.registers(),
+            pcmem,
+            bank: self.bus.rom_bank(self.pc),
+        };
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(&line);
+        }
+    }
+
+    fn set_flag(&mut self, flag: Flag, val: bool) {
+        let z = &mut self.af[1];
+        if val {
+            *z |= flag as u8;
+        } else {
+            *z &= !(flag as u8);
+        }
+    }
+
+    fn get_flag(&mut self, flag: Flag) -> bool {
+        let mut z = self.af[1];
+        z &= flag as u8;
+        z == flag as u8
+    }
+
+    // Every bus access takes one M-cycle
+    fn read_word(&mut self, addr: u16) -> u8 {
+        self.cycles += 1;
+        self.bus.read(addr)
+    }
+
+    fn write_word(&mut self, addr: u16, val: u8) {
+        self.cycles += 1;
+        self.bus.write(addr, val);
+    }
+
+    /// An M-cycle of internal work.
+    fn tick(&mut self) {
+        self.cycles += 1;
+        self.bus.tick();
+    }
+
+    /// Little-endian, the low byte is read first.
+    fn read_dword(&mut self, addr: u16) -> u16 {
+        let low = self.read_word(addr) as u16;
+        let high = self.read_word(addr.wrapping_add(1)) as u16;
+        (high << 8) + low
+    }
+
+    fn write_dword(&mut self, addr: u16, val: u16) {
+        self.write_word(addr, (val & 0x00FF) as u8);
+        self.write_word(addr.wrapping_add(1), ((val & 0xFF00) >> 8) as u8);
+    }
+}
+
+/// Only covers the registers, the bus is saved by its owner.
+impl<B: Bus> Snapshot for Cpu<B> {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_u16(*self.af);
+        w.write_u16(*self.bc);
+        w.write_u16(*self.de);
+        w.write_u16(*self.hl);
+        w.write_u16(self.sp);
+        w.write_u16(self.pc);
+        w.write_u8(self.ime as u8);
+        w.write_bool(self.halted);
+        w.write_bool(self.halt_bug);
+        w.write_bool(self.locked_up);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        *self.af = r.read_u16()? & 0xFFF0;
+        *self.bc = r.read_u16()?;
+        *self.de = r.read_u16()?;
+        *self.hl = r.read_u16()?;
+        self.sp = r.read_u16()?;
+        self.pc = r.read_u16()?;
+        self.ime = match r.read_u8()? {
+            0 => Ime::Disabled,
+            1 => Ime::Pending,
+            2 => Ime::Enabled,
+            _ => return Err(StateError::Invalid),
+        };
+        self.halted = r.read_bool()?;
+        self.halt_bug = r.read_bool()?;
+        self.locked_up = r.read_bool()?;
+        Ok(())
+    }
+}
+
+// no rust project is complete without some unsafe!.
+// indexing returns registers in big-endian order, assuming a little endian host. this means af[0] returns a.
+#[derive(Copy, Clone)]
+#[repr(C)]
+union Register {
+    a: [u8; 2],
+    b: u16,
+}
+
+impl core::ops::Deref for Register {
+    type Target = u16;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &self.b }
+    }
+}
+
+impl core::ops::DerefMut for Register {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut self.b }
+    }
+}
+
+impl core::ops::Index<u8> for Register {
+    type Output = u8;
+
+    fn index(&self, index: u8) -> &Self::Output {
+        unsafe {
+            match index {
+                0 => &self.a[1],
+                1 => &self.a[0],
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+impl core::ops::IndexMut<u8> for Register {
+    fn index_mut(&mut self, index: u8) -> &mut Self::Output {
+        unsafe {
+            match index {
+                0 => &mut self.a[1],
+                1 => &mut self.a[0],
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+impl Register {
+    pub fn new(b: u16) -> Self {
+        Self { b }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    const IF: u16 = 0xFF0F;
+    const IE: u16 = 0xFFFF;
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    enum Cycle {
+        Read(u16),
+        Write(u16, u8),
+        Internal,
+        Stopped,
+    }
+
+    /// A flat 64 KiB RAM with IF at 0xFF0F and IE at 0xFFFF, logging every M-cycle.
+    struct TestBus {
+        mem: Vec<u8>,
+        cycles: Vec<Cycle>,
+        /// What STOP turns into.
+        stop: Stop,
+        stopped: bool,
+    }
+
+    impl Bus for TestBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.cycles.push(Cycle::Read(addr));
+            self.mem[addr as usize]
+        }
+
+        fn write(&mut self, addr: u16, val: u8) {
+            self.cycles.push(Cycle::Write(addr, val));
+            self.mem[addr as usize] = val;
+        }
+
+        fn peek(&mut self, addr: u16) -> u8 {
+            self.mem[addr as usize]
+        }
+
+        fn tick(&mut self) {
+            self.cycles.push(Cycle::Internal);
+        }
+
+        fn tick_stopped(&mut self) {
+            self.cycles.push(Cycle::Stopped);
+        }
+
+        fn stop(&mut self) -> Stop {
+            self.stopped = self.stop == Stop::Stopped;
+            self.stop
+        }
+
+        fn is_stopped(&self) -> bool {
+            self.stopped
+        }
+
+        fn pending_interrupts(&self) -> u8 {
+            self.mem[IE as usize] & self.mem[IF as usize] & 0x1F
+        }
+
+        fn acknowledge_interrupt(&mut self, mask: u8) {
+            self.mem[IF as usize] &= !mask;
+        }
+    }
+
+    /// A DMG CPU about to run `code` at 0x0100.
+    fn cpu(code: &[u8]) -> Cpu<TestBus> {
+        let mut mem = vec![0; 0x10000];
+        mem[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        let bus = TestBus {
+            mem,
+            cycles: Vec::new(),
+            stop: Stop::Stopped,
+            stopped: false,
+        };
+        Cpu::new(bus, Model::Dmg)
+    }
+
+    /// Sets IE and IF and clears the cycle log.
+    fn request(cpu: &mut Cpu<TestBus>, ie: u8, int_f: u8) {
+        let bus = cpu.bus_mut();
+        bus.mem[IE as usize] = ie;
+        bus.mem[IF as usize] = int_f;
+        bus.cycles.clear();
+    }
+
+    fn mem(cpu: &Cpu<TestBus>, addr: u16) -> u8 {
+        cpu.bus().mem[addr as usize]
+    }
+
+    #[test]
+    fn dispatch_pushes_pc_and_jumps_to_the_vector() {
+        let mut cpu = cpu(&[0x00]);
+        cpu.ime = Ime::Enabled;
+        request(&mut cpu, 0x1F, 0x14);
+
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.sp, 0xFFFC);
+        assert_eq!((mem(&cpu, 0xFFFD), mem(&cpu, 0xFFFC)), (0x01, 0x00));
+        // Only the dispatched interrupt is acknowledged
+        assert_eq!(mem(&cpu, IF), 0x10);
+        assert_eq!(cpu.ime, Ime::Disabled);
+        assert_eq!(
+            cpu.bus().cycles,
+            [
+                Cycle::Internal,
+                Cycle::Internal,
+                Cycle::Write(0xFFFD, 0x01),
+                Cycle::Write(0xFFFC, 0x00),
+                Cycle::Internal,
+            ]
+        );
+    }
+
+    #[test]
+    fn lower_bits_take_priority() {
+        for bit in 0..5 {
+            let mut cpu = cpu(&[0x00]);
+            cpu.ime = Ime::Enabled;
+            let int_f = (0x1F << bit) & 0x1F;
+            request(&mut cpu, 0x1F, int_f);
+
+            assert_eq!(cpu.step(), 5);
+            assert_eq!(cpu.pc, 0x40 + 8 * bit as u16);
+            assert_eq!(mem(&cpu, IF), int_f & !(0x1 << bit));
+        }
+    }
+
+    #[test]
+    fn dispatch_needs_ime_and_ie() {
+        // IME off
+        let mut cpu = cpu(&[0x00]);
+        request(&mut cpu, 0x1F, 0x1F);
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.pc, 0x0101);
+        assert_eq!(mem(&cpu, IF), 0x1F);
+
+        // Nothing enabled in IE
+        let mut cpu = self::cpu(&[0x00]);
+        cpu.ime = Ime::Enabled;
+        request(&mut cpu, 0x00, 0x1F);
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.pc, 0x0101);
+        assert_eq!(cpu.ime, Ime::Enabled);
+    }
+
+    #[test]
+    fn pushing_into_ie_cancels_the_dispatch() {
+        // With SP at 0x0000 the high byte of PC, 0x01, is pushed into IE. It
+        // disables the timer interrupt being dispatched, so there's none left.
+        let mut cpu = cpu(&[0x00]);
+        cpu.ime = Ime::Enabled;
+        cpu.sp = 0x0000;
+        request(&mut cpu, 0x04, 0x04);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(cpu.sp, 0xFFFE);
+        assert_eq!(mem(&cpu, IE), 0x01);
+        assert_eq!(mem(&cpu, IF), 0x04);
+        assert_eq!(cpu.ime, Ime::Disabled);
+
+        // Now it enables VBlank, which is requested too and dispatched instead
+        let mut cpu = self::cpu(&[0x00]);
+        cpu.ime = Ime::Enabled;
+        cpu.sp = 0x0000;
+        request(&mut cpu, 0x04, 0x05);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.pc, 0x0040);
+        assert_eq!(mem(&cpu, IF), 0x04);
+    }
+
+    #[test]
+    fn halt_wakes_without_ime() {
+        let mut cpu = cpu(&[0x76, 0x3C]);
+        assert_eq!(cpu.step(), 1);
+        assert!(cpu.halted);
+        assert_eq!(cpu.pc, 0x0101);
+
+        // Idles while nothing is pending
+        request(&mut cpu, 0x04, 0x00);
+        assert_eq!(cpu.step(), 1);
+        assert!(cpu.halted);
+        assert_eq!(cpu.bus().cycles, [Cycle::Internal]);
+
+        // Wakes and carries on without dispatching or acknowledging anything
+        request(&mut cpu, 0x04, 0x04);
+        assert_eq!(cpu.step(), 1);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0x0102);
+        assert_eq!(cpu.af[0], 0x02);
+        assert_eq!(mem(&cpu, IF), 0x04);
+    }
+
+    #[test]
+    fn halt_wakes_into_the_handler_with_ime() {
+        let mut cpu = cpu(&[0x76, 0x3C]);
+        cpu.ime = Ime::Enabled;
+        cpu.step();
+        assert!(cpu.halted);
+
+        // An extra M-cycle to wake up, then the dispatch returns after HALT
+        request(&mut cpu, 0x04, 0x04);
+        assert_eq!(cpu.step(), 6);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!((mem(&cpu, 0xFFFD), mem(&cpu, 0xFFFC)), (0x01, 0x01));
+        assert_eq!(mem(&cpu, IF), 0x00);
+        assert_eq!(cpu.af[0], 0x01);
+    }
+
+    #[test]
+    fn halt_bug_reads_the_next_byte_twice() {
+        let mut cpu = cpu(&[0x76, 0x3C, 0x00]);
+        request(&mut cpu, 0x04, 0x04);
+        assert_eq!(cpu.step(), 1);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0x0101);
+
+        // INC A is executed, but PC stays on it
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.pc, 0x0101);
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.pc, 0x0102);
+        assert_eq!(cpu.af[0], 0x03);
+        assert_eq!(mem(&cpu, IF), 0x04);
+    }
+
+    /// Runs STOP turning into `stop`, with or without an interrupt pending.
+    fn stop(stop: Stop, pending: bool) -> Cpu<TestBus> {
+        let mut cpu = cpu(&[0x10, 0x00, 0x3C]);
+        cpu.bus_mut().stop = stop;
+        request(&mut cpu, 0x04, if pending { 0x04 } else { 0x00 });
+        cpu.step();
+        cpu
+    }
+
+    #[test]
+    fn stop_skips_a_byte_unless_an_interrupt_is_pending() {
+        let mut cpu = stop(Stop::Stopped, false);
+        assert_eq!(cpu.pc, 0x0102);
+        assert_eq!(cpu.bus().cycles, [Cycle::Read(0x0100)]);
+        // Idles until the clock runs again
+        cpu.bus_mut().cycles.clear();
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.pc, 0x0102);
+        assert_eq!(cpu.bus().cycles, [Cycle::Internal]);
+
+        let cpu = stop(Stop::Stopped, true);
+        assert_eq!(cpu.pc, 0x0101);
+        assert!(cpu.bus().stopped);
+    }
+
+    #[test]
+    fn stop_with_a_button_held() {
+        // Halts, skipping the byte after STOP as nothing is pending
+        let cpu = stop(Stop::Halt, false);
+        assert!(cpu.halted);
+        assert_eq!(cpu.pc, 0x0102);
+
+        // Does nothing at all
+        let cpu = stop(Stop::Nop, true);
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0x0101);
+        assert_eq!(cpu.bus().cycles, [Cycle::Read(0x0100)]);
+    }
+
+    #[test]
+    fn speed_switch_pauses_the_cpu() {
+        let mut cpu = cpu(&[0x10, 0x00, 0x3C]);
+        cpu.bus_mut().stop = Stop::SpeedSwitch;
+        assert_eq!(cpu.step(), 1 + SPEED_SWITCH_CYCLES);
+        assert_eq!(cpu.pc, 0x0102);
+        let cycles = &cpu.bus().cycles;
+        assert_eq!(cycles[0], Cycle::Read(0x0100));
+        assert!(cycles[1..].iter().all(|&cycle| cycle == Cycle::Stopped));
+
+        // Carries on right after
+        cpu.step();
+        assert_eq!(cpu.af[0], 0x02);
+    }
+
+    /// The return address the last dispatch pushed, with SP starting at 0xFFFE.
+    fn pushed(cpu: &Cpu<TestBus>) -> u16 {
+        u16::from_le_bytes([mem(cpu, 0xFFFC), mem(cpu, 0xFFFD)])
+    }
+
+    #[test]
+    fn ei_takes_effect_after_the_next_instruction() {
+        let mut cpu = cpu(&[0xFB, 0x00, 0x00]);
+        request(&mut cpu, 0x04, 0x04);
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.ime, Ime::Pending);
+        assert_eq!(cpu.step(), 1);
+        assert_eq!(cpu.pc, 0x0102);
+        assert_eq!(cpu.ime, Ime::Enabled);
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(pushed(&cpu), 0x0102);
+    }
+
+    #[test]
+    fn di_right_after_ei_keeps_ime_off() {
+        let mut cpu = cpu(&[0xFB, 0xF3, 0x00]);
+        request(&mut cpu, 0x04, 0x04);
+        for _ in 0..3 {
+            assert_eq!(cpu.step(), 1);
+        }
+        assert_eq!(cpu.pc, 0x0103);
+        assert_eq!(cpu.ime, Ime::Disabled);
+        assert_eq!(mem(&cpu, IF), 0x04);
+    }
+
+    #[test]
+    fn ei_halt_returns_to_the_halt() {
+        let mut cpu = cpu(&[0xFB, 0x76]);
+        cpu.bus_mut().mem[0x0050] = 0xD9;
+        request(&mut cpu, 0x04, 0x04);
+        cpu.step();
+
+        // HALT doesn't halt and doesn't advance PC, but IME is enabled after it
+        assert_eq!(cpu.step(), 1);
+        assert!(!cpu.halted);
+        assert!(!cpu.halt_bug);
+        assert_eq!(cpu.pc, 0x0101);
+        assert_eq!(cpu.ime, Ime::Enabled);
+
+        assert_eq!(cpu.step(), 5);
+        assert_eq!(pushed(&cpu), 0x0101);
+
+        // The handler's RETI returns to the HALT, which now halts
+        assert_eq!(cpu.step(), 4);
+        assert_eq!(cpu.pc, 0x0101);
+        assert_eq!(cpu.step(), 1);
+        assert!(cpu.halted);
+        assert_eq!(cpu.pc, 0x0102);
+    }
+
+    #[test]
+    fn reti_enables_ime_immediately() {
+        for (opcode, ime) in [(0xD9, Ime::Enabled), (0xC9, Ime::Disabled)] {
+            let mut cpu = cpu(&[opcode]);
+            cpu.sp = 0xFFFC;
+            cpu.bus_mut().mem[0xFFFC..0xFFFE].copy_from_slice(&[0x00, 0x02]);
+            request(&mut cpu, 0x04, 0x04);
+
+            assert_eq!(cpu.step(), 4);
+            assert_eq!(cpu.pc, 0x0200);
+            assert_eq!(cpu.ime, ime);
+            // RETI's handler is dispatched before the next instruction, RET's never is
+            if ime == Ime::Enabled {
+                assert_eq!(cpu.step(), 5);
+                assert_eq!(cpu.pc, 0x0050);
+                assert_eq!(pushed(&cpu), 0x0200);
+            } else {
+                assert_eq!(cpu.step(), 1);
+                assert_eq!(cpu.pc, 0x0201);
+            }
+        }
+    }
+
+    /// The M-cycles `code` takes, after `setup` prepared the CPU.
+    fn accesses(code: &[u8], setup: impl FnOnce(&mut Cpu<TestBus>)) -> Vec<Cycle> {
+        let mut cpu = cpu(code);
+        cpu.hl = Register::new(0xC000);
+        setup(&mut cpu);
+        cpu.step();
+        core::mem::take(&mut cpu.bus_mut().cycles)
+    }
+
+    #[test]
+    fn memory_is_accessed_in_order() {
+        use Cycle::*;
+
+        let nothing = |_: &mut Cpu<TestBus>| {};
+        let pop_0200 = |cpu: &mut Cpu<TestBus>| {
+            cpu.sp = 0xFFFC;
+            cpu.bus_mut().mem[0xFFFC..0xFFFE].copy_from_slice(&[0x00, 0x02]);
+        };
+        let fetch = Read(0x0100);
+
+        // Operands are read before the internal cycle, the high byte is pushed first
+        let call = [fetch, Read(0x0101), Read(0x0102), Internal];
+        let push = [Write(0xFFFD, 0x01), Write(0xFFFC, 0x03)];
+        assert_eq!(
+            accesses(&[0xCD, 0x00, 0x02], nothing),
+            [&call[..], &push].concat()
+        );
+        assert_eq!(
+            accesses(&[0xFF], nothing),
+            [fetch, Internal, Write(0xFFFD, 0x01), Write(0xFFFC, 0x01)]
+        );
+        assert_eq!(
+            accesses(&[0xC5], nothing),
+            [fetch, Internal, Write(0xFFFD, 0x00), Write(0xFFFC, 0x13)]
+        );
+
+        // The low byte is popped first
+        assert_eq!(
+            accesses(&[0xC1], pop_0200),
+            [fetch, Read(0xFFFC), Read(0xFFFD)]
+        );
+        assert_eq!(
+            accesses(&[0xC9], pop_0200),
+            [fetch, Read(0xFFFC), Read(0xFFFD), Internal]
+        );
+        // Checking the condition takes a cycle of its own
+        let ret_nz = |cpu: &mut Cpu<TestBus>| {
+            pop_0200(cpu);
+            cpu.af[1] = 0x00;
+        };
+        assert_eq!(
+            accesses(&[0xC0], ret_nz),
+            [fetch, Internal, Read(0xFFFC), Read(0xFFFD), Internal]
+        );
+        // Z is set after boot, so it isn't taken
+        assert_eq!(accesses(&[0xC0], pop_0200), [fetch, Internal]);
+
+        assert_eq!(
+            accesses(&[0x18, 0x02], nothing),
+            [fetch, Read(0x0101), Internal]
+        );
+        assert_eq!(
+            accesses(&[0xC3, 0x00, 0x02], nothing),
+            [fetch, Read(0x0101), Read(0x0102), Internal]
+        );
+        assert_eq!(
+            accesses(&[0x08, 0x00, 0xC0], nothing),
+            [
+                fetch,
+                Read(0x0101),
+                Read(0x0102),
+                Write(0xC000, 0xFE),
+                Write(0xC001, 0xFF)
+            ]
+        );
+        assert_eq!(
+            accesses(&[0xFA, 0x00, 0xC0], nothing),
+            [fetch, Read(0x0101), Read(0x0102), Read(0xC000)]
+        );
+
+        // Read-modify-write instructions write in the cycle after the read
+        assert_eq!(
+            accesses(&[0x34], nothing),
+            [fetch, Read(0xC000), Write(0xC000, 0x01)]
+        );
+        assert_eq!(
+            accesses(&[0xCB, 0xC6], nothing),
+            [fetch, Read(0x0101), Read(0xC000), Write(0xC000, 0x01)]
+        );
+        assert_eq!(
+            accesses(&[0xCB, 0x46], nothing),
+            [fetch, Read(0x0101), Read(0xC000)]
+        );
+    }
+}
@@ -0,0 +1,1003 @@
+//! An interactive debugger on top of [`Gameboy`].
+//!
+//! [`Debugger`] owns a `Gameboy` and adds what a debugger front end needs:
+//! breakpoints that only trigger in a given ROM bank or while a register
+//! condition holds, read, write and execute watchpoints on address ranges,
+//! stepping into, over and out of calls, and inspecting and patching registers
+//! and memory. Like the rest of the core it only needs `alloc`, so host tools
+//! and the firmware drive the same code.
+//!
+//! Breakpoints, conditions and watchpoints can be parsed from the text a user
+//! types into a console, e.g. `01:4000 if A == 3` or `C000-C0FF rw`. Numbers are
+//! hex, optionally prefixed with `$` or `0x`.
+//!
+//! PC breakpoints and read and write watchpoints are checked by the `Gameboy`
+//! while it runs at full speed. Execute watchpoints and unfinished steps make
+//! the debugger run one instruction at a time, which is a lot slower.
+
+use crate::cpu::Registers;
+use crate::disasm::Instruction;
+use crate::{Gameboy, Outcome, RunResult};
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// A number isn't valid hex or doesn't fit.
+    InvalidNumber,
+    UnknownRegister,
+    /// A condition isn't of the form `REG OP VALUE`.
+    InvalidCondition,
+    /// Watchpoint access flags other than `r`, `w` and `x`.
+    InvalidAccess,
+    /// A label is in this ROM bank, but another one is mapped at its address.
+    UnmappedBank(u16),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::InvalidNumber => write!(f, "invalid number"),
+            ParseError::UnknownRegister => write!(f, "unknown register"),
+            ParseError::InvalidCondition => write!(f, "condition must look like `A == 3`"),
+            ParseError::InvalidAccess => write!(f, "access must be a combination of r, w and x"),
+            ParseError::UnmappedBank(bank) => write!(f, "ROM bank {:02X} isn't mapped", bank),
+        }
+    }
+}
+
+/// Parses hex, with or without a `$` or `0x` prefix.
+pub(crate) fn parse_number(s: &str) -> Result<u16, ParseError> {
+    let s = s.trim();
+    let digits = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| ParseError::InvalidNumber)
+}
+
+/// A register that conditions can test and consoles can patch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reg {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    Af,
+    Bc,
+    De,
+    Hl,
+    Sp,
+    Pc,
+}
+
+impl Reg {
+    pub fn get(self, regs: &Registers) -> u16 {
+        let pair = |hi: u8, lo: u8| u16::from_be_bytes([hi, lo]);
+        match self {
+            Reg::A => regs.a as u16,
+            Reg::F => regs.f as u16,
+            Reg::B => regs.b as u16,
+            Reg::C => regs.c as u16,
+            Reg::D => regs.d as u16,
+            Reg::E => regs.e as u16,
+            Reg::H => regs.h as u16,
+            Reg::L => regs.l as u16,
+            Reg::Af => pair(regs.a, regs.f),
+            Reg::Bc => pair(regs.b, regs.c),
+            Reg::De => pair(regs.d, regs.e),
+            Reg::Hl => pair(regs.h, regs.l),
+            Reg::Sp => regs.sp,
+            Reg::Pc => regs.pc,
+        }
+    }
+
+    /// Sets the register to `val`, 8-bit registers take its low byte.
+    pub fn set(self, regs: &mut Registers, val: u16) {
+        let [hi, lo] = val.to_be_bytes();
+        match self {
+            Reg::A => regs.a = lo,
+            Reg::F => regs.f = lo,
+            Reg::B => regs.b = lo,
+            Reg::C => regs.c = lo,
+            Reg::D => regs.d = lo,
+            Reg::E => regs.e = lo,
+            Reg::H => regs.h = lo,
+            Reg::L => regs.l = lo,
+            Reg::Af => (regs.a, regs.f) = (hi, lo),
+            Reg::Bc => (regs.b, regs.c) = (hi, lo),
+            Reg::De => (regs.d, regs.e) = (hi, lo),
+            Reg::Hl => (regs.h, regs.l) = (hi, lo),
+            Reg::Sp => regs.sp = val,
+            Reg::Pc => regs.pc = val,
+        }
+    }
+}
+
+impl FromStr for Reg {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let reg = match s.trim().to_ascii_lowercase().as_str() {
+            "a" => Reg::A,
+            "f" => Reg::F,
+            "b" => Reg::B,
+            "c" => Reg::C,
+            "d" => Reg::D,
+            "e" => Reg::E,
+            "h" => Reg::H,
+            "l" => Reg::L,
+            "af" => Reg::Af,
+            "bc" => Reg::Bc,
+            "de" => Reg::De,
+            "hl" => Reg::Hl,
+            "sp" => Reg::Sp,
+            "pc" => Reg::Pc,
+            _ => return Err(ParseError::UnknownRegister),
+        };
+        Ok(reg)
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Reg::A => "A",
+            Reg::F => "F",
+            Reg::B => "B",
+            Reg::C => "C",
+            Reg::D => "D",
+            Reg::E => "E",
+            Reg::H => "H",
+            Reg::L => "L",
+            Reg::Af => "AF",
+            Reg::Bc => "BC",
+            Reg::De => "DE",
+            Reg::Hl => "HL",
+            Reg::Sp => "SP",
+            Reg::Pc => "PC",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A register compared to a value, e.g. `A == $10` or `HL >= C000`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub reg: Reg,
+    pub cmp: Cmp,
+    pub value: u16,
+}
+
+impl Condition {
+    pub fn holds(&self, regs: &Registers) -> bool {
+        let reg = self.reg.get(regs);
+        match self.cmp {
+            Cmp::Eq => reg == self.value,
+            Cmp::Ne => reg != self.value,
+            Cmp::Lt => reg < self.value,
+            Cmp::Le => reg <= self.value,
+            Cmp::Gt => reg > self.value,
+            Cmp::Ge => reg >= self.value,
+        }
+    }
+}
+
+impl FromStr for Condition {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let is_op = |c: char| matches!(c, '=' | '!' | '<' | '>');
+        let op_start = s.find(is_op).ok_or(ParseError::InvalidCondition)?;
+        let (reg, rest) = s.split_at(op_start);
+        let op_len = rest.find(|c| !is_op(c)).unwrap_or(rest.len());
+        let (op, value) = rest.split_at(op_len);
+        let cmp = match op {
+            "==" => Cmp::Eq,
+            "!=" => Cmp::Ne,
+            "<" => Cmp::Lt,
+            "<=" => Cmp::Le,
+            ">" => Cmp::Gt,
+            ">=" => Cmp::Ge,
+            _ => return Err(ParseError::InvalidCondition),
+        };
+        Ok(Condition {
+            reg: reg.parse()?,
+            cmp,
+            value: parse_number(value)?,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.cmp {
+            Cmp::Eq => "==",
+            Cmp::Ne => "!=",
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Gt => ">",
+            Cmp::Ge => ">=",
+        };
+        match self.reg {
+            Reg::Af | Reg::Bc | Reg::De | Reg::Hl | Reg::Sp | Reg::Pc => {
+                write!(f, "{} {} ${:04X}", self.reg, op, self.value)
+            }
+            _ => write!(f, "{} {} ${:02X}", self.reg, op, self.value),
+        }
+    }
+}
+
+/// Stops before the instruction at `addr` executes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    /// Only trigger while this ROM bank is mapped at `addr`. Ignored outside of
+    /// ROM, the debugger only tracks ROM banks.
+    pub bank: Option<u16>,
+    /// Only trigger while this holds.
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u16) -> Self {
+        Self {
+            addr,
+            bank: None,
+            condition: None,
+        }
+    }
+
+    pub fn with_bank(mut self, bank: u16) -> Self {
+        self.bank = Some(bank);
+        self
+    }
+
+    pub fn with_condition(mut self, condition: Condition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    /// Whether the breakpoint triggers with the CPU at its address.
+    // `Option::is_none_or` needs Rust 1.82, newer than some toolchains building this
+    #[allow(clippy::unnecessary_map_or)]
+    fn triggers(&self, gameboy: &Gameboy) -> bool {
+        let bank = gameboy.rom_bank(self.addr);
+        let in_bank = self.bank.is_none() || bank.is_none() || bank == self.bank;
+        in_bank
+            && self
+                .condition
+                .map_or(true, |cond| cond.holds(&gameboy.registers()))
+    }
+}
+
+/// Parses `[BANK:]ADDR [if CONDITION]`, e.g. `01:4000 if A == 3`.
+impl FromStr for Breakpoint {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (location, condition) = match s.split_once(" if ") {
+            Some((location, condition)) => (location, Some(condition.parse()?)),
+            None => (s, None),
+        };
+        let (bank, addr) = match location.split_once(':') {
+            Some((bank, addr)) => (Some(parse_number(bank)?), addr),
+            None => (None, location),
+        };
+        Ok(Breakpoint {
+            addr: parse_number(addr)?,
+            bank,
+            condition,
+        })
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(bank) = self.bank {
+            write!(f, "{:02X}:", bank)?;
+        }
+        write!(f, "{:04X}", self.addr)?;
+        if let Some(condition) = self.condition {
+            write!(f, " if {}", condition)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+/// Watches the CPU's accesses to `start..=end`. DMA and the PPU aren't watched.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    /// Includes fetching instructions and their operands.
+    pub read: bool,
+    pub write: bool,
+    /// The CPU is about to execute an instruction starting in the range.
+    pub execute: bool,
+}
+
+impl Watchpoint {
+    pub fn reads(start: u16, end: u16) -> Self {
+        Self::new(start, end, true, false, false)
+    }
+
+    pub fn writes(start: u16, end: u16) -> Self {
+        Self::new(start, end, false, true, false)
+    }
+
+    pub fn accesses(start: u16, end: u16) -> Self {
+        Self::new(start, end, true, true, false)
+    }
+
+    pub fn executes(start: u16, end: u16) -> Self {
+        Self::new(start, end, false, false, true)
+    }
+
+    pub fn watches(&self, addr: u16, access: Access) -> bool {
+        let watched = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        };
+        watched && (self.start..=self.end).contains(&addr)
+    }
+
+    fn new(start: u16, end: u16, read: bool, write: bool, execute: bool) -> Self {
+        Self {
+            start,
+            end,
+            read,
+            write,
+            execute,
+        }
+    }
+}
+
+/// Parses `START[-END] [ACCESS]`, where `ACCESS` combines `r`, `w` and `x` and
+/// defaults to `w`, e.g. `C000-C0FF rw` or `FF40`.
+impl FromStr for Watchpoint {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let range = parts.next().ok_or(ParseError::InvalidNumber)?;
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+            None => (parse_number(range)?, parse_number(range)?),
+        };
+        let access = parts.next().unwrap_or("w");
+        if parts.next().is_some() || access.is_empty() || access.contains(|c| !"rwx".contains(c)) {
+            return Err(ParseError::InvalidAccess);
+        }
+        Ok(Watchpoint::new(
+            start.min(end),
+            start.max(end),
+            access.contains('r'),
+            access.contains('w'),
+            access.contains('x'),
+        ))
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X}", self.start)?;
+        if self.end != self.start {
+            write!(f, "-{:04X}", self.end)?;
+        }
+        f.write_str(" ")?;
+        for (watched, flag) in [(self.read, "r"), (self.write, "w"), (self.execute, "x")] {
+            if watched {
+                f.write_str(flag)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An access that hit a watchpoint.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    /// The value read or written, or the opcode about to execute.
+    pub val: u8,
+    pub access: Access,
+}
+
+/// Why the debugger returned control.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DebugEvent {
+    /// The cycle budget ran out.
+    Completed,
+    /// The last step into, over or out of a call finished.
+    Stepped,
+    FrameReady,
+    SerialByte(u8),
+    LockedUp,
+    /// LD B, B executed at this address, see [`Gameboy::set_break_on_ld_b_b`].
+    SoftwareBreakpoint(u16),
+    /// The breakpoint with this id triggered, its instruction is about to execute.
+    Breakpoint(u32),
+    /// The watchpoint with this id was hit. Reads and writes stop after the
+    /// instruction, executes before it.
+    Watchpoint {
+        id: u32,
+        hit: WatchHit,
+    },
+}
+
+/// How many words [`Debugger::backtrace`] looks at.
+const STACK_SCAN: usize = 0x100;
+
+/// An unfinished step.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Step {
+    Into,
+    /// Until the call at the start returned to `ret`, with the stack back at `sp`.
+    Over {
+        ret: u16,
+        sp: u16,
+    },
+    /// Until a return pops the stack above `sp`.
+    Out {
+        sp: u16,
+    },
+    /// Reported by the next run.
+    Done,
+}
+
+/// Runs a [`Gameboy`] under the control of breakpoints, watchpoints and steps.
+///
+/// Breakpoints and watchpoints are identified by the id they were added with,
+/// ids aren't reused. The debugger manages the `Gameboy`'s own breakpoints,
+/// ones added to it directly are ignored.
+pub struct Debugger {
+    gameboy: Gameboy,
+    breakpoints: Vec<(u32, Breakpoint)>,
+    watchpoints: Vec<(u32, Watchpoint)>,
+    next_id: u32,
+    step: Option<Step>,
+}
+
+impl Debugger {
+    pub fn new(gameboy: Gameboy) -> Self {
+        Self {
+            gameboy,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next_id: 1,
+            step: None,
+        }
+    }
+
+    pub fn gameboy(&self) -> &Gameboy {
+        &self.gameboy
+    }
+
+    pub fn gameboy_mut(&mut self) -> &mut Gameboy {
+        &mut self.gameboy
+    }
+
+    pub fn into_inner(self) -> Gameboy {
+        self.gameboy
+    }
+
+    /// Returns the id of the new breakpoint.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> u32 {
+        self.gameboy.add_breakpoint(breakpoint.addr);
+        let id = self.take_id();
+        self.breakpoints.push((id, breakpoint));
+        id
+    }
+
+    /// Returns whether a breakpoint with this id existed.
+    pub fn remove_breakpoint(&mut self, id: u32) -> bool {
+        let idx = match self.breakpoints.iter().position(|&(bp, _)| bp == id) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        let (_, removed) = self.breakpoints.remove(idx);
+        if !self
+            .breakpoints
+            .iter()
+            .any(|(_, bp)| bp.addr == removed.addr)
+        {
+            self.gameboy.remove_breakpoint(removed.addr);
+        }
+        true
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (u32, &Breakpoint)> {
+        self.breakpoints.iter().map(|(id, bp)| (*id, bp))
+    }
+
+    /// Returns the id of the new watchpoint.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> u32 {
+        let id = self.take_id();
+        self.watchpoints.push((id, watchpoint));
+        self.sync_watchpoints();
+        id
+    }
+
+    /// Returns whether a watchpoint with this id existed.
+    pub fn remove_watchpoint(&mut self, id: u32) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|&(wp, _)| wp != id);
+        self.sync_watchpoints();
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (u32, &Watchpoint)> {
+        self.watchpoints.iter().map(|(id, wp)| (*id, wp))
+    }
+
+    /// Runs until at least `cycles` T-cycles have passed or something stops the
+    /// machine. An unfinished step continues, and is reported as
+    /// [`DebugEvent::Stepped`] once it finishes. Stopping at a breakpoint or
+    /// watchpoint cancels it.
+    ///
+    /// The instruction the debugger stopped at executes even if a breakpoint
+    /// triggered on it, so calling `run` again resumes.
+    pub fn run(&mut self, cycles: u32) -> DebugEvent {
+        let mut elapsed: u32 = 0;
+        loop {
+            let slow = self.step.is_some() || self.watches_execution();
+            if slow && !self.gameboy.at_breakpoint() {
+                if let Some(event) = self.check_pc() {
+                    return event;
+                }
+            }
+            if self.step == Some(Step::Done) {
+                self.step = None;
+                return DebugEvent::Stepped;
+            }
+            if elapsed >= cycles {
+                return DebugEvent::Completed;
+            }
+
+            let result = if slow {
+                self.step_instruction()
+            } else {
+                self.gameboy.run_cycles(cycles - elapsed)
+            };
+            elapsed = elapsed.saturating_add(result.cycles);
+
+            let event = match result.outcome {
+                Outcome::Completed => continue,
+                // Only the address is known to match, check the rest
+                Outcome::Breakpoint(_) => match self.check_pc() {
+                    Some(event) => return event,
+                    None => continue,
+                },
+                Outcome::FrameReady => return DebugEvent::FrameReady,
+                Outcome::SerialByte(val) => return DebugEvent::SerialByte(val),
+                Outcome::LockedUp => DebugEvent::LockedUp,
+                Outcome::SoftwareBreakpoint(pc) => DebugEvent::SoftwareBreakpoint(pc),
+                Outcome::Watchpoint(hit) => {
+                    let id = self
+                        .watchpoints
+                        .iter()
+                        .find(|(_, wp)| wp.watches(hit.addr, hit.access))
+                        .map(|&(id, _)| id);
+                    match id {
+                        Some(id) => DebugEvent::Watchpoint { id, hit },
+                        None => continue,
+                    }
+                }
+            };
+            self.step = None;
+            return event;
+        }
+    }
+
+    /// Runs until the current frame is complete or something stops the machine.
+    pub fn run_frame(&mut self) -> DebugEvent {
+        self.run(u32::MAX)
+    }
+
+    /// Starts a step that executes a single instruction, or enters the interrupt
+    /// handler that is due. The step doesn't run by itself: the following calls
+    /// to [`run`](Self::run) carry it out and return [`DebugEvent::Stepped`] once
+    /// it's done, unless something else stops the machine first.
+    pub fn step_into(&mut self) {
+        self.step = Some(Step::Into);
+    }
+
+    /// Like [`step_into`](Self::step_into), but runs a CALL or RST until it returns.
+    pub fn step_over(&mut self) {
+        let regs = self.gameboy.registers();
+        let instr = self.gameboy.disassemble(regs.pc);
+        self.step = Some(if instr.is_call() {
+            Step::Over {
+                ret: instr.next_addr(),
+                sp: regs.sp,
+            }
+        } else {
+            Step::Into
+        });
+    }
+
+    /// Starts a step that runs until the current function returns to its caller.
+    pub fn step_out(&mut self) {
+        self.step = Some(Step::Out {
+            sp: self.gameboy.registers().sp,
+        });
+    }
+
+    /// Forgets an unfinished step, so [`run`](Self::run) runs freely again.
+    pub fn cancel_step(&mut self) {
+        self.step = None;
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.gameboy.registers()
+    }
+
+    pub fn set_registers(&mut self, regs: &Registers) {
+        self.gameboy.set_registers(regs);
+    }
+
+    pub fn set_register(&mut self, reg: Reg, val: u16) {
+        let mut regs = self.gameboy.registers();
+        reg.set(&mut regs, val);
+        self.gameboy.set_registers(&regs);
+    }
+
+    /// Reads memory without side effects, see [`Gameboy::peek`].
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        self.gameboy.peek(addr)
+    }
+
+    /// Patches memory, see [`Gameboy::poke`]. Doesn't trigger watchpoints.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        self.gameboy.poke(addr, val);
+    }
+
+    pub fn disassemble(&mut self, addr: u16) -> Instruction {
+        self.gameboy.disassemble(addr)
+    }
+
+    /// The return addresses on the stack, innermost first, at most `max` of them.
+    ///
+    /// Found by walking up the stack from SP and keeping every word that points
+    /// right behind a CALL or RST, so pushed data that happens to look like a
+    /// return address shows up too, and frames entered by interrupts or jumps don't.
+    pub fn backtrace(&mut self, max: usize) -> Vec<u16> {
+        let mut frames = Vec::new();
+        let mut addr = self.gameboy.registers().sp;
+        for _ in 0..STACK_SCAN {
+            if frames.len() == max || addr >= 0xFFFE {
+                break;
+            }
+            let ret = u16::from_le_bytes([self.gameboy.peek(addr), self.gameboy.peek(addr + 1)]);
+            // CALL is 3 bytes long, RST 1
+            let called = [3, 1].iter().any(|&len| {
+                let instr = self.gameboy.disassemble(ret.wrapping_sub(len));
+                instr.is_call() && instr.next_addr() == ret
+            });
+            if called {
+                frames.push(ret);
+            }
+            addr += 2;
+        }
+        frames
+    }
+
+    fn take_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn watches_execution(&self) -> bool {
+        self.watchpoints.iter().any(|(_, wp)| wp.execute)
+    }
+
+    fn sync_watchpoints(&mut self) {
+        let watchpoints = self
+            .watchpoints
+            .iter()
+            .map(|&(_, wp)| wp)
+            .filter(|wp| wp.read || wp.write)
+            .collect();
+        self.gameboy.set_watchpoints(watchpoints);
+    }
+
+    /// Checks breakpoints and execute watchpoints on the instruction about to
+    /// execute. Resuming after a stop executes it.
+    fn check_pc(&mut self) -> Option<DebugEvent> {
+        let pc = self.gameboy.registers().pc;
+        let breakpoint = self
+            .breakpoints
+            .iter()
+            .find(|(_, bp)| bp.addr == pc && bp.triggers(&self.gameboy))
+            .map(|&(id, _)| id);
+        let event = match breakpoint {
+            Some(id) => DebugEvent::Breakpoint(id),
+            None => {
+                let (id, _) = self
+                    .watchpoints
+                    .iter()
+                    .find(|(_, wp)| wp.watches(pc, Access::Execute))?;
+                let hit = WatchHit {
+                    addr: pc,
+                    val: self.gameboy.peek(pc),
+                    access: Access::Execute,
+                };
+                DebugEvent::Watchpoint { id: *id, hit }
+            }
+        };
+        self.gameboy.set_at_breakpoint();
+        self.step = None;
+        Some(event)
+    }
+
+    /// Executes one instruction and notes whether that finished the current step.
+    fn step_instruction(&mut self) -> RunResult {
+        let before = self.gameboy.registers();
+        let returning = matches!(self.step, Some(Step::Out { .. }))
+            && self.gameboy.disassemble(before.pc).is_return();
+        let result = self.gameboy.step_instruction();
+
+        let after = self.gameboy.registers();
+        let done = match self.step {
+            Some(Step::Into) => true,
+            Some(Step::Over { ret, sp }) => after.pc == ret && after.sp >= sp,
+            Some(Step::Out { sp }) => returning && after.sp > sp,
+            Some(Step::Done) | None => false,
+        };
+        if done {
+            self.step = Some(Step::Done);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, CODE};
+    use alloc::string::ToString;
+
+    #[test]
+    fn parses_breakpoints() {
+        let cond = Condition {
+            reg: Reg::A,
+            cmp: Cmp::Eq,
+            value: 3,
+        };
+        assert_eq!(
+            "01:4000 if A == 3".parse(),
+            Ok(Breakpoint::new(0x4000).with_bank(1).with_condition(cond))
+        );
+        assert_eq!("$C000".parse(), Ok(Breakpoint::new(0xC000)));
+        assert_eq!("0x150".parse(), Ok(Breakpoint::new(0x0150)));
+
+        assert_eq!("".parse::<Breakpoint>(), Err(ParseError::InvalidNumber));
+        assert_eq!(
+            "10000".parse::<Breakpoint>(),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(
+            "xx:4000".parse::<Breakpoint>(),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(
+            "4000 if A".parse::<Breakpoint>(),
+            Err(ParseError::InvalidCondition)
+        );
+        assert_eq!(
+            "4000 if A = 3".parse::<Breakpoint>(),
+            Err(ParseError::InvalidCondition)
+        );
+        assert_eq!(
+            "4000 if Q == 3".parse::<Breakpoint>(),
+            Err(ParseError::UnknownRegister)
+        );
+    }
+
+    #[test]
+    fn breakpoints_display_as_parsed() {
+        for text in [
+            "01:4000 if A == $03",
+            "C000 if HL >= $C000",
+            "0150",
+            "0150 if sp != 0",
+        ] {
+            let bp: Breakpoint = text.parse().unwrap();
+            assert_eq!(bp.to_string().parse(), Ok(bp), "{}", text);
+        }
+        assert_eq!(
+            "1:4000 if hl<c000"
+                .parse::<Breakpoint>()
+                .unwrap()
+                .to_string(),
+            "01:4000 if HL < $C000"
+        );
+    }
+
+    #[test]
+    fn conditions_compare_registers() {
+        let mut regs = testing::gameboy(&[]).registers();
+        regs.a = 0x10;
+        (regs.h, regs.l) = (0xC0, 0x00);
+        let holds = |cond: &str| cond.parse::<Condition>().unwrap().holds(&regs);
+
+        assert!(holds("A == 10"));
+        assert!(!holds("A != 10"));
+        assert!(holds("A < 11"));
+        assert!(holds("A <= 10"));
+        assert!(!holds("A > 10"));
+        assert!(holds("A >= 10"));
+        assert!(holds("HL == C000"));
+        assert!(holds("H == C0"));
+        assert_eq!(
+            "A <> 1".parse::<Condition>(),
+            Err(ParseError::InvalidCondition)
+        );
+    }
+
+    #[test]
+    fn parses_watchpoints() {
+        assert_eq!("FF40".parse(), Ok(Watchpoint::writes(0xFF40, 0xFF40)));
+        assert_eq!("C000-C0FF r".parse(), Ok(Watchpoint::reads(0xC000, 0xC0FF)));
+        // The range is put in order
+        assert_eq!(
+            "C0FF-C000 rw".parse(),
+            Ok(Watchpoint::accesses(0xC000, 0xC0FF))
+        );
+        assert_eq!("$0150 x".parse(), Ok(Watchpoint::executes(0x0150, 0x0150)));
+
+        assert_eq!("".parse::<Watchpoint>(), Err(ParseError::InvalidNumber));
+        assert_eq!(
+            "C000-".parse::<Watchpoint>(),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(
+            "C000 q".parse::<Watchpoint>(),
+            Err(ParseError::InvalidAccess)
+        );
+        assert_eq!(
+            "C000 r w".parse::<Watchpoint>(),
+            Err(ParseError::InvalidAccess)
+        );
+
+        assert_eq!(
+            Watchpoint::accesses(0xC000, 0xC0FF).to_string(),
+            "C000-C0FF rw"
+        );
+        assert_eq!(Watchpoint::writes(0xFF40, 0xFF40).to_string(), "FF40 w");
+        let wp: Watchpoint = "8000-9FFF rwx".parse().unwrap();
+        assert_eq!(wp.to_string().parse(), Ok(wp));
+    }
+
+    #[test]
+    fn watchpoints_match_access_and_range() {
+        let wp = Watchpoint::reads(0xC000, 0xC0FF);
+        assert!(wp.watches(0xC000, Access::Read));
+        assert!(wp.watches(0xC0FF, Access::Read));
+        assert!(!wp.watches(0xC100, Access::Read));
+        assert!(!wp.watches(0xC000, Access::Write));
+        assert!(!wp.watches(0xC000, Access::Execute));
+    }
+
+    /// CALL $0160, then loops forever, the function increments A.
+    const CALL: [u8; 19] = [
+        0xCD, 0x60, 0x01, // CALL $0160
+        0x18, 0xFE, // JR $0153
+        0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,    // Padding up to $0160
+        0x00, // NOP
+        0x3C, // INC A
+        0xC9, // RET
+    ];
+
+    /// Runs until something other than a frame stops the machine.
+    fn finish(debugger: &mut Debugger) -> DebugEvent {
+        loop {
+            match debugger.run(u32::MAX) {
+                DebugEvent::FrameReady => {}
+                event => return event,
+            }
+        }
+    }
+
+    fn pc(debugger: &Debugger) -> u16 {
+        debugger.registers().pc
+    }
+
+    #[test]
+    fn steps_are_driven_by_run() {
+        let mut debugger = Debugger::new(testing::gameboy(&CALL));
+        debugger.step_into();
+        // Arming the step doesn't run anything
+        assert_eq!(pc(&debugger), 0x0100);
+        assert_eq!(finish(&mut debugger), DebugEvent::Stepped);
+        assert_eq!(pc(&debugger), CODE);
+        // Done steps aren't reported again
+        assert_eq!(debugger.run(100), DebugEvent::Completed);
+    }
+
+    #[test]
+    fn step_into_enters_calls() {
+        let mut debugger = Debugger::new(testing::gameboy(&CALL));
+        debugger.step_into();
+        finish(&mut debugger);
+        debugger.step_into();
+        assert_eq!(finish(&mut debugger), DebugEvent::Stepped);
+        assert_eq!(pc(&debugger), 0x0160);
+        assert_eq!(debugger.registers().sp, 0xFFFC);
+    }
+
+    #[test]
+    fn step_over_runs_calls() {
+        let mut debugger = Debugger::new(testing::gameboy(&CALL));
+        debugger.step_into();
+        finish(&mut debugger);
+        let a = debugger.registers().a;
+        debugger.step_over();
+        assert_eq!(finish(&mut debugger), DebugEvent::Stepped);
+        assert_eq!(pc(&debugger), CODE + 3);
+        assert_eq!(debugger.registers().sp, 0xFFFE);
+        assert_eq!(debugger.registers().a, a.wrapping_add(1));
+    }
+
+    #[test]
+    fn step_out_returns_to_the_caller() {
+        let mut debugger = Debugger::new(testing::gameboy(&CALL));
+        for _ in 0..2 {
+            debugger.step_into();
+            finish(&mut debugger);
+        }
+        assert_eq!(pc(&debugger), 0x0160);
+        debugger.step_out();
+        assert_eq!(finish(&mut debugger), DebugEvent::Stepped);
+        assert_eq!(pc(&debugger), CODE + 3);
+        assert_eq!(debugger.registers().sp, 0xFFFE);
+    }
+
+    #[test]
+    fn breakpoints_cancel_steps() {
+        let mut debugger = Debugger::new(testing::gameboy(&CALL));
+        debugger.step_into();
+        finish(&mut debugger);
+        let id = debugger.add_breakpoint(Breakpoint::new(0x0161));
+        debugger.step_over();
+        assert_eq!(finish(&mut debugger), DebugEvent::Breakpoint(id));
+        assert_eq!(pc(&debugger), 0x0161);
+        // The rest of the call and the loop after it run without stopping
+        assert_eq!(debugger.run(1000), DebugEvent::Completed);
+        assert_eq!(pc(&debugger), CODE + 3);
+    }
+
+    #[test]
+    fn cancelled_steps_are_not_reported() {
+        let mut debugger = Debugger::new(testing::gameboy(&CALL));
+        debugger.step_into();
+        debugger.cancel_step();
+        assert_eq!(debugger.run(1000), DebugEvent::Completed);
+        assert_eq!(pc(&debugger), CODE + 3);
+    }
+}
@@ -0,0 +1,423 @@
+//! SM83 opcode tables.
+//!
+//! Every opcode is decoded once at compile time into an [`Instr`] with its
+//! operands already picked out, so executing an instruction is a table lookup
+//! and a single `match` instead of decoding bit fields on every step.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum R8 {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    /// The byte at HL.
+    HlInd,
+    A,
+}
+
+/// Operands of 16-bit loads and arithmetic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum R16 {
+    Bc,
+    De,
+    Hl,
+    Sp,
+}
+
+/// Operands of PUSH and POP.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum R16Stack {
+    Bc,
+    De,
+    Hl,
+    Af,
+}
+
+/// Pointers used by `LD [r16], A` and `LD A, [r16]`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum R16Mem {
+    Bc,
+    De,
+    /// HL, incremented afterwards.
+    HlInc,
+    /// HL, decremented afterwards.
+    HlDec,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cond {
+    Nz,
+    Z,
+    Nc,
+    C,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+}
+
+/// Rotates, shifts and SWAP, the first quarter of the CB table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ShiftOp {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
+
+/// The accumulator-only operations at 0x07..=0x3F.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccOp {
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Instr {
+    Nop,
+    LdU16pSp,
+    Stop,
+    Jr,
+    JrCond(Cond),
+    LdR16U16(R16),
+    AddHlR16(R16),
+    LdR16pA(R16Mem),
+    LdAR16p(R16Mem),
+    IncR16(R16),
+    DecR16(R16),
+    IncR8(R8),
+    DecR8(R8),
+    LdR8U8(R8),
+    Acc(AccOp),
+    Halt,
+    LdR8R8(R8, R8),
+    AluR8(AluOp, R8),
+    RetCond(Cond),
+    LdhU8A,
+    AddSpI8,
+    LdhAU8,
+    LdHlSpI8,
+    Pop(R16Stack),
+    Ret,
+    Reti,
+    JpHl,
+    LdSpHl,
+    JpCond(Cond),
+    LdhCA,
+    LdU16pA,
+    LdhAC,
+    LdAU16p,
+    JpU16,
+    /// 0xCB, the next byte is looked up in [`CB_OPCODES`].
+    Prefix,
+    Di,
+    Ei,
+    CallCond(Cond),
+    Push(R16Stack),
+    CallU16,
+    AluU8(AluOp),
+    /// The target address, 0x00 to 0x38.
+    Rst(u8),
+    /// Hangs the CPU.
+    Illegal,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CbInstr {
+    Shift(ShiftOp, R8),
+    Bit(u8, R8),
+    Res(u8, R8),
+    Set(u8, R8),
+}
+
+impl Instr {
+    /// Length in bytes including the opcode. STOP counts as 2 like in assemblers.
+    pub fn len(self) -> u16 {
+        match self {
+            Instr::LdU16pSp
+            | Instr::LdR16U16(_)
+            | Instr::JpCond(_)
+            | Instr::LdU16pA
+            | Instr::LdAU16p
+            | Instr::JpU16
+            | Instr::CallCond(_)
+            | Instr::CallU16 => 3,
+            Instr::Stop
+            | Instr::Jr
+            | Instr::JrCond(_)
+            | Instr::LdR8U8(_)
+            | Instr::LdhU8A
+            | Instr::AddSpI8
+            | Instr::LdhAU8
+            | Instr::LdHlSpI8
+            | Instr::Prefix
+            | Instr::AluU8(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+pub static OPCODES: [Instr; 256] = {
+    let mut table = [Instr::Nop; 256];
+    let mut op = 0;
+    while op < 256 {
+        table[op] = decode(op as u8);
+        op += 1;
+    }
+    table
+};
+
+pub static CB_OPCODES: [CbInstr; 256] = {
+    let mut table = [CbInstr::Bit(0, R8::B); 256];
+    let mut op = 0;
+    while op < 256 {
+        table[op] = decode_cb(op as u8);
+        op += 1;
+    }
+    table
+};
+
+const R8S: [R8; 8] = [R8::B, R8::C, R8::D, R8::E, R8::H, R8::L, R8::HlInd, R8::A];
+const R16S: [R16; 4] = [R16::Bc, R16::De, R16::Hl, R16::Sp];
+const R16_STACK: [R16Stack; 4] = [R16Stack::Bc, R16Stack::De, R16Stack::Hl, R16Stack::Af];
+const R16_MEM: [R16Mem; 4] = [R16Mem::Bc, R16Mem::De, R16Mem::HlInc, R16Mem::HlDec];
+const CONDS: [Cond; 4] = [Cond::Nz, Cond::Z, Cond::Nc, Cond::C];
+const ALU_OPS: [AluOp; 8] = [
+    AluOp::Add,
+    AluOp::Adc,
+    AluOp::Sub,
+    AluOp::Sbc,
+    AluOp::And,
+    AluOp::Xor,
+    AluOp::Or,
+    AluOp::Cp,
+];
+const ACC_OPS: [AccOp; 8] = [
+    AccOp::Rlca,
+    AccOp::Rrca,
+    AccOp::Rla,
+    AccOp::Rra,
+    AccOp::Daa,
+    AccOp::Cpl,
+    AccOp::Scf,
+    AccOp::Ccf,
+];
+const SHIFT_OPS: [ShiftOp; 8] = [
+    ShiftOp::Rlc,
+    ShiftOp::Rrc,
+    ShiftOp::Rl,
+    ShiftOp::Rr,
+    ShiftOp::Sla,
+    ShiftOp::Sra,
+    ShiftOp::Swap,
+    ShiftOp::Srl,
+];
+
+/// Splits the opcode into its `xxyyyzzz` fields, `y` is further split into `ppq`.
+const fn decode(op: u8) -> Instr {
+    let x = op >> 6;
+    let y = ((op >> 3) & 0x7) as usize;
+    let z = (op & 0x7) as usize;
+    let p = y >> 1;
+    let q = y & 0x1;
+
+    match x {
+        0x0 => match z {
+            0x0 => match y {
+                0x0 => Instr::Nop,
+                0x1 => Instr::LdU16pSp,
+                0x2 => Instr::Stop,
+                0x3 => Instr::Jr,
+                _ => Instr::JrCond(CONDS[y - 4]),
+            },
+            0x1 if q == 0x0 => Instr::LdR16U16(R16S[p]),
+            0x1 => Instr::AddHlR16(R16S[p]),
+            0x2 if q == 0x0 => Instr::LdR16pA(R16_MEM[p]),
+            0x2 => Instr::LdAR16p(R16_MEM[p]),
+            0x3 if q == 0x0 => Instr::IncR16(R16S[p]),
+            0x3 => Instr::DecR16(R16S[p]),
+            0x4 => Instr::IncR8(R8S[y]),
+            0x5 => Instr::DecR8(R8S[y]),
+            0x6 => Instr::LdR8U8(R8S[y]),
+            _ => Instr::Acc(ACC_OPS[y]),
+        },
+        // LD [HL], [HL] is where HALT went
+        0x1 if op == 0x76 => Instr::Halt,
+        0x1 => Instr::LdR8R8(R8S[y], R8S[z]),
+        0x2 => Instr::AluR8(ALU_OPS[y], R8S[z]),
+        _ => match z {
+            0x0 => match y {
+                0x0..=0x3 => Instr::RetCond(CONDS[y]),
+                0x4 => Instr::LdhU8A,
+                0x5 => Instr::AddSpI8,
+                0x6 => Instr::LdhAU8,
+                _ => Instr::LdHlSpI8,
+            },
+            0x1 if q == 0x0 => Instr::Pop(R16_STACK[p]),
+            0x1 => match p {
+                0x0 => Instr::Ret,
+                0x1 => Instr::Reti,
+                0x2 => Instr::JpHl,
+                _ => Instr::LdSpHl,
+            },
+            0x2 => match y {
+                0x0..=0x3 => Instr::JpCond(CONDS[y]),
+                0x4 => Instr::LdhCA,
+                0x5 => Instr::LdU16pA,
+                0x6 => Instr::LdhAC,
+                _ => Instr::LdAU16p,
+            },
+            0x3 => match y {
+                0x0 => Instr::JpU16,
+                0x1 => Instr::Prefix,
+                0x6 => Instr::Di,
+                0x7 => Instr::Ei,
+                _ => Instr::Illegal,
+            },
+            0x4 if y < 0x4 => Instr::CallCond(CONDS[y]),
+            0x5 if q == 0x0 => Instr::Push(R16_STACK[p]),
+            0x5 if p == 0x0 => Instr::CallU16,
+            0x4 | 0x5 => Instr::Illegal,
+            0x6 => Instr::AluU8(ALU_OPS[y]),
+            _ => Instr::Rst((y as u8) << 3),
+        },
+    }
+}
+
+const fn decode_cb(op: u8) -> CbInstr {
+    let y = (op >> 3) & 0x7;
+    let reg = R8S[(op & 0x7) as usize];
+    match op >> 6 {
+        0x0 => CbInstr::Shift(SHIFT_OPS[y as usize], reg),
+        0x1 => CbInstr::Bit(y, reg),
+        0x2 => CbInstr::Res(y, reg),
+        _ => CbInstr::Set(y, reg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Instruction lengths from the opcode table in the Pan Docs, row by row.
+    const LENGTHS: [u16; 256] = [
+        1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1, // 0x00
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 0x10
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 0x20
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1, // 0x30
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x40
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x50
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x60
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x70
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x80
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0x90
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0xA0
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, // 0xB0
+        1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1, // 0xC0
+        1, 1, 3, 1, 3, 1, 2, 1, 1, 1, 3, 1, 3, 1, 2, 1, // 0xD0
+        2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1, // 0xE0
+        2, 1, 1, 1, 1, 1, 2, 1, 2, 1, 3, 1, 1, 1, 2, 1, // 0xF0
+    ];
+
+    #[test]
+    fn lengths_match_the_pan_docs() {
+        for (op, instr) in OPCODES.iter().enumerate() {
+            assert_eq!(instr.len(), LENGTHS[op], "opcode {:02X}", op);
+        }
+    }
+
+    #[test]
+    fn illegal_opcodes() {
+        let illegal: [u8; 11] = [
+            0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+        ];
+        for (op, &instr) in OPCODES.iter().enumerate() {
+            assert_eq!(
+                instr == Instr::Illegal,
+                illegal.contains(&(op as u8)),
+                "opcode {:02X}",
+                op
+            );
+        }
+    }
+
+    #[test]
+    fn decodes_operands() {
+        assert_eq!(OPCODES[0x00], Instr::Nop);
+        assert_eq!(OPCODES[0x10], Instr::Stop);
+        assert_eq!(OPCODES[0x21], Instr::LdR16U16(R16::Hl));
+        assert_eq!(OPCODES[0x22], Instr::LdR16pA(R16Mem::HlInc));
+        assert_eq!(OPCODES[0x3A], Instr::LdAR16p(R16Mem::HlDec));
+        assert_eq!(OPCODES[0x38], Instr::JrCond(Cond::C));
+        assert_eq!(OPCODES[0x27], Instr::Acc(AccOp::Daa));
+        assert_eq!(OPCODES[0x36], Instr::LdR8U8(R8::HlInd));
+        assert_eq!(OPCODES[0x41], Instr::LdR8R8(R8::B, R8::C));
+        assert_eq!(OPCODES[0x76], Instr::Halt);
+        assert_eq!(OPCODES[0x77], Instr::LdR8R8(R8::HlInd, R8::A));
+        assert_eq!(OPCODES[0x9E], Instr::AluR8(AluOp::Sbc, R8::HlInd));
+        assert_eq!(OPCODES[0xBF], Instr::AluR8(AluOp::Cp, R8::A));
+        assert_eq!(OPCODES[0xC8], Instr::RetCond(Cond::Z));
+        assert_eq!(OPCODES[0xD9], Instr::Reti);
+        assert_eq!(OPCODES[0xF1], Instr::Pop(R16Stack::Af));
+        assert_eq!(OPCODES[0xE9], Instr::JpHl);
+        assert_eq!(OPCODES[0xF9], Instr::LdSpHl);
+        assert_eq!(OPCODES[0xE2], Instr::LdhCA);
+        assert_eq!(OPCODES[0xCB], Instr::Prefix);
+        assert_eq!(OPCODES[0xD4], Instr::CallCond(Cond::Nc));
+        assert_eq!(OPCODES[0xE5], Instr::Push(R16Stack::Hl));
+        assert_eq!(OPCODES[0xEE], Instr::AluU8(AluOp::Xor));
+        assert_eq!(OPCODES[0xFF], Instr::Rst(0x38));
+    }
+
+    #[test]
+    fn decodes_cb_opcodes() {
+        // One of each shift, on every register
+        assert_eq!(CB_OPCODES[0x00], CbInstr::Shift(ShiftOp::Rlc, R8::B));
+        assert_eq!(CB_OPCODES[0x09], CbInstr::Shift(ShiftOp::Rrc, R8::C));
+        assert_eq!(CB_OPCODES[0x12], CbInstr::Shift(ShiftOp::Rl, R8::D));
+        assert_eq!(CB_OPCODES[0x1B], CbInstr::Shift(ShiftOp::Rr, R8::E));
+        assert_eq!(CB_OPCODES[0x24], CbInstr::Shift(ShiftOp::Sla, R8::H));
+        assert_eq!(CB_OPCODES[0x2D], CbInstr::Shift(ShiftOp::Sra, R8::L));
+        assert_eq!(CB_OPCODES[0x36], CbInstr::Shift(ShiftOp::Swap, R8::HlInd));
+        assert_eq!(CB_OPCODES[0x37], CbInstr::Shift(ShiftOp::Swap, R8::A));
+        assert_eq!(CB_OPCODES[0x3E], CbInstr::Shift(ShiftOp::Srl, R8::HlInd));
+        assert_eq!(CB_OPCODES[0x3F], CbInstr::Shift(ShiftOp::Srl, R8::A));
+
+        assert_eq!(CB_OPCODES[0x40], CbInstr::Bit(0, R8::B));
+        assert_eq!(CB_OPCODES[0x46], CbInstr::Bit(0, R8::HlInd));
+        assert_eq!(CB_OPCODES[0x5A], CbInstr::Bit(3, R8::D));
+        assert_eq!(CB_OPCODES[0x7C], CbInstr::Bit(7, R8::H));
+        assert_eq!(CB_OPCODES[0x7F], CbInstr::Bit(7, R8::A));
+
+        assert_eq!(CB_OPCODES[0x80], CbInstr::Res(0, R8::B));
+        assert_eq!(CB_OPCODES[0x86], CbInstr::Res(0, R8::HlInd));
+        assert_eq!(CB_OPCODES[0xA3], CbInstr::Res(4, R8::E));
+        assert_eq!(CB_OPCODES[0xBE], CbInstr::Res(7, R8::HlInd));
+
+        assert_eq!(CB_OPCODES[0xC0], CbInstr::Set(0, R8::B));
+        assert_eq!(CB_OPCODES[0xD1], CbInstr::Set(2, R8::C));
+        assert_eq!(CB_OPCODES[0xEE], CbInstr::Set(5, R8::HlInd));
+        assert_eq!(CB_OPCODES[0xF5], CbInstr::Set(6, R8::L));
+        assert_eq!(CB_OPCODES[0xFF], CbInstr::Set(7, R8::A));
+    }
+}
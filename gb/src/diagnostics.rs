@@ -0,0 +1,374 @@
+//! Structured diagnostics emitted by the emulator core.
+//!
+//! Components report noteworthy accesses as [`Event`]s to the [`Diagnostics`]
+//! the [`Gameboy`](crate::Gameboy) was constructed with. Events are filtered by
+//! [`Level`] and rate-limited per kind before they reach the [`Sink`], so a game
+//! hammering an unimplemented register doesn't stall the emulator.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    UnmappedIoRead { addr: u16 },
+    UnmappedIoWrite { addr: u16, val: u8 },
+    ProhibitedRead { addr: u16 },
+    ProhibitedWrite { addr: u16, val: u8 },
+    UnsupportedMapperWrite { addr: u16, val: u8 },
+    IllegalOpcode { addr: u16, opcode: u8 },
+}
+
+impl Event {
+    const KINDS: usize = 6;
+
+    pub fn level(&self) -> Level {
+        match self {
+            Event::UnmappedIoRead { .. } | Event::UnmappedIoWrite { .. } => Level::Debug,
+            Event::ProhibitedRead { .. } | Event::ProhibitedWrite { .. } => Level::Warn,
+            Event::UnsupportedMapperWrite { .. } => Level::Warn,
+            Event::IllegalOpcode { .. } => Level::Error,
+        }
+    }
+
+    fn kind(&self) -> usize {
+        match self {
+            Event::UnmappedIoRead { .. } => 0,
+            Event::UnmappedIoWrite { .. } => 1,
+            Event::ProhibitedRead { .. } => 2,
+            Event::ProhibitedWrite { .. } => 3,
+            Event::UnsupportedMapperWrite { .. } => 4,
+            Event::IllegalOpcode { .. } => 5,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Event::UnmappedIoRead { addr } => {
+                write!(f, "Tried to access unimplemented IO register at {:X}", addr)
+            }
+            Event::UnmappedIoWrite { addr, val } => write!(
+                f,
+                "Tried to write {:X} to unimplemented IO register at {:X}",
+                val, addr
+            ),
+            Event::ProhibitedRead { addr } => {
+                write!(f, "Tried to access prohibited memory at {:X}", addr)
+            }
+            Event::ProhibitedWrite { addr, val } => write!(
+                f,
+                "Tried to write {:X} to prohibited memory at {:X}",
+                val, addr
+            ),
+            Event::UnsupportedMapperWrite { addr, val } => {
+                write!(f, "Tried to write {:X} to {:X} in cartridge", val, addr)
+            }
+            Event::IllegalOpcode { addr, opcode } => write!(
+                f,
+                "Executed illegal opcode {:X} at {:X}, the CPU locked up",
+                opcode, addr
+            ),
+        }
+    }
+}
+
+/// An event as handed to a [`Sink`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub level: Level,
+    pub event: Event,
+    /// How often an event of this kind has occurred so far, including this one.
+    pub count: u32,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self.level {
+            Level::Debug => "DEBUG",
+            Level::Info => "INFO",
+            Level::Warn => "WARN",
+            Level::Error => "ERROR",
+        };
+        write!(f, "[{}] {}", level, self.event)?;
+        if self.count > 1 {
+            write!(f, " (#{})", self.count)?;
+        }
+        Ok(())
+    }
+}
+
+pub trait Sink {
+    fn record(&mut self, record: &Record);
+}
+
+/// Shared sinks let the owner inspect what was recorded, e.g. a [`MemorySink`] in tests.
+impl<S: Sink> Sink for Rc<RefCell<S>> {
+    fn record(&mut self, record: &Record) {
+        self.borrow_mut().record(record);
+    }
+}
+
+/// Discards everything.
+pub struct NullSink;
+
+impl Sink for NullSink {
+    fn record(&mut self, _record: &Record) {}
+}
+
+/// Keeps the most recent `capacity` records in memory.
+pub struct MemorySink {
+    records: VecDeque<Record>,
+    capacity: usize,
+    dropped: usize,
+}
+
+impl MemorySink {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.records.iter()
+    }
+
+    /// Number of records evicted because the buffer was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.dropped = 0;
+    }
+}
+
+impl Sink for MemorySink {
+    fn record(&mut self, record: &Record) {
+        if self.capacity == 0 {
+            self.dropped += 1;
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+            self.dropped += 1;
+        }
+        self.records.push_back(*record);
+    }
+}
+
+/// Formats records as lines into any [`fmt::Write`], e.g. a USART transmitter.
+pub struct WriteSink<W: fmt::Write> {
+    writer: W,
+}
+
+impl<W: fmt::Write> WriteSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: fmt::Write> Sink for WriteSink<W> {
+    fn record(&mut self, record: &Record) {
+        let _ = write!(self.writer, "{}\r\n", record);
+    }
+}
+
+/// Prints records through ARM semihosting. Very slow, but needs no wiring.
+#[cfg(feature = "semihosting")]
+pub struct SemihostingSink;
+
+#[cfg(feature = "semihosting")]
+impl Sink for SemihostingSink {
+    fn record(&mut self, record: &Record) {
+        let _ = cortex_m_semihosting::hprintln!("{}", record);
+    }
+}
+
+/// Filters, rate-limits and forwards events to a [`Sink`].
+///
+/// The first `burst` events of each kind are forwarded, after that only every
+/// power-of-two occurrence is, so the log still shows that an event keeps happening.
+pub struct Diagnostics {
+    sink: Box<dyn Sink>,
+    level: Level,
+    burst: u32,
+    counts: [u32; Event::KINDS],
+}
+
+impl Diagnostics {
+    pub fn new(sink: impl Sink + 'static) -> Self {
+        Self {
+            sink: Box::new(sink),
+            level: Level::Debug,
+            burst: 8,
+            counts: [0; Event::KINDS],
+        }
+    }
+
+    /// Drops all events below `level`.
+    pub fn with_level(mut self, level: Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        let level = event.level();
+        if level < self.level {
+            return;
+        }
+
+        let count = &mut self.counts[event.kind()];
+        *count = count.saturating_add(1);
+        let count = *count;
+        if count > self.burst && !count.is_power_of_two() {
+            return;
+        }
+
+        self.sink.record(&Record {
+            level,
+            event,
+            count,
+        });
+    }
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self::new(NullSink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn recorded(sink: &Rc<RefCell<MemorySink>>) -> Vec<(Level, u32)> {
+        sink.borrow()
+            .records()
+            .map(|record| (record.level, record.count))
+            .collect()
+    }
+
+    #[test]
+    fn bursts_then_powers_of_two() {
+        let sink = Rc::new(RefCell::new(MemorySink::new(64)));
+        let mut diag = Diagnostics::new(sink.clone()).with_burst(3);
+        for _ in 0..40 {
+            diag.emit(Event::ProhibitedRead { addr: 0xFEA0 });
+        }
+        let counts: Vec<u32> = recorded(&sink).iter().map(|&(_, count)| count).collect();
+        assert_eq!(counts, [1, 2, 3, 4, 8, 16, 32]);
+    }
+
+    #[test]
+    fn kinds_are_limited_separately() {
+        let sink = Rc::new(RefCell::new(MemorySink::new(64)));
+        let mut diag = Diagnostics::new(sink.clone()).with_burst(1);
+        for _ in 0..3 {
+            diag.emit(Event::ProhibitedRead { addr: 0xFEA0 });
+        }
+        diag.emit(Event::ProhibitedWrite {
+            addr: 0xFEA0,
+            val: 1,
+        });
+        let events: Vec<Event> = sink.borrow().records().map(|record| record.event).collect();
+        assert_eq!(
+            events,
+            [
+                Event::ProhibitedRead { addr: 0xFEA0 },
+                Event::ProhibitedRead { addr: 0xFEA0 },
+                Event::ProhibitedWrite {
+                    addr: 0xFEA0,
+                    val: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_by_level() {
+        let sink = Rc::new(RefCell::new(MemorySink::new(64)));
+        let mut diag = Diagnostics::new(sink.clone()).with_level(Level::Warn);
+        diag.emit(Event::UnmappedIoRead { addr: 0xFF03 });
+        diag.emit(Event::ProhibitedRead { addr: 0xFEA0 });
+        diag.emit(Event::IllegalOpcode {
+            addr: 0x0150,
+            opcode: 0xD3,
+        });
+        assert_eq!(recorded(&sink), [(Level::Warn, 1), (Level::Error, 1)]);
+
+        // Filtered events don't count towards the limit either
+        let sink = Rc::new(RefCell::new(MemorySink::new(64)));
+        let mut diag = Diagnostics::new(sink.clone())
+            .with_level(Level::Error)
+            .with_burst(1);
+        diag.emit(Event::UnmappedIoRead { addr: 0xFF03 });
+        diag.with_level(Level::Debug)
+            .emit(Event::UnmappedIoRead { addr: 0xFF03 });
+        assert_eq!(recorded(&sink), [(Level::Debug, 1)]);
+    }
+
+    #[test]
+    fn memory_sink_keeps_the_newest() {
+        let mut sink = MemorySink::new(2);
+        for addr in 0..5 {
+            sink.record(&Record {
+                level: Level::Debug,
+                event: Event::UnmappedIoRead { addr },
+                count: 1,
+            });
+        }
+        let events: Vec<Event> = sink.records().map(|record| record.event).collect();
+        assert_eq!(
+            events,
+            [
+                Event::UnmappedIoRead { addr: 3 },
+                Event::UnmappedIoRead { addr: 4 }
+            ]
+        );
+        assert_eq!(sink.dropped(), 3);
+    }
+
+    #[test]
+    fn records_format_with_their_count() {
+        let record = Record {
+            level: Level::Warn,
+            event: Event::ProhibitedRead { addr: 0xFEA0 },
+            count: 1,
+        };
+        assert_eq!(
+            alloc::format!("{}", record),
+            "[WARN] Tried to access prohibited memory at FEA0"
+        );
+        let record = Record {
+            count: 16,
+            ..record
+        };
+        assert_eq!(
+            alloc::format!("{}", record),
+            "[WARN] Tried to access prohibited memory at FEA0 (#16)"
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! SM83 disassembler.
+//!
+//! Decodes with the same opcode tables the CPU executes from, so what is shown
+//! is what runs. Output follows RGBDS syntax: `LD [HL+], A`, `JR NZ, $0150`,
+//! with IO registers named like in `hardware.inc`, e.g. `LDH [rLCDC], A`.
+
+use crate::decode::{
+    AccOp, AluOp, CbInstr, Cond, Instr, R16Mem, R16Stack, ShiftOp, CB_OPCODES, OPCODES, R16, R8,
+};
+use core::fmt;
+
+/// A decoded instruction, formats as its mnemonic.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    addr: u16,
+    bytes: [u8; 3],
+    instr: Instr,
+}
+
+impl Instruction {
+    /// Decodes the instruction at `addr`, `bytes` are the 3 bytes starting there.
+    /// Bytes past the instruction's length are ignored.
+    pub fn decode(addr: u16, bytes: [u8; 3]) -> Self {
+        Self {
+            addr,
+            bytes,
+            instr: OPCODES[bytes[0] as usize],
+        }
+    }
+
+    pub fn addr(&self) -> u16 {
+        self.addr
+    }
+
+    /// The 1 to 3 bytes making up the instruction.
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes[..self.instr.len() as usize]
+    }
+
+    /// The address of the next instruction in memory.
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.instr.len())
+    }
+
+    /// Where a jump, call or RST goes, if it's known without running it.
+    pub fn target(&self) -> Option<u16> {
+        match self.instr {
+            Instr::Jr | Instr::JrCond(_) => Some(self.relative_target()),
+            Instr::JpU16 | Instr::JpCond(_) | Instr::CallU16 | Instr::CallCond(_) => {
+                Some(self.u16())
+            }
+            Instr::Rst(dest) => Some(dest as u16),
+            _ => None,
+        }
+    }
+
+    /// Whether the instruction returns to the next one, i.e. a CALL or RST.
+    pub fn is_call(&self) -> bool {
+        matches!(
+            self.instr,
+            Instr::CallU16 | Instr::CallCond(_) | Instr::Rst(_)
+        )
+    }
+
+    /// Whether the instruction may return from a call, i.e. any RET or RETI.
+    pub fn is_return(&self) -> bool {
+        matches!(self.instr, Instr::Ret | Instr::Reti | Instr::RetCond(_))
+    }
+
+    /// Formats like `Display`, but names jump targets and memory operands that
+    /// `labels` knows, e.g. `CALL Main` or `LD A, [wCounter]`.
+    pub fn labelled<'a>(&'a self, labels: &'a dyn Fn(u16) -> Option<&'a str>) -> Labelled<'a> {
+        Labelled {
+            instr: self,
+            labels,
+        }
+    }
+
+    fn u8(&self) -> u8 {
+        self.bytes[1]
+    }
+
+    fn u16(&self) -> u16 {
+        u16::from_le_bytes([self.bytes[1], self.bytes[2]])
+    }
+
+    fn relative_target(&self) -> u16 {
+        self.next_addr().wrapping_add(self.bytes[1] as i8 as u16)
+    }
+
+    fn fmt_with<'a>(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        labels: &dyn Fn(u16) -> Option<&'a str>,
+    ) -> fmt::Result {
+        let addr = |addr: u16| Addr {
+            addr,
+            label: labels(addr),
+        };
+        match self.instr {
+            Instr::Nop => write!(f, "NOP"),
+            Instr::LdU16pSp => write!(f, "LD [{}], SP", addr(self.u16())),
+            Instr::Stop => write!(f, "STOP"),
+            Instr::Jr => write!(f, "JR {}", addr(self.relative_target())),
+            Instr::JrCond(cond) => write!(f, "JR {}, {}", cond, addr(self.relative_target())),
+            Instr::LdR16U16(r) => write!(f, "LD {}, ${:04X}", r, self.u16()),
+            Instr::AddHlR16(r) => write!(f, "ADD HL, {}", r),
+            Instr::LdR16pA(r) => write!(f, "LD {}, A", r),
+            Instr::LdAR16p(r) => write!(f, "LD A, {}", r),
+            Instr::IncR16(r) => write!(f, "INC {}", r),
+            Instr::DecR16(r) => write!(f, "DEC {}", r),
+            Instr::IncR8(r) => write!(f, "INC {}", r),
+            Instr::DecR8(r) => write!(f, "DEC {}", r),
+            Instr::LdR8U8(r) => write!(f, "LD {}, ${:02X}", r, self.u8()),
+            Instr::Acc(op) => write!(f, "{}", op),
+            Instr::Halt => write!(f, "HALT"),
+            Instr::LdR8R8(dst, src) => write!(f, "LD {}, {}", dst, src),
+            Instr::AluR8(op, r) => write!(f, "{} A, {}", op, r),
+            Instr::RetCond(cond) => write!(f, "RET {}", cond),
+            Instr::LdhU8A => write!(f, "LDH [{}], A", addr(0xFF00 | self.u8() as u16)),
+            Instr::AddSpI8 => write!(f, "ADD SP, {}", Offset(self.u8() as i8 as i16)),
+            Instr::LdhAU8 => write!(f, "LDH A, [{}]", addr(0xFF00 | self.u8() as u16)),
+            Instr::LdHlSpI8 => match self.u8() as i8 {
+                offset if offset < 0 => write!(f, "LD HL, SP - {}", Offset(-(offset as i16))),
+                offset => write!(f, "LD HL, SP + {}", Offset(offset as i16)),
+            },
+            Instr::Pop(r) => write!(f, "POP {}", r),
+            Instr::Ret => write!(f, "RET"),
+            Instr::Reti => write!(f, "RETI"),
+            Instr::JpHl => write!(f, "JP HL"),
+            Instr::LdSpHl => write!(f, "LD SP, HL"),
+            Instr::JpCond(cond) => write!(f, "JP {}, {}", cond, addr(self.u16())),
+            Instr::LdhCA => write!(f, "LDH [C], A"),
+            Instr::LdU16pA => write!(f, "LD [{}], A", addr(self.u16())),
+            Instr::LdhAC => write!(f, "LDH A, [C]"),
+            Instr::LdAU16p => write!(f, "LD A, [{}]", addr(self.u16())),
+            Instr::JpU16 => write!(f, "JP {}", addr(self.u16())),
+            Instr::Prefix => match CB_OPCODES[self.u8() as usize] {
+                CbInstr::Shift(op, r) => write!(f, "{} {}", op, r),
+                CbInstr::Bit(bit, r) => write!(f, "BIT {}, {}", bit, r),
+                CbInstr::Res(bit, r) => write!(f, "RES {}, {}", bit, r),
+                CbInstr::Set(bit, r) => write!(f, "SET {}, {}", bit, r),
+            },
+            Instr::Di => write!(f, "DI"),
+            Instr::Ei => write!(f, "EI"),
+            Instr::CallCond(cond) => write!(f, "CALL {}, {}", cond, addr(self.u16())),
+            Instr::Push(r) => write!(f, "PUSH {}", r),
+            Instr::CallU16 => write!(f, "CALL {}", addr(self.u16())),
+            Instr::AluU8(op) => write!(f, "{} A, ${:02X}", op, self.u8()),
+            Instr::Rst(dest) => write!(f, "RST ${:02X}", dest),
+            Instr::Illegal => write!(f, "DB ${:02X}", self.bytes[0]),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &|_| None)
+    }
+}
+
+/// An instruction formatted with labels, see [`Instruction::labelled`].
+pub struct Labelled<'a> {
+    instr: &'a Instruction,
+    labels: &'a dyn Fn(u16) -> Option<&'a str>,
+}
+
+impl fmt::Display for Labelled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.instr.fmt_with(f, self.labels)
+    }
+}
+
+/// A signed immediate, e.g. `-$02`.
+struct Offset(i16);
+
+impl fmt::Display for Offset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 < 0 {
+            write!(f, "-${:02X}", -self.0)
+        } else {
+            write!(f, "${:02X}", self.0)
+        }
+    }
+}
+
+/// An address operand, named by its label or as an IO register if it has either.
+struct Addr<'a> {
+    addr: u16,
+    label: Option<&'a str>,
+}
+
+impl fmt::Display for Addr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.label.or_else(|| io_name(self.addr)) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "${:04X}", self.addr),
+        }
+    }
+}
+
+/// The `hardware.inc` name of the IO register at `addr`.
+pub fn io_name(addr: u16) -> Option<&'static str> {
+    let name = match addr {
+        0xFF00 => "rP1",
+        0xFF01 => "rSB",
+        0xFF02 => "rSC",
+        0xFF04 => "rDIV",
+        0xFF05 => "rTIMA",
+        0xFF06 => "rTMA",
+        0xFF07 => "rTAC",
+        0xFF0F => "rIF",
+        0xFF10 => "rNR10",
+        0xFF11 => "rNR11",
+        0xFF12 => "rNR12",
+        0xFF13 => "rNR13",
+        0xFF14 => "rNR14",
+        0xFF16 => "rNR21",
+        0xFF17 => "rNR22",
+        0xFF18 => "rNR23",
+        0xFF19 => "rNR24",
+        0xFF1A => "rNR30",
+        0xFF1B => "rNR31",
+        0xFF1C => "rNR32",
+        0xFF1D => "rNR33",
+        0xFF1E => "rNR34",
+        0xFF20 => "rNR41",
+        0xFF21 => "rNR42",
+        0xFF22 => "rNR43",
+        0xFF23 => "rNR44",
+        0xFF24 => "rNR50",
+        0xFF25 => "rNR51",
+        0xFF26 => "rNR52",
+        0xFF40 => "rLCDC",
+        0xFF41 => "rSTAT",
+        0xFF42 => "rSCY",
+        0xFF43 => "rSCX",
+        0xFF44 => "rLY",
+        0xFF45 => "rLYC",
+        0xFF46 => "rDMA",
+        0xFF47 => "rBGP",
+        0xFF48 => "rOBP0",
+        0xFF49 => "rOBP1",
+        0xFF4A => "rWY",
+        0xFF4B => "rWX",
+        0xFF4D => "rKEY1",
+        0xFF4F => "rVBK",
+        0xFF51 => "rHDMA1",
+        0xFF52 => "rHDMA2",
+        0xFF53 => "rHDMA3",
+        0xFF54 => "rHDMA4",
+        0xFF55 => "rHDMA5",
+        0xFF56 => "rRP",
+        0xFF68 => "rBCPS",
+        0xFF69 => "rBCPD",
+        0xFF6A => "rOCPS",
+        0xFF6B => "rOCPD",
+        0xFF70 => "rSVBK",
+        0xFFFF => "rIE",
+        _ => return None,
+    };
+    Some(name)
+}
+
+impl fmt::Display for R8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            R8::B => "B",
+            R8::C => "C",
+            R8::D => "D",
+            R8::E => "E",
+            R8::H => "H",
+            R8::L => "L",
+            R8::HlInd => "[HL]",
+            R8::A => "A",
+        })
+    }
+}
+
+impl fmt::Display for R16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            R16::Bc => "BC",
+            R16::De => "DE",
+            R16::Hl => "HL",
+            R16::Sp => "SP",
+        })
+    }
+}
+
+impl fmt::Display for R16Stack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            R16Stack::Bc => "BC",
+            R16Stack::De => "DE",
+            R16Stack::Hl => "HL",
+            R16Stack::Af => "AF",
+        })
+    }
+}
+
+impl fmt::Display for R16Mem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            R16Mem::Bc => "[BC]",
+            R16Mem::De => "[DE]",
+            R16Mem::HlInc => "[HL+]",
+            R16Mem::HlDec => "[HL-]",
+        })
+    }
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cond::Nz => "NZ",
+            Cond::Z => "Z",
+            Cond::Nc => "NC",
+            Cond::C => "C",
+        })
+    }
+}
+
+impl fmt::Display for AluOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AluOp::Add => "ADD",
+            AluOp::Adc => "ADC",
+            AluOp::Sub => "SUB",
+            AluOp::Sbc => "SBC",
+            AluOp::And => "AND",
+            AluOp::Xor => "XOR",
+            AluOp::Or => "OR",
+            AluOp::Cp => "CP",
+        })
+    }
+}
+
+impl fmt::Display for ShiftOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ShiftOp::Rlc => "RLC",
+            ShiftOp::Rrc => "RRC",
+            ShiftOp::Rl => "RL",
+            ShiftOp::Rr => "RR",
+            ShiftOp::Sla => "SLA",
+            ShiftOp::Sra => "SRA",
+            ShiftOp::Swap => "SWAP",
+            ShiftOp::Srl => "SRL",
+        })
+    }
+}
+
+impl fmt::Display for AccOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AccOp::Rlca => "RLCA",
+            AccOp::Rrca => "RRCA",
+            AccOp::Rla => "RLA",
+            AccOp::Rra => "RRA",
+            AccOp::Daa => "DAA",
+            AccOp::Cpl => "CPL",
+            AccOp::Scf => "SCF",
+            AccOp::Ccf => "CCF",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::{String, ToString};
+
+    fn text(addr: u16, bytes: &[u8]) -> String {
+        let mut padded = [0; 3];
+        padded[..bytes.len()].copy_from_slice(bytes);
+        let instr = Instruction::decode(addr, padded);
+        assert_eq!(instr.bytes(), bytes);
+        instr.to_string()
+    }
+
+    #[test]
+    fn formats_like_rgbds() {
+        assert_eq!(text(0, &[0x00]), "NOP");
+        assert_eq!(text(0, &[0x01, 0x34, 0x12]), "LD BC, $1234");
+        assert_eq!(text(0, &[0x08, 0x00, 0xC0]), "LD [$C000], SP");
+        assert_eq!(text(0, &[0x10, 0x00]), "STOP");
+        assert_eq!(text(0, &[0x22]), "LD [HL+], A");
+        assert_eq!(text(0, &[0x3A]), "LD A, [HL-]");
+        assert_eq!(text(0, &[0x36, 0x42]), "LD [HL], $42");
+        assert_eq!(text(0, &[0x2F]), "CPL");
+        assert_eq!(text(0, &[0x76]), "HALT");
+        assert_eq!(text(0, &[0x78]), "LD A, B");
+        assert_eq!(text(0, &[0x8E]), "ADC A, [HL]");
+        assert_eq!(text(0, &[0xFE, 0x90]), "CP A, $90");
+        assert_eq!(text(0, &[0xF1]), "POP AF");
+        assert_eq!(text(0, &[0xE2]), "LDH [C], A");
+        assert_eq!(text(0, &[0xFA, 0x00, 0xD0]), "LD A, [$D000]");
+        assert_eq!(text(0, &[0xE8, 0xFE]), "ADD SP, -$02");
+        assert_eq!(text(0, &[0xF8, 0x05]), "LD HL, SP + $05");
+        assert_eq!(text(0, &[0xF8, 0x80]), "LD HL, SP - $80");
+        assert_eq!(text(0, &[0xD8]), "RET C");
+        assert_eq!(text(0, &[0xEF]), "RST $28");
+        assert_eq!(text(0, &[0xDD]), "DB $DD");
+    }
+
+    #[test]
+    fn formats_cb_instructions() {
+        assert_eq!(text(0, &[0xCB, 0x11]), "RL C");
+        assert_eq!(text(0, &[0xCB, 0x37]), "SWAP A");
+        assert_eq!(text(0, &[0xCB, 0x7E]), "BIT 7, [HL]");
+        assert_eq!(text(0, &[0xCB, 0x80]), "RES 0, B");
+        assert_eq!(text(0, &[0xCB, 0xDF]), "SET 3, A");
+    }
+
+    #[test]
+    fn names_io_registers() {
+        assert_eq!(text(0, &[0xE0, 0x40]), "LDH [rLCDC], A");
+        assert_eq!(text(0, &[0xF0, 0x44]), "LDH A, [rLY]");
+        assert_eq!(text(0, &[0xEA, 0xFF, 0xFF]), "LD [rIE], A");
+        // Unused IO addresses and HRAM have no name
+        assert_eq!(text(0, &[0xE0, 0x03]), "LDH [$FF03], A");
+        assert_eq!(text(0, &[0xF0, 0x80]), "LDH A, [$FF80]");
+    }
+
+    #[test]
+    fn resolves_jump_targets() {
+        assert_eq!(text(0x0150, &[0x18, 0xFE]), "JR $0150");
+        assert_eq!(text(0x0150, &[0x20, 0x10]), "JR NZ, $0162");
+        assert_eq!(text(0x0150, &[0xC3, 0x00, 0x40]), "JP $4000");
+        assert_eq!(text(0x0150, &[0xCC, 0x34, 0x12]), "CALL Z, $1234");
+
+        let jr = Instruction::decode(0x0150, [0x18, 0xFE, 0x00]);
+        assert_eq!(jr.target(), Some(0x0150));
+        assert_eq!(jr.next_addr(), 0x0152);
+        assert!(!jr.is_call());
+
+        let call = Instruction::decode(0x0150, [0xCD, 0x00, 0x40]);
+        assert_eq!(call.target(), Some(0x4000));
+        assert_eq!(call.next_addr(), 0x0153);
+        assert!(call.is_call());
+
+        let rst = Instruction::decode(0x0150, [0xFF, 0x00, 0x00]);
+        assert_eq!(rst.target(), Some(0x0038));
+        assert!(rst.is_call());
+
+        let ret = Instruction::decode(0x0150, [0xC0, 0x00, 0x00]);
+        assert_eq!(ret.target(), None);
+        assert!(ret.is_return());
+        assert!(!Instruction::decode(0x0150, [0xE9, 0, 0]).is_return());
+    }
+
+    #[test]
+    fn wraps_around_the_address_space() {
+        assert_eq!(text(0xFFFF, &[0x18, 0x00]), "JR $0001");
+        assert_eq!(Instruction::decode(0xFFFF, [0x00; 3]).next_addr(), 0x0000);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+/// OAM DMA. After a write to 0xFF46, one byte per M-cycle is copied from
+/// `source << 8` into OAM, starting one M-cycle later.
+pub struct Dma {
+    source: u8,
+    state: DmaState,
+}
+
+#[derive(Copy, Clone)]
+enum DmaState {
+    Idle,
+    Starting,
+    Active(u8),
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        Self {
+            source: 0,
+            state: DmaState::Idle,
+        }
+    }
+
+    pub fn start(&mut self, source: u8) {
+        self.source = source;
+        self.state = DmaState::Starting;
+    }
+
+    pub fn source(&self) -> u8 {
+        self.source
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self.state, DmaState::Active(_))
+    }
+
+    /// Advances the transfer by one M-cycle, returns the source address and OAM
+    /// index of the byte to copy during it.
+    pub fn tick(&mut self) -> Option<(u16, usize)> {
+        match self.state {
+            DmaState::Idle => None,
+            DmaState::Starting => {
+                self.state = DmaState::Active(0);
+                None
+            }
+            DmaState::Active(idx) => {
+                self.state = if idx == 0x9F {
+                    DmaState::Idle
+                } else {
+                    DmaState::Active(idx + 1)
+                };
+                Some((((self.source as u16) << 8) + idx as u16, idx as usize))
+            }
+        }
+    }
+}
+
+impl Snapshot for Dma {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_u8(self.source);
+        match self.state {
+            DmaState::Idle => w.write_bytes(&[0, 0]),
+            DmaState::Starting => w.write_bytes(&[1, 0]),
+            DmaState::Active(idx) => w.write_bytes(&[2, idx]),
+        }
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.source = r.read_u8()?;
+        let tag = r.read_u8()?;
+        let idx = r.read_u8()?;
+        self.state = match tag {
+            0 => DmaState::Idle,
+            1 => DmaState::Starting,
+            2 if idx < 0xA0 => DmaState::Active(idx),
+            _ => return Err(StateError::Invalid),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_a_byte_per_cycle_after_a_delay() {
+        let mut dma = Dma::new();
+        dma.start(0xC1);
+        assert_eq!(dma.source(), 0xC1);
+        assert!(!dma.is_active());
+        assert_eq!(dma.tick(), None);
+
+        for idx in 0..0xA0 {
+            assert!(dma.is_active());
+            assert_eq!(dma.tick(), Some((0xC100 + idx as u16, idx)));
+        }
+        assert!(!dma.is_active());
+        assert_eq!(dma.tick(), None);
+    }
+
+    #[test]
+    fn restarting_begins_again() {
+        let mut dma = Dma::new();
+        dma.start(0xC0);
+        for _ in 0..0x10 {
+            dma.tick();
+        }
+        dma.start(0xD0);
+        assert_eq!(dma.tick(), None);
+        assert_eq!(dma.tick(), Some((0xD000, 0)));
+    }
+}
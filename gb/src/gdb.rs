@@ -0,0 +1,744 @@
+//! A GDB remote serial protocol stub for the emulated CPU.
+//!
+//! [`GdbStub`] is fed the bytes GDB sends and writes its replies to any
+//! `fmt::Write`, so the same stub serves a TCP socket on the host and USART1
+//! on the board. Like the [`console`](crate::console), it doesn't run the
+//! machine by itself: while [`GdbStub::is_running`], front ends run the
+//! [`Debugger`] and pass every [`DebugEvent`] to [`GdbStub::report`].
+//!
+//! GDB has no SM83 target, but its Z80 one shares the register layout the stub
+//! uses: AF, BC, DE, HL, SP and PC, 16 bits each. The Z80-only registers that
+//! follow are left out, which GDB shows as unavailable. Addresses are the CPU's
+//! 16-bit view of memory, the currently mapped ROM bank included.
+//!
+//! GDB can share a serial line with a console: [`GdbStub::claims`] tells which
+//! received bytes belong to the stub.
+//!
+//! Supported are reading and writing registers and memory, continuing,
+//! single-stepping, interrupting with Ctrl-C, breakpoints (`Z0`, `Z1`) and
+//! write, read and access watchpoints (`Z2` to `Z4`).
+
+use crate::console::Console;
+use crate::cpu::Registers;
+use crate::debugger::{Access, Breakpoint, DebugEvent, Debugger, Reg, Watchpoint};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+/// The largest packet accepted, in bytes, as announced to GDB.
+const PACKET_SIZE: usize = 0x400;
+
+/// Signals sent in stop replies.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+/// Where the receiver is within a packet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Rx {
+    /// Between packets, acks and interrupts arrive here.
+    Idle,
+    Data,
+    /// Expecting the checksum's first or second hex digit.
+    Checksum(u8),
+}
+
+/// A breakpoint or watchpoint GDB inserted, by the `Z` packet that inserted it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Inserted {
+    kind: u8,
+    addr: u16,
+    len: u16,
+    id: u32,
+}
+
+pub struct GdbStub {
+    rx: Rx,
+    packet: Vec<u8>,
+    checksum: u8,
+    /// GDB asked for QStartNoAckMode, packets aren't acknowledged anymore.
+    no_ack: bool,
+    attached: bool,
+    running: bool,
+    /// The reply to `?`.
+    last_stop: String,
+    inserted: Vec<Inserted>,
+}
+
+impl Default for GdbStub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GdbStub {
+    pub fn new() -> Self {
+        Self {
+            rx: Rx::Idle,
+            packet: Vec::new(),
+            checksum: 0,
+            no_ack: false,
+            attached: false,
+            running: false,
+            last_stop: stop_signal(SIGTRAP),
+            inserted: Vec::new(),
+        }
+    }
+
+    /// Whether GDB is connected, from its first packet until it detaches or kills.
+    pub fn is_attached(&self) -> bool {
+        self.attached
+    }
+
+    /// Whether GDB let the machine continue.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Whether a packet has started arriving and isn't complete yet.
+    pub fn is_receiving(&self) -> bool {
+        self.rx != Rx::Idle
+    }
+
+    /// Whether `byte`, received on a line shared with `console`, is meant for
+    /// the stub: everything while GDB is attached or a packet is arriving, and
+    /// the `$` that starts a packet unless it's part of a console command.
+    pub fn claims(&self, byte: u8, console: &Console) -> bool {
+        self.attached || self.is_receiving() || (byte == b'$' && !console.has_input())
+    }
+
+    /// Takes one byte received from GDB. A complete packet stops the machine if
+    /// GDB only just attached, and is answered right away.
+    pub fn input(&mut self, byte: u8, debugger: &mut Debugger, out: &mut dyn Write) -> fmt::Result {
+        match self.rx {
+            Rx::Idle => match byte {
+                b'$' => {
+                    self.packet.clear();
+                    self.rx = Rx::Data;
+                }
+                // Ctrl-C
+                0x03 if self.running => {
+                    debugger.cancel_step();
+                    self.running = false;
+                    self.last_stop = stop_signal(SIGINT);
+                    let stop = self.last_stop.clone();
+                    send(&stop, out)?;
+                }
+                // Acks, retransmissions aren't supported
+                _ => {}
+            },
+            Rx::Data => match byte {
+                b'#' => self.rx = Rx::Checksum(0),
+                _ if self.packet.len() < PACKET_SIZE => self.packet.push(byte),
+                // Too long, fails the checksum
+                _ => {}
+            },
+            Rx::Checksum(digit) => {
+                let val = match (byte as char).to_digit(16) {
+                    Some(val) => val as u8,
+                    None => {
+                        self.rx = Rx::Idle;
+                        return self.nak(out);
+                    }
+                };
+                if digit == 0 {
+                    self.checksum = val << 4;
+                    self.rx = Rx::Checksum(1);
+                    return Ok(());
+                }
+                self.rx = Rx::Idle;
+                let sum = self.packet.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+                if sum != self.checksum | val {
+                    return self.nak(out);
+                }
+                if !self.no_ack {
+                    out.write_char('+')?;
+                }
+
+                let packet = core::mem::take(&mut self.packet);
+                let result = match core::str::from_utf8(&packet) {
+                    Ok(packet) => self.handle(packet, debugger, out),
+                    Err(_) => send("", out),
+                };
+                self.packet = packet;
+                return result;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells GDB why the machine stopped, if it did. Serial output is forwarded
+    /// to GDB's console.
+    pub fn report(&mut self, event: DebugEvent, out: &mut dyn Write) -> fmt::Result {
+        let stop = match event {
+            DebugEvent::Completed | DebugEvent::FrameReady => return Ok(()),
+            DebugEvent::SerialByte(val) => {
+                let mut output = String::from("O");
+                push_hex(&mut output, &[val]);
+                return send(&output, out);
+            }
+            DebugEvent::Stepped | DebugEvent::Breakpoint(_) | DebugEvent::SoftwareBreakpoint(_) => {
+                stop_signal(SIGTRAP)
+            }
+            DebugEvent::LockedUp => stop_signal(SIGILL),
+            DebugEvent::Watchpoint { id, hit } => {
+                let access = self
+                    .inserted
+                    .iter()
+                    .find(|ins| ins.id == id)
+                    .map(|ins| ins.kind);
+                let reason = match (access, hit.access) {
+                    (Some(4), _) => "awatch",
+                    (_, Access::Read) => "rwatch",
+                    _ => "watch",
+                };
+                let mut stop = String::new();
+                let _ = write!(stop, "T{:02x}{}:{:04x};", SIGTRAP, reason, hit.addr);
+                stop
+            }
+        };
+        self.running = false;
+        self.last_stop = stop;
+        let stop = self.last_stop.clone();
+        send(&stop, out)
+    }
+
+    fn handle(
+        &mut self,
+        packet: &str,
+        debugger: &mut Debugger,
+        out: &mut dyn Write,
+    ) -> fmt::Result {
+        if !self.attached {
+            self.attached = true;
+            self.running = false;
+        }
+
+        let (command, args) = match packet.char_indices().nth(1) {
+            Some((at, _)) => packet.split_at(at),
+            None => (packet, ""),
+        };
+        let reply = match command {
+            "?" => self.last_stop.clone(),
+            "g" => {
+                let mut reply = String::new();
+                for val in registers(&debugger.registers()) {
+                    push_hex(&mut reply, &val.to_le_bytes());
+                }
+                reply
+            }
+            "G" => {
+                let mut vals = [0; 6];
+                for (idx, val) in vals.iter_mut().enumerate() {
+                    match args.get(idx * 4..idx * 4 + 4).and_then(parse_le_u16) {
+                        Some(parsed) => *val = parsed,
+                        None => return send("E01", out),
+                    }
+                }
+                let mut regs = debugger.registers();
+                set_registers(&mut regs, vals);
+                debugger.set_registers(&regs);
+                "OK".into()
+            }
+            "p" => {
+                let regs = registers(&debugger.registers());
+                match parse_hex(args).and_then(|reg| regs.get(reg as usize)) {
+                    Some(val) => {
+                        let mut reply = String::new();
+                        push_hex(&mut reply, &val.to_le_bytes());
+                        reply
+                    }
+                    None => "E01".into(),
+                }
+            }
+            "P" => {
+                let parsed = args.split_once('=').and_then(|(reg, val)| {
+                    Some((parse_hex(reg).filter(|&reg| reg < 6)?, parse_le_u16(val)?))
+                });
+                match parsed {
+                    Some((reg, val)) => {
+                        let mut regs = debugger.registers();
+                        let mut vals = registers(&regs);
+                        vals[reg as usize] = val;
+                        set_registers(&mut regs, vals);
+                        debugger.set_registers(&regs);
+                        "OK".into()
+                    }
+                    None => "E01".into(),
+                }
+            }
+            "m" => match parse_range(args) {
+                // Each byte takes two characters in the reply, and an empty
+                // one would say `m` isn't supported
+                Some((addr, len)) if len > 0 && (len as usize) * 2 <= PACKET_SIZE => {
+                    let mut reply = String::new();
+                    for offset in 0..len {
+                        let val = debugger.peek(addr.wrapping_add(offset));
+                        push_hex(&mut reply, &[val]);
+                    }
+                    reply
+                }
+                _ => "E01".into(),
+            },
+            "M" => {
+                let parsed = args.split_once(':').and_then(|(range, data)| {
+                    let (addr, len) = parse_range(range)?;
+                    (data.len() == len as usize * 2).then_some((addr, data))
+                });
+                match parsed {
+                    Some((addr, data)) => {
+                        for (offset, byte) in data.as_bytes().chunks(2).enumerate() {
+                            let val = core::str::from_utf8(byte)
+                                .ok()
+                                .and_then(|byte| u8::from_str_radix(byte, 16).ok());
+                            match val {
+                                Some(val) => debugger.poke(addr.wrapping_add(offset as u16), val),
+                                None => return send("E01", out),
+                            }
+                        }
+                        "OK".into()
+                    }
+                    None => "E01".into(),
+                }
+            }
+            "c" => {
+                if let Some(addr) = parse_hex(args) {
+                    debugger.set_register(Reg::Pc, addr);
+                }
+                debugger.cancel_step();
+                self.running = true;
+                return Ok(());
+            }
+            "s" => {
+                if let Some(addr) = parse_hex(args) {
+                    debugger.set_register(Reg::Pc, addr);
+                }
+                // Runs like `c`, the stop reply comes once the step is done
+                debugger.step_into();
+                self.running = true;
+                return Ok(());
+            }
+            "Z" | "z" => match parse_point(args) {
+                Some((kind, addr, len)) if command == "Z" => self.insert(kind, addr, len, debugger),
+                Some((kind, addr, len)) => self.remove(kind, addr, len, debugger),
+                None => "E01".into(),
+            },
+            "D" => {
+                self.detach(debugger);
+                "OK".into()
+            }
+            "k" => {
+                self.detach(debugger);
+                return Ok(());
+            }
+            "H" => "OK".into(),
+            "q" if args.starts_with("Supported") => {
+                let mut reply = String::new();
+                let _ = write!(reply, "PacketSize={:x};QStartNoAckMode+", PACKET_SIZE);
+                reply
+            }
+            "q" if args == "Attached" => "1".into(),
+            "Q" if args == "StartNoAckMode" => {
+                self.no_ack = true;
+                "OK".into()
+            }
+            // Anything else isn't supported, which an empty reply says
+            _ => String::new(),
+        };
+        send(&reply, out)
+    }
+
+    fn insert(&mut self, kind: u8, addr: u16, len: u16, debugger: &mut Debugger) -> String {
+        let end = addr.wrapping_add(len.max(1) - 1);
+        let id = match kind {
+            0 | 1 => debugger.add_breakpoint(Breakpoint::new(addr)),
+            2 => debugger.add_watchpoint(Watchpoint::writes(addr, end)),
+            3 => debugger.add_watchpoint(Watchpoint::reads(addr, end)),
+            4 => debugger.add_watchpoint(Watchpoint::accesses(addr, end)),
+            _ => return String::new(),
+        };
+        self.inserted.push(Inserted {
+            kind,
+            addr,
+            len,
+            id,
+        });
+        "OK".into()
+    }
+
+    fn remove(&mut self, kind: u8, addr: u16, len: u16, debugger: &mut Debugger) -> String {
+        let idx = self
+            .inserted
+            .iter()
+            .position(|ins| ins.kind == kind && ins.addr == addr && ins.len == len);
+        match idx {
+            Some(idx) => {
+                let removed = self.inserted.remove(idx);
+                match kind {
+                    0 | 1 => debugger.remove_breakpoint(removed.id),
+                    _ => debugger.remove_watchpoint(removed.id),
+                };
+                "OK".into()
+            }
+            None => "E01".into(),
+        }
+    }
+
+    /// Asks GDB to resend a garbled packet.
+    fn nak(&self, out: &mut dyn Write) -> fmt::Result {
+        if self.no_ack {
+            Ok(())
+        } else {
+            out.write_char('-')
+        }
+    }
+
+    /// Removes everything GDB inserted and lets the machine run on.
+    fn detach(&mut self, debugger: &mut Debugger) {
+        for ins in self.inserted.drain(..) {
+            match ins.kind {
+                0 | 1 => debugger.remove_breakpoint(ins.id),
+                _ => debugger.remove_watchpoint(ins.id),
+            };
+        }
+        debugger.cancel_step();
+        self.attached = false;
+        self.running = true;
+        self.no_ack = false;
+        self.last_stop = stop_signal(SIGTRAP);
+    }
+}
+
+/// Wraps `data` into a packet. Replies never contain characters that need escaping.
+fn send(data: &str, out: &mut dyn Write) -> fmt::Result {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    write!(out, "${}#{:02x}", data, checksum)
+}
+
+fn stop_signal(signal: u8) -> String {
+    let mut stop = String::new();
+    let _ = write!(stop, "S{:02x}", signal);
+    stop
+}
+
+/// AF, BC, DE, HL, SP and PC in GDB's register order.
+fn registers(r: &Registers) -> [u16; 6] {
+    let pair = |hi: u8, lo: u8| u16::from_be_bytes([hi, lo]);
+    [
+        pair(r.a, r.f),
+        pair(r.b, r.c),
+        pair(r.d, r.e),
+        pair(r.h, r.l),
+        r.sp,
+        r.pc,
+    ]
+}
+
+fn set_registers(r: &mut Registers, [af, bc, de, hl, sp, pc]: [u16; 6]) {
+    [r.a, r.f] = af.to_be_bytes();
+    [r.b, r.c] = bc.to_be_bytes();
+    [r.d, r.e] = de.to_be_bytes();
+    [r.h, r.l] = hl.to_be_bytes();
+    r.sp = sp;
+    r.pc = pc;
+}
+
+fn push_hex(s: &mut String, bytes: &[u8]) {
+    for byte in bytes {
+        let _ = write!(s, "{:02x}", byte);
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u16> {
+    u16::from_str_radix(s, 16).ok()
+}
+
+/// Registers are sent as little-endian bytes.
+fn parse_le_u16(s: &str) -> Option<u16> {
+    let val = parse_hex(s.get(..4)?)?;
+    Some(val.swap_bytes())
+}
+
+/// Parses `ADDR,LEN`.
+fn parse_range(s: &str) -> Option<(u16, u16)> {
+    let (addr, len) = s.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+/// Parses the `TYPE,ADDR,KIND` of `Z` and `z` packets.
+fn parse_point(s: &str) -> Option<(u8, u16, u16)> {
+    let (kind, range) = s.split_once(',')?;
+    let (addr, len) = parse_range(range)?;
+    Some((kind.parse().ok()?, addr, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use alloc::format;
+
+    fn packet(data: &str) -> String {
+        let mut packet = String::new();
+        send(data, &mut packet).unwrap();
+        packet
+    }
+
+    /// A serial line shared by a console and GDB, routed like the firmware does.
+    struct Line {
+        debugger: Debugger,
+        gdb: GdbStub,
+        console: Console,
+        gdb_out: String,
+        console_out: String,
+    }
+
+    impl Line {
+        fn new() -> Self {
+            let mut console = Console::new();
+            console.set_running(true);
+            Self {
+                debugger: Debugger::new(testing::gameboy(&[])),
+                gdb: GdbStub::new(),
+                console,
+                gdb_out: String::new(),
+                console_out: String::new(),
+            }
+        }
+
+        fn receive(&mut self, bytes: &str) {
+            for byte in bytes.bytes() {
+                if self.gdb.claims(byte, &self.console) {
+                    self.gdb
+                        .input(byte, &mut self.debugger, &mut self.gdb_out)
+                        .unwrap();
+                } else {
+                    self.console
+                        .input(byte, &mut self.debugger, &mut self.console_out)
+                        .unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn whole_packets_reach_the_stub() {
+        let mut line = Line::new();
+        line.receive(&packet("?"));
+        assert!(line.gdb.is_attached());
+        assert!(!line.gdb.is_running());
+        assert_eq!(line.gdb_out, format!("+{}", packet("S05")));
+        assert_eq!(line.console_out, "");
+
+        // Everything goes to GDB until it detaches
+        line.gdb_out.clear();
+        line.receive(&format!("+{}", packet("D")));
+        assert!(!line.gdb.is_attached());
+        assert_eq!(line.gdb_out, format!("+{}", packet("OK")));
+        line.receive("r");
+        assert_eq!(line.console_out, "r");
+    }
+
+    #[test]
+    fn dollars_in_console_commands_stay_there() {
+        let mut line = Line::new();
+        line.receive("x $C000");
+        assert!(!line.gdb.is_receiving());
+        assert_eq!(line.console_out, "x $C000");
+        assert_eq!(line.gdb_out, "");
+
+        line.receive("\r");
+        line.receive(&packet("g"));
+        assert!(line.gdb.is_attached());
+    }
+
+    #[test]
+    fn receiving_lasts_until_the_checksum() {
+        let mut line = Line::new();
+        let packet = packet("g");
+        let (start, checksum) = packet.split_at(packet.len() - 1);
+        line.receive(start);
+        assert!(line.gdb.is_receiving());
+        line.receive(checksum);
+        assert!(!line.gdb.is_receiving());
+        assert!(line.gdb.is_attached());
+    }
+
+    /// Sends `data` as a packet and returns the reply, without the ack.
+    fn request(gdb: &mut GdbStub, debugger: &mut Debugger, data: &str) -> String {
+        let mut out = String::new();
+        for byte in packet(data).bytes() {
+            gdb.input(byte, debugger, &mut out).unwrap();
+        }
+        match out.strip_prefix('+') {
+            Some(reply) => reply.into(),
+            None => out,
+        }
+    }
+
+    fn stub() -> (GdbStub, Debugger) {
+        (GdbStub::new(), Debugger::new(testing::gameboy(&[])))
+    }
+
+    #[test]
+    fn packets_are_checksummed() {
+        assert_eq!(packet("OK"), "$OK#9a");
+        assert_eq!(packet(""), "$#00");
+
+        let (mut gdb, mut debugger) = stub();
+        let mut out = String::new();
+        for byte in "$?#00$?#3g".bytes() {
+            gdb.input(byte, &mut debugger, &mut out).unwrap();
+        }
+        // Neither a wrong nor a garbled checksum gets an answer
+        assert_eq!(out, "--");
+        assert!(!gdb.is_attached());
+
+        out.clear();
+        for byte in "$?#3F".bytes() {
+            gdb.input(byte, &mut debugger, &mut out).unwrap();
+        }
+        assert_eq!(out, format!("+{}", packet("S05")));
+    }
+
+    #[test]
+    fn no_ack_mode_stops_acks() {
+        let (mut gdb, mut debugger) = stub();
+        assert_eq!(
+            request(&mut gdb, &mut debugger, "QStartNoAckMode"),
+            packet("OK")
+        );
+        let mut out = String::new();
+        for byte in packet("?").bytes().chain("$?#00".bytes()) {
+            gdb.input(byte, &mut debugger, &mut out).unwrap();
+        }
+        assert_eq!(out, packet("S05"));
+    }
+
+    #[test]
+    fn announces_features() {
+        let (mut gdb, mut debugger) = stub();
+        assert_eq!(
+            request(&mut gdb, &mut debugger, "qSupported:multiprocess+"),
+            packet("PacketSize=400;QStartNoAckMode+")
+        );
+        assert_eq!(request(&mut gdb, &mut debugger, "qAttached"), packet("1"));
+        assert_eq!(
+            request(&mut gdb, &mut debugger, "vMustReplyEmpty"),
+            packet("")
+        );
+    }
+
+    #[test]
+    fn registers_round_trip() {
+        let (mut gdb, mut debugger) = stub();
+        // AF, BC, DE, HL, SP and PC, little-endian
+        let regs = concat!("f012", "7856", "bc9a", "00c0", "feff", "5001");
+        assert_eq!(
+            request(&mut gdb, &mut debugger, &format!("G{}", regs)),
+            packet("OK")
+        );
+        let r = debugger.registers();
+        assert_eq!(
+            (r.a, r.f, r.b, r.c, r.d, r.e),
+            (0x12, 0xF0, 0x56, 0x78, 0x9A, 0xBC)
+        );
+        assert_eq!((r.h, r.l, r.sp, r.pc), (0xC0, 0x00, 0xFFFE, 0x0150));
+        assert_eq!(request(&mut gdb, &mut debugger, "g"), packet(regs));
+
+        assert_eq!(request(&mut gdb, &mut debugger, "p5"), packet("5001"));
+        assert_eq!(request(&mut gdb, &mut debugger, "P3=34c1"), packet("OK"));
+        assert_eq!(debugger.registers().h, 0xC1);
+        assert_eq!(request(&mut gdb, &mut debugger, "p6"), packet("E01"));
+        assert_eq!(request(&mut gdb, &mut debugger, "G1234"), packet("E01"));
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let (mut gdb, mut debugger) = stub();
+        assert_eq!(
+            request(&mut gdb, &mut debugger, "Mc000,3:0102ff"),
+            packet("OK")
+        );
+        assert_eq!(debugger.peek(0xC002), 0xFF);
+        assert_eq!(
+            request(&mut gdb, &mut debugger, "mc000,3"),
+            packet("0102ff")
+        );
+        assert_eq!(request(&mut gdb, &mut debugger, "m100,1"), packet("c3"));
+
+        assert_eq!(request(&mut gdb, &mut debugger, "mc000,0"), packet("E01"));
+        assert_eq!(request(&mut gdb, &mut debugger, "mc000,201"), packet("E01"));
+        assert_eq!(
+            request(&mut gdb, &mut debugger, "Mc000,2:01"),
+            packet("E01")
+        );
+        assert_eq!(
+            request(&mut gdb, &mut debugger, "Mc000,1:zz"),
+            packet("E01")
+        );
+    }
+
+    #[test]
+    fn inserts_and_removes_points() {
+        let (mut gdb, mut debugger) = stub();
+        assert_eq!(request(&mut gdb, &mut debugger, "Z0,150,1"), packet("OK"));
+        assert_eq!(request(&mut gdb, &mut debugger, "Z2,c000,2"), packet("OK"));
+        assert_eq!(debugger.breakpoints().count(), 1);
+        let (_, wp) = debugger.watchpoints().next().unwrap();
+        assert_eq!(*wp, Watchpoint::writes(0xC000, 0xC001));
+
+        // Unsupported kinds get an empty reply
+        assert_eq!(request(&mut gdb, &mut debugger, "Z5,150,1"), packet(""));
+        assert_eq!(request(&mut gdb, &mut debugger, "z0,150,1"), packet("OK"));
+        assert_eq!(request(&mut gdb, &mut debugger, "z0,150,1"), packet("E01"));
+        assert_eq!(debugger.breakpoints().count(), 0);
+
+        // Detaching removes the rest
+        request(&mut gdb, &mut debugger, "D");
+        assert_eq!(debugger.watchpoints().count(), 0);
+    }
+
+    #[test]
+    fn stops_are_reported() {
+        let (mut gdb, mut debugger) = stub();
+        assert_eq!(request(&mut gdb, &mut debugger, "Z0,150,1"), packet("OK"));
+        assert_eq!(request(&mut gdb, &mut debugger, "c"), "");
+        assert!(gdb.is_running());
+
+        let mut out = String::new();
+        let event = debugger.run(u32::MAX);
+        gdb.report(event, &mut out).unwrap();
+        assert_eq!(out, packet("S05"));
+        assert!(!gdb.is_running());
+        assert_eq!(debugger.registers().pc, 0x0150);
+    }
+
+    #[test]
+    fn steps_reply_once_done() {
+        let (mut gdb, mut debugger) = stub();
+        assert_eq!(request(&mut gdb, &mut debugger, "s"), "");
+        assert!(gdb.is_running());
+        assert_eq!(debugger.registers().pc, 0x0100);
+
+        let mut out = String::new();
+        let event = debugger.run(u32::MAX);
+        gdb.report(event, &mut out).unwrap();
+        assert_eq!(out, packet("S05"));
+        assert_eq!(debugger.registers().pc, 0x0150);
+        assert_eq!(request(&mut gdb, &mut debugger, "?"), packet("S05"));
+    }
+
+    #[test]
+    fn ctrl_c_interrupts() {
+        let (mut gdb, mut debugger) = stub();
+        request(&mut gdb, &mut debugger, "s");
+        let mut out = String::new();
+        gdb.input(0x03, &mut debugger, &mut out).unwrap();
+        assert_eq!(out, packet("S02"));
+        assert!(!gdb.is_running());
+        // The step was dropped
+        assert_eq!(debugger.run(100), DebugEvent::Completed);
+        assert_eq!(request(&mut gdb, &mut debugger, "?"), packet("S02"));
+    }
+}
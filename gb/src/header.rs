@@ -0,0 +1,353 @@
+//! The cartridge header at 0x0100..=0x014F.
+//!
+//! Everything a menu or tool wants to know about a ROM without running it. The
+//! loader rejects ROMs whose header checksum or length are wrong, the global
+//! checksum is only checked on request as neither the boot ROM nor any game
+//! looks at it.
+
+use crate::cartridge::CartridgeError;
+use crate::rom::RomSource;
+
+/// Bytes up to the end of the header, shorter ROMs are truncated.
+pub const HEADER_END: usize = 0x0150;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CgbSupport {
+    /// A DMG game.
+    None,
+    /// Runs on both, with CGB enhancements.
+    Enhanced,
+    /// Only runs on a CGB.
+    Only,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Licensee {
+    /// The single byte code at 0x014B.
+    Old(u8),
+    /// The two ASCII characters at 0x0144, used when the old code is 0x33.
+    New([u8; 2]),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    /// 0x0134..=0x0143, the last bytes overlap the manufacturer code and CGB flag.
+    pub raw_title: [u8; 16],
+    pub cgb_flag: u8,
+    pub new_licensee: [u8; 2],
+    pub sgb_flag: u8,
+    pub cartridge_type: u8,
+    pub rom_size: u8,
+    pub ram_size: u8,
+    pub destination: u8,
+    pub old_licensee: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    /// Stored big-endian, unlike everything else.
+    pub global_checksum: u16,
+    /// The header checksum the boot ROM computes over 0x0134..=0x014C.
+    computed_checksum: u8,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &mut dyn RomSource) -> Result<Self, CartridgeError> {
+        if rom.len() < HEADER_END {
+            return Err(CartridgeError::Truncated { len: rom.len() });
+        }
+
+        let mut raw_title = [0; 16];
+        for (byte, addr) in raw_title.iter_mut().zip(0x0134..0x0144) {
+            *byte = rom.read(addr);
+        }
+        let computed_checksum = (0x0134..=0x014C).fold(0u8, |x, addr| {
+            x.wrapping_sub(rom.read(addr)).wrapping_sub(1)
+        });
+
+        Ok(Self {
+            raw_title,
+            cgb_flag: rom.read(0x0143),
+            new_licensee: [rom.read(0x0144), rom.read(0x0145)],
+            sgb_flag: rom.read(0x0146),
+            cartridge_type: rom.read(0x0147),
+            rom_size: rom.read(0x0148),
+            ram_size: rom.read(0x0149),
+            destination: rom.read(0x014A),
+            old_licensee: rom.read(0x014B),
+            version: rom.read(0x014C),
+            header_checksum: rom.read(0x014D),
+            global_checksum: u16::from_be_bytes([rom.read(0x014E), rom.read(0x014F)]),
+            computed_checksum,
+        })
+    }
+
+    /// The title up to the first NUL. Older games use all 16 bytes, CGB games
+    /// only up to the CGB flag. Newer games end it with the manufacturer code,
+    /// which can't be told apart from a longer title reliably, so it is kept.
+    /// Non-ASCII titles are cut off at the first invalid byte.
+    pub fn title(&self) -> &str {
+        let end = if self.cgb_flag & 0x80 == 0x80 { 15 } else { 16 };
+        let title = &self.raw_title[..end];
+        let title = match title.iter().position(|&byte| byte == 0x0) {
+            Some(len) => &title[..len],
+            None => title,
+        };
+        match core::str::from_utf8(title) {
+            Ok(title) => title,
+            Err(err) => core::str::from_utf8(&title[..err.valid_up_to()]).unwrap(),
+        }
+    }
+
+    /// The 4 character manufacturer code at 0x013F of newer games, if the title leaves room for one.
+    pub fn manufacturer_code(&self) -> Option<&str> {
+        let code = &self.raw_title[11..15];
+        if self.cgb_flag & 0x80 == 0x80
+            && code
+                .iter()
+                .all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit())
+        {
+            core::str::from_utf8(code).ok()
+        } else {
+            None
+        }
+    }
+
+    pub fn cgb_support(&self) -> CgbSupport {
+        match self.cgb_flag {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 == 0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        }
+    }
+
+    /// SGB functions are only enabled with the new licensee code in use.
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee == 0x33
+    }
+
+    pub fn licensee(&self) -> Licensee {
+        if self.old_licensee == 0x33 {
+            Licensee::New(self.new_licensee)
+        } else {
+            Licensee::Old(self.old_licensee)
+        }
+    }
+
+    /// Whether the game was sold in Japan.
+    pub fn is_japanese(&self) -> bool {
+        self.destination == 0x00
+    }
+
+    /// The ROM length declared by the ROM size code, 32 KiB << code.
+    pub fn rom_len(&self) -> Option<usize> {
+        match self.rom_size {
+            0x00..=0x08 => Some(0x8000 << self.rom_size),
+            _ => None,
+        }
+    }
+
+    /// The cartridge RAM declared by the RAM size code.
+    pub fn ram_len(&self) -> Option<usize> {
+        match self.ram_size {
+            0x00 => Some(0),
+            // Unofficial, but used by some homebrew
+            0x01 => Some(0x800),
+            0x02 => Some(0x2000),
+            0x03 => Some(0x8000),
+            0x04 => Some(0x20000),
+            0x05 => Some(0x10000),
+            _ => None,
+        }
+    }
+
+    /// Checks what the boot ROM checks, plus that `rom_len` bytes are what the header declares.
+    pub fn verify(&self, rom_len: usize) -> Result<(), CartridgeError> {
+        if self.header_checksum != self.computed_checksum {
+            return Err(CartridgeError::BadHeaderChecksum {
+                expected: self.header_checksum,
+                actual: self.computed_checksum,
+            });
+        }
+
+        let expected = self
+            .rom_len()
+            .ok_or(CartridgeError::UnknownRomSize(self.rom_size))?;
+        if rom_len != expected {
+            return Err(CartridgeError::SizeMismatch {
+                expected,
+                actual: rom_len,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Sums every byte of `rom` except the global checksum itself. Reads the whole ROM.
+    pub fn verify_global_checksum(&self, rom: &mut dyn RomSource) -> Result<(), CartridgeError> {
+        let sum = (0..rom.len())
+            .filter(|&addr| addr != 0x014E && addr != 0x014F)
+            .fold(0u16, |sum, addr| sum.wrapping_add(rom.read(addr) as u16));
+        if sum != self.global_checksum {
+            return Err(CartridgeError::BadGlobalChecksum {
+                expected: self.global_checksum,
+                actual: sum,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn header(rom: &mut Vec<u8>) -> CartridgeHeader {
+        CartridgeHeader::parse(rom).unwrap()
+    }
+
+    #[test]
+    fn parses_every_field() {
+        let mut rom = testing::rom(0x03, 4);
+        rom[0x0134..0x0144].copy_from_slice(b"POKEMON RED\0\0\0\0\0");
+        rom[0x0146] = 0x03;
+        rom[0x0149] = 0x03;
+        rom[0x014A] = 0x01;
+        rom[0x014B] = 0x01;
+        rom[0x014C] = 0x02;
+        rom[0x014E..0x0150].copy_from_slice(&[0x12, 0x34]);
+        testing::fix_header_checksum(&mut rom);
+
+        let header = header(&mut rom);
+        assert_eq!(header.title(), "POKEMON RED");
+        assert_eq!(header.cartridge_type, 0x03);
+        assert_eq!(header.rom_len(), Some(0x1_0000));
+        assert_eq!(header.ram_len(), Some(0x8000));
+        assert_eq!(header.version, 0x02);
+        assert_eq!(header.global_checksum, 0x1234);
+        assert_eq!(header.cgb_support(), CgbSupport::None);
+        assert_eq!(header.licensee(), Licensee::Old(0x01));
+        // SGB support needs the new licensee code
+        assert!(!header.supports_sgb());
+        assert!(!header.is_japanese());
+        assert_eq!(header.verify(rom.len()), Ok(()));
+    }
+
+    #[test]
+    fn cgb_titles_end_before_the_cgb_flag() {
+        let mut rom = testing::rom(0x00, 2);
+        rom[0x0134..0x0143].copy_from_slice(b"ZELDA DX   AZ7E");
+        rom[0x0143] = 0x80;
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        rom[0x0146] = 0x03;
+        rom[0x014B] = 0x33;
+
+        let header = header(&mut rom);
+        assert_eq!(header.title(), "ZELDA DX   AZ7E");
+        assert_eq!(header.manufacturer_code(), Some("AZ7E"));
+        assert_eq!(header.cgb_support(), CgbSupport::Enhanced);
+        assert_eq!(header.licensee(), Licensee::New(*b"01"));
+        assert!(header.supports_sgb());
+
+        rom[0x0143] = 0xC0;
+        assert_eq!(
+            CartridgeHeader::parse(&mut rom).unwrap().cgb_support(),
+            CgbSupport::Only
+        );
+    }
+
+    #[test]
+    fn titles_stop_at_nul_and_invalid_utf8() {
+        let mut rom = testing::rom(0x00, 2);
+        rom[0x0134..0x0144].copy_from_slice(b"TETRIS\0\0\0\0\0\0\0\0\0\0");
+        assert_eq!(header(&mut rom).title(), "TETRIS");
+        rom[0x0134..0x013A].copy_from_slice(b"TET\xFFIS");
+        assert_eq!(header(&mut rom).title(), "TET");
+        assert_eq!(header(&mut rom).manufacturer_code(), None);
+    }
+
+    #[test]
+    fn rejects_truncated_roms() {
+        let mut rom = vec![0; HEADER_END - 1];
+        assert_eq!(
+            CartridgeHeader::parse(&mut rom),
+            Err(CartridgeError::Truncated {
+                len: HEADER_END - 1
+            })
+        );
+    }
+
+    #[test]
+    fn verify_checks_the_header_checksum() {
+        let mut rom = testing::rom(0x00, 2);
+        rom[0x014D] ^= 0xFF;
+        let header = header(&mut rom);
+        assert_eq!(
+            header.verify(rom.len()),
+            Err(CartridgeError::BadHeaderChecksum {
+                expected: rom[0x014D],
+                actual: rom[0x014D] ^ 0xFF,
+            })
+        );
+    }
+
+    #[test]
+    fn verify_checks_the_rom_size() {
+        let mut rom = testing::rom(0x00, 2);
+        let header = header(&mut rom);
+        assert_eq!(
+            header.verify(0x4000),
+            Err(CartridgeError::SizeMismatch {
+                expected: 0x8000,
+                actual: 0x4000,
+            })
+        );
+
+        rom[0x0148] = 0x52;
+        testing::fix_header_checksum(&mut rom);
+        let header = CartridgeHeader::parse(&mut rom).unwrap();
+        assert_eq!(header.rom_len(), None);
+        assert_eq!(
+            header.verify(rom.len()),
+            Err(CartridgeError::UnknownRomSize(0x52))
+        );
+    }
+
+    #[test]
+    fn ram_sizes() {
+        let mut rom = testing::rom(0x03, 4);
+        for (code, len) in [
+            (0x00, Some(0)),
+            (0x02, Some(0x2000)),
+            (0x04, Some(0x2_0000)),
+            (0x05, Some(0x1_0000)),
+            (0x06, None),
+        ] {
+            rom[0x0149] = code;
+            assert_eq!(header(&mut rom).ram_len(), len);
+        }
+    }
+
+    #[test]
+    fn global_checksum_covers_everything_but_itself() {
+        let mut rom = testing::rom(0x00, 2);
+        rom[0x4000] = 0xFF;
+        // The checksum bytes are still 0, so they don't count yet either
+        let sum = rom
+            .iter()
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        rom[0x014E..0x0150].copy_from_slice(&sum.to_be_bytes());
+        let header = header(&mut rom);
+        assert_eq!(header.verify_global_checksum(&mut rom), Ok(()));
+
+        rom[0x7FFF] = 0x01;
+        assert_eq!(
+            header.verify_global_checksum(&mut rom),
+            Err(CartridgeError::BadGlobalChecksum {
+                expected: sum,
+                actual: sum + 1,
+            })
+        );
+    }
+}
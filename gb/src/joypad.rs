@@ -0,0 +1,83 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right = 0x01,
+    Left = 0x02,
+    Up = 0x04,
+    Down = 0x08,
+    A = 0x10,
+    B = 0x20,
+    Select = 0x40,
+    Start = 0x80,
+}
+
+/// P1. Held buttons pull their line low if their group is selected, and any
+/// line going low requests the joypad interrupt.
+pub struct Joypad {
+    /// Bits 4 and 5 of P1, a group is selected while its bit is cleared.
+    select: u8,
+    /// Directions in the lower nibble, buttons in the upper one.
+    held: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            select: 0x30,
+            held: 0,
+        }
+    }
+
+    /// Returns `true` when a line went low, requesting the joypad interrupt.
+    pub fn set(&mut self, button: Button, pressed: bool) -> bool {
+        let old = self.lines();
+        if pressed {
+            self.held |= button as u8;
+        } else {
+            self.held &= !(button as u8);
+        }
+        old & !self.lines() != 0x0
+    }
+
+    /// Whether a held button is in a selected group.
+    pub fn any_selected(&self) -> bool {
+        self.lines() != 0xF
+    }
+
+    /// Returns `true` when selecting another group pulled a line low.
+    pub fn write(&mut self, val: u8) -> bool {
+        let old = self.lines();
+        self.select = val & 0x30;
+        old & !self.lines() != 0x0
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    /// The input lines in P1's lower nibble, active low.
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & 0x10 == 0x0 {
+            pressed |= self.held & 0xF;
+        }
+        if self.select & 0x20 == 0x0 {
+            pressed |= self.held >> 4;
+        }
+        !pressed & 0xF
+    }
+}
+
+impl Snapshot for Joypad {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_u8(self.select);
+        w.write_u8(self.held);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.select = r.read_u8()? & 0x30;
+        self.held = r.read_u8()?;
+        Ok(())
+    }
+}
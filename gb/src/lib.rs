@@ -0,0 +1,611 @@
+//! Platform-independent Gameboy emulator core.
+//!
+//! The core only depends on `core` and `alloc`, so it runs unchanged in the
+//! STM32 firmware, in `cargo test` on the host and in host tools. Enable the
+//! `semihosting` feature to get diagnostics printed through ARM semihosting.
+#![no_std]
+
+extern crate alloc;
+
+#[macro_use]
+extern crate num_derive;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+#[macro_use]
+mod macros;
+
+mod apu;
+pub mod bus;
+mod cartridge;
+pub mod console;
+pub mod cpu;
+pub mod debugger;
+mod decode;
+pub mod diagnostics;
+pub mod disasm;
+mod dma;
+pub mod gdb;
+pub mod header;
+mod joypad;
+mod mem;
+mod ppu;
+pub mod rewind;
+pub mod rom;
+mod serial;
+pub mod state;
+pub mod symbols;
+#[cfg(test)]
+mod testing;
+mod timer;
+pub mod trace;
+
+pub use crate::cartridge::CartridgeError;
+use crate::cpu::Registers;
+use crate::debugger::{WatchHit, Watchpoint};
+use crate::diagnostics::{Diagnostics, Event};
+use crate::disasm::Instruction;
+use crate::header::CartridgeHeader;
+pub use crate::joypad::Button;
+use crate::mem::Memory;
+use crate::rom::RomSource;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use crate::trace::TraceSink;
+
+/// T-cycles per frame, i.e. 154 scanlines of 456 T-cycles each.
+pub const CYCLES_PER_FRAME: u32 = 70224;
+
+/// The hardware being emulated. CGB graphics aren't, only what the CPU can
+/// observe differs: the boot ROM's registers, KEY1 and the speed switch.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Cgb,
+}
+
+/// Why a call into the emulator returned.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// The requested instruction or cycle budget ran without interruption.
+    Completed,
+    /// A full frame has been emulated.
+    FrameReady,
+    /// The instruction at this address is about to execute and has a breakpoint set.
+    Breakpoint(u16),
+    /// The CPU hung itself by executing an illegal opcode and won't execute anything anymore.
+    /// Reported once, the rest of the machine keeps running and finishing frames.
+    LockedUp,
+    /// The game shifted this byte out of the serial port.
+    SerialByte(u8),
+    /// LD B, B just executed at this address, see [`Gameboy::set_break_on_ld_b_b`].
+    SoftwareBreakpoint(u16),
+    /// The last instruction accessed memory under a watchpoint, see [`debugger`].
+    Watchpoint(WatchHit),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RunResult {
+    pub outcome: Outcome,
+    /// T-cycles emulated during the call.
+    pub cycles: u32,
+}
+
+pub struct Gameboy {
+    cpu: cpu::Cpu<Memory>,
+    breakpoints: Vec<u16>,
+    at_breakpoint: bool,
+    /// The CPU locked up during the last instruction.
+    report_lock_up: bool,
+    /// The address of an LD B, B that hasn't been reported yet.
+    report_ld_b_b: Option<u16>,
+}
+
+impl Gameboy {
+    /// Emulates a DMG. Fails if the ROM's header is damaged or its mapper isn't emulated.
+    pub fn new(rom: impl RomSource + 'static, diag: Diagnostics) -> Result<Self, CartridgeError> {
+        Self::with_model(rom, Model::Dmg, diag)
+    }
+
+    pub fn with_model(
+        rom: impl RomSource + 'static,
+        model: Model,
+        diag: Diagnostics,
+    ) -> Result<Self, CartridgeError> {
+        Ok(Self {
+            cpu: cpu::Cpu::new(Memory::new(Box::new(rom), model, diag)?, model),
+            breakpoints: Vec::new(),
+            at_breakpoint: false,
+            report_lock_up: false,
+            report_ld_b_b: None,
+        })
+    }
+
+    /// The header of the loaded ROM.
+    pub fn header(&self) -> &CartridgeHeader {
+        self.cpu.bus().header()
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.cpu.bus_mut().set_button(button, true);
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.cpu.bus_mut().set_button(button, false);
+    }
+
+    pub fn is_locked_up(&self) -> bool {
+        self.cpu.is_locked_up()
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.cpu.registers()
+    }
+
+    pub fn set_registers(&mut self, regs: &Registers) {
+        self.cpu.set_registers(regs);
+    }
+
+    /// M-cycles emulated since power-on.
+    pub fn cycles(&self) -> u64 {
+        self.cpu.bus().cycles()
+    }
+
+    /// Runs forever, forwarding serial output through semihosting.
+    pub fn run(&mut self) -> ! {
+        loop {
+            if let Outcome::SerialByte(val) = self.run_frame().outcome {
+                hprint!("{}", val as char);
+            }
+        }
+    }
+
+    /// Executes exactly one instruction, even if a breakpoint is set on it.
+    pub fn step_instruction(&mut self) -> RunResult {
+        let cycles = self.execute();
+        RunResult {
+            outcome: self.check_outcome().unwrap_or(Outcome::Completed),
+            cycles,
+        }
+    }
+
+    /// Executes instructions until at least `cycles` T-cycles have passed or something
+    /// noteworthy happens, whichever comes first.
+    pub fn run_cycles(&mut self, cycles: u32) -> RunResult {
+        // Output that the last call stopped before reporting comes first
+        if let Some(outcome) = self.check_output() {
+            return RunResult { outcome, cycles: 0 };
+        }
+
+        let mut elapsed = 0;
+        while elapsed < cycles {
+            if let Some(outcome) = self.check_breakpoint() {
+                return RunResult {
+                    outcome,
+                    cycles: elapsed,
+                };
+            }
+
+            elapsed += self.execute();
+            if let Some(outcome) = self.check_outcome() {
+                return RunResult {
+                    outcome,
+                    cycles: elapsed,
+                };
+            }
+        }
+        RunResult {
+            outcome: Outcome::Completed,
+            cycles: elapsed,
+        }
+    }
+
+    /// Executes instructions until the current frame is complete. Returns early on
+    /// anything else noteworthy, in which case calling it again resumes the same frame.
+    pub fn run_frame(&mut self) -> RunResult {
+        self.run_cycles(u32::MAX)
+    }
+
+    /// Serialises the whole machine, see [`state`] for the format.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut w = StateWriter::new();
+        state::write_header(&mut w, &self.cpu.bus().rom_id());
+        self.cpu.save(&mut w);
+        self.cpu.bus().save(&mut w);
+        w.finish()
+    }
+
+    /// Restores a state taken by [`save_state`](Self::save_state). The machine is
+    /// left untouched if the state doesn't belong to the running ROM or is malformed.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let backup = self.save_state();
+        if let Err(err) = self.restore(state) {
+            // Roll back whatever got restored before the error was found
+            self.restore(&backup).unwrap();
+            return Err(err);
+        }
+        self.at_breakpoint = false;
+        self.report_lock_up = false;
+        self.report_ld_b_b = None;
+        self.cpu.bus_mut().take_watch_hit();
+        Ok(())
+    }
+
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        if !self.breakpoints.contains(&pc) {
+            self.breakpoints.push(pc);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.retain(|&bp| bp != pc);
+    }
+
+    /// Reads memory as the CPU sees it, without spending cycles or any other side effect.
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        self.cpu.bus_mut().peek(addr)
+    }
+
+    /// Writes memory as the CPU would, without spending cycles. Writes to I/O
+    /// registers and the mapper have their usual effects.
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        self.cpu.bus_mut().write_word(addr, val);
+    }
+
+    /// The ROM bank currently mapped at `addr`, or `None` outside of ROM.
+    pub fn rom_bank(&self, addr: u16) -> Option<u16> {
+        self.cpu.bus().rom_bank(addr)
+    }
+
+    /// Stops with [`Outcome::SoftwareBreakpoint`] after every LD B, B. Test ROMs,
+    /// e.g. mooneye's, execute it to signal they're done.
+    pub fn set_break_on_ld_b_b(&mut self, enabled: bool) {
+        self.cpu.set_break_on_ld_b_b(enabled);
+    }
+
+    /// Reports CPU reads and writes of watched memory as [`Outcome::Watchpoint`].
+    /// Execute watchpoints are handled by the [`Debugger`](debugger::Debugger) itself.
+    pub(crate) fn set_watchpoints(&mut self, watchpoints: Vec<Watchpoint>) {
+        self.cpu.bus_mut().set_watchpoints(watchpoints);
+    }
+
+    /// Whether the breakpoint at the current instruction has been reported already.
+    pub(crate) fn at_breakpoint(&self) -> bool {
+        self.at_breakpoint
+    }
+
+    /// Marks the current instruction as reported, so that resuming executes it.
+    pub(crate) fn set_at_breakpoint(&mut self) {
+        self.at_breakpoint = true;
+    }
+
+    /// Decodes the instruction at `addr` without touching the emulated machine.
+    pub fn disassemble(&mut self, addr: u16) -> Instruction {
+        let mem = self.cpu.bus_mut();
+        let bytes = [
+            mem.peek(addr),
+            mem.peek(addr.wrapping_add(1)),
+            mem.peek(addr.wrapping_add(2)),
+        ];
+        Instruction::decode(addr, bytes)
+    }
+
+    /// Logs every instruction to `sink` before it executes, see [`trace`] for the
+    /// format. LY is stubbed to 0x90 until tracing stops.
+    pub fn start_trace(&mut self, sink: impl TraceSink + 'static) {
+        self.cpu.set_tracer(Some(Box::new(sink)));
+        self.cpu.bus_mut().set_ly_stub(true);
+    }
+
+    pub fn stop_trace(&mut self) {
+        self.cpu.set_tracer(None);
+        self.cpu.bus_mut().set_ly_stub(false);
+    }
+
+    /// Runs the CPU for a single instruction and returns the T-cycles it took.
+    fn execute(&mut self) -> u32 {
+        let pc = self.cpu.pc();
+        let idle = self.cpu.is_idle();
+        let locked_up = self.cpu.is_locked_up();
+        let cycles = self.cpu.step();
+
+        if !locked_up && self.cpu.is_locked_up() {
+            let mem = self.cpu.bus_mut();
+            // Peeked, so that looking at it doesn't hit watchpoints or emit diagnostics
+            let opcode = mem.peek(pc);
+            mem.emit(Event::IllegalOpcode { addr: pc, opcode });
+            self.report_lock_up = true;
+        }
+        if self.cpu.take_ld_b_b() {
+            self.report_ld_b_b = Some(pc);
+        }
+
+        // Idling in HALT or STOP doesn't leave the breakpoint
+        if !idle || self.cpu.pc() != pc {
+            self.at_breakpoint = false;
+        }
+        cycles * 4
+    }
+
+    fn restore(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut r = StateReader::new(state);
+        state::read_header(&mut r, &self.cpu.bus().rom_id())?;
+        self.cpu.load(&mut r)?;
+        self.cpu.bus_mut().load(&mut r)?;
+        if !r.is_empty() {
+            return Err(StateError::BadLength);
+        }
+        Ok(())
+    }
+
+    fn check_outcome(&mut self) -> Option<Outcome> {
+        let mem = self.cpu.bus_mut();
+        // Reported first, so the debugger stops right after the access
+        if let Some(hit) = mem.take_watch_hit() {
+            return Some(Outcome::Watchpoint(hit));
+        }
+
+        // Also before output, which stays pending until the next call, or the
+        // machine would have moved past the instruction by the time it's reported
+        if let Some(pc) = self.report_ld_b_b.take() {
+            return Some(Outcome::SoftwareBreakpoint(pc));
+        }
+
+        if core::mem::replace(&mut self.report_lock_up, false) {
+            return Some(Outcome::LockedUp);
+        }
+
+        self.check_output().or_else(|| self.check_breakpoint())
+    }
+
+    /// Reports a serial byte or finished frame that hasn't been reported yet.
+    fn check_output(&mut self) -> Option<Outcome> {
+        let mem = self.cpu.bus_mut();
+        if let Some(val) = mem.take_serial() {
+            return Some(Outcome::SerialByte(val));
+        }
+
+        if mem.take_frame() {
+            return Some(Outcome::FrameReady);
+        }
+
+        None
+    }
+
+    /// Reports each arrival at a breakpoint once, so that resuming executes the instruction.
+    fn check_breakpoint(&mut self) -> Option<Outcome> {
+        let pc = self.cpu.pc();
+        if !self.at_breakpoint && self.breakpoints.contains(&pc) {
+            self.at_breakpoint = true;
+            return Some(Outcome::Breakpoint(pc));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Ime;
+    use crate::decode::{CbInstr, Cond, Instr, CB_OPCODES, OPCODES, R8};
+    use crate::testing;
+    use alloc::vec;
+
+    #[test]
+    fn ld_b_b_is_reported_before_serial_output() {
+        // Start shifting out a byte with the internal clock, then execute LD B, B
+        // until well after the transfer is done
+        let setup = [0x3E, 0x42, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02];
+        let mut code = vec![0x40; 0x6000];
+        code[..setup.len()].copy_from_slice(&setup);
+        let mut gameboy = testing::gameboy(&code);
+        gameboy.set_break_on_ld_b_b(true);
+
+        let end = testing::CODE + 0x5F00;
+        let mut serial = None;
+        let mut breakpoints = 0;
+        while gameboy.registers().pc < end {
+            match gameboy.run_frame().outcome {
+                Outcome::SoftwareBreakpoint(pc) => {
+                    // Still stopped right after it
+                    assert_eq!(gameboy.registers().pc, pc + 1);
+                    breakpoints += 1;
+                }
+                Outcome::SerialByte(val) => serial = Some(val),
+                _ => {}
+            }
+        }
+        assert_eq!(serial, Some(0x42));
+        assert_eq!(breakpoints, end - testing::CODE - setup.len() as u16);
+    }
+
+    /// Fills WRAM with a counter, so every step changes the state.
+    const COUNTER: [u8; 7] = [0x21, 0x00, 0xC0, 0x22, 0x3C, 0x18, 0xFC];
+
+    /// The state after a few steps, and a machine that has run on from there.
+    fn state_and_later() -> (Vec<u8>, Gameboy) {
+        let mut gameboy = testing::gameboy(&COUNTER);
+        gameboy.run_cycles(100);
+        let state = gameboy.save_state();
+        gameboy.run_cycles(10_000);
+        (state, gameboy)
+    }
+
+    #[test]
+    fn states_round_trip() {
+        let (state, mut gameboy) = state_and_later();
+        let later = gameboy.save_state();
+        assert_ne!(state, later);
+        assert_eq!(&state[..6], b"GBSS\x01\x00");
+
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.save_state(), state);
+        // Runs on exactly as it did the first time
+        gameboy.run_cycles(10_000);
+        assert_eq!(gameboy.save_state(), later);
+    }
+
+    #[test]
+    fn bad_states_leave_the_machine_alone() {
+        let (state, mut gameboy) = state_and_later();
+        let later = gameboy.save_state();
+
+        let mut magic = state.clone();
+        magic[0] = b'X';
+        let mut version = state.clone();
+        version[4] = 0x02;
+        let mut rom_id = state.clone();
+        rom_id[6] ^= 0x01;
+        // The CPU and most of memory are restored before these are found
+        let truncated = &state[..state.len() - 1];
+        let mut trailing = state.clone();
+        trailing.push(0x00);
+        let mut ime = state.clone();
+        ime[25 + 12] = 0x03;
+
+        let cases: [(&[u8], StateError); 6] = [
+            (&magic, StateError::BadMagic),
+            (&version, StateError::UnsupportedVersion(2)),
+            (&rom_id, StateError::RomMismatch),
+            (truncated, StateError::BadLength),
+            (&trailing, StateError::BadLength),
+            (&ime, StateError::Invalid),
+        ];
+        for (bad, err) in cases.iter() {
+            assert_eq!(gameboy.load_state(bad), Err(*err));
+            assert!(gameboy.save_state() == later, "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn div_is_frozen_during_speed_switches() {
+        // LD A, 1; LDH (KEY1), A; STOP; LDH A, (DIV)
+        let code = [0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0xF0, 0x04];
+        let mut gameboy = testing::gameboy_with_model(&code, Model::Cgb);
+        for _ in 0..5 {
+            gameboy.step_instruction();
+        }
+        assert_eq!(gameboy.peek(0xFF4D) & 0x80, 0x80);
+        assert_eq!(gameboy.registers().a, 0x00);
+    }
+
+    #[test]
+    fn illegal_opcodes_lock_up() {
+        let illegal = [
+            0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+        ];
+        for &opcode in illegal.iter() {
+            let mut gameboy = testing::gameboy(&[opcode]);
+            assert_eq!(gameboy.run_cycles(1000).outcome, Outcome::LockedUp);
+            assert!(gameboy.is_locked_up());
+            let regs = gameboy.registers();
+            assert_eq!(regs.pc, testing::CODE, "{:02X}", opcode);
+
+            // Pending interrupts are ignored, even with IME on
+            gameboy.poke(0xFFFF, 0x1F);
+            gameboy.poke(0xFF0F, 0x1F);
+            gameboy.set_registers(&Registers {
+                ime: Ime::Enabled,
+                ..regs
+            });
+            // Reported once, then time passes without the CPU doing anything
+            let cycles = gameboy.cycles();
+            let result = gameboy.run_cycles(1000);
+            assert_eq!(result.outcome, Outcome::Completed);
+            assert_eq!(gameboy.cycles() - cycles, result.cycles as u64 / 4);
+            assert_eq!(
+                gameboy.registers(),
+                Registers {
+                    ime: Ime::Enabled,
+                    ..regs
+                }
+            );
+            assert_eq!(gameboy.peek(0xFF0F) & 0x1F, 0x1F);
+        }
+    }
+
+    /// M-cycles of every unprefixed opcode, with conditions not taken. The
+    /// prefix and illegal opcodes are 0.
+    #[rustfmt::skip]
+    const CYCLES: [u32; 256] = [
+        1, 3, 2, 2, 1, 1, 2, 1, 5, 2, 2, 2, 1, 1, 2, 1,
+        1, 3, 2, 2, 1, 1, 2, 1, 3, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 1, 1, 2, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        2, 3, 2, 2, 3, 3, 3, 1, 2, 2, 2, 2, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 2, 2, 2, 2, 2, 1, 2, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 3, 4, 3, 4, 2, 4, 2, 4, 3, 0, 3, 6, 2, 4,
+        2, 3, 3, 0, 3, 4, 2, 4, 2, 4, 3, 0, 3, 0, 2, 4,
+        3, 3, 2, 0, 0, 4, 2, 4, 4, 1, 4, 0, 0, 0, 2, 4,
+        3, 3, 2, 1, 0, 4, 2, 4, 3, 2, 4, 1, 0, 0, 2, 4,
+    ];
+
+    /// Runs `code` with `f` as flags and HL pointing into WRAM, returns the M-cycles
+    /// its first instruction took.
+    fn cycles(code: &[u8], f: u8) -> u32 {
+        let mut gameboy = testing::gameboy(code);
+        gameboy.step_instruction();
+        let regs = gameboy.registers();
+        gameboy.set_registers(&Registers {
+            f,
+            h: 0xC0,
+            l: 0x00,
+            ..regs
+        });
+        gameboy.step_instruction().cycles / 4
+    }
+
+    #[test]
+    fn opcodes_take_their_cycles() {
+        for (opcode, &instr) in OPCODES.iter().enumerate() {
+            let code = [opcode as u8, 0x00, 0x00];
+            let taken = match instr {
+                Instr::Illegal | Instr::Prefix => {
+                    assert_eq!(CYCLES[opcode], 0);
+                    continue;
+                }
+                Instr::JrCond(cond) => Some((cond, 3)),
+                Instr::JpCond(cond) => Some((cond, 4)),
+                Instr::CallCond(cond) => Some((cond, 6)),
+                Instr::RetCond(cond) => Some((cond, 5)),
+                _ => None,
+            };
+            match taken {
+                Some((cond, taken)) => {
+                    // Flags that make the condition hold, and ones that don't
+                    let (holds, fails) = match cond {
+                        Cond::Nz => (0x00, 0x80),
+                        Cond::Z => (0x80, 0x00),
+                        Cond::Nc => (0x00, 0x10),
+                        Cond::C => (0x10, 0x00),
+                    };
+                    assert_eq!(cycles(&code, holds), taken, "{:02X} taken", opcode);
+                    assert_eq!(cycles(&code, fails), CYCLES[opcode], "{:02X}", opcode);
+                }
+                None => assert_eq!(cycles(&code, 0x00), CYCLES[opcode], "{:02X}", opcode),
+            }
+        }
+    }
+
+    #[test]
+    fn cb_opcodes_take_their_cycles() {
+        for (opcode, &instr) in CB_OPCODES.iter().enumerate() {
+            // BIT only reads (HL), the others write it back too
+            let expected = match instr {
+                CbInstr::Bit(_, R8::HlInd) => 3,
+                CbInstr::Shift(_, R8::HlInd)
+                | CbInstr::Res(_, R8::HlInd)
+                | CbInstr::Set(_, R8::HlInd) => 4,
+                _ => 2,
+            };
+            let code = [0xCB, opcode as u8];
+            assert_eq!(cycles(&code, 0x00), expected, "CB {:02X}", opcode);
+        }
+    }
+}
@@ -0,0 +1,10 @@
+/// Prints through semihosting when the `semihosting` feature is enabled and
+/// compiles to nothing otherwise, so the core stays usable on the host.
+macro_rules! hprint {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "semihosting")]
+        let _ = cortex_m_semihosting::hprint!($($arg)*);
+        #[cfg(not(feature = "semihosting"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
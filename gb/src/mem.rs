@@ -0,0 +1,698 @@
+use crate::apu::Apu;
+use crate::bus::{Bus, Stop};
+use crate::cartridge::{Cartridge, CartridgeError};
+use crate::debugger::{Access, WatchHit, Watchpoint};
+use crate::diagnostics::{Diagnostics, Event};
+use crate::dma::Dma;
+use crate::header::CartridgeHeader;
+use crate::joypad::{Button, Joypad};
+use crate::ppu::Ppu;
+use crate::rom::RomSource;
+use crate::serial::Serial;
+use crate::state::{Snapshot, StateError, StateReader, StateWriter, ROM_ID_LEN};
+use crate::timer::Timer;
+use crate::Model;
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub const INT_VBLANK: u8 = 0x01;
+pub const INT_STAT: u8 = 0x02;
+pub const INT_TIMER: u8 = 0x04;
+pub const INT_SERIAL: u8 = 0x08;
+pub const INT_JOYPAD: u8 = 0x10;
+
+pub struct Memory {
+    model: Model,
+    rom: Cartridge,
+    vram: Vec<u8>,
+    wram_0: Vec<u8>,
+    wram_n: Vec<u8>,
+    oam: Vec<u8>,
+    pub io_regs: IoRegs,
+    hram: Vec<u8>,
+    ie: u8,
+    diag: Diagnostics,
+
+    timer: Timer,
+    serial: Serial,
+    ppu: Ppu,
+    apu: Apu,
+    dma: Dma,
+    joypad: Joypad,
+    /// KEY1 bit 0, STOP switches speed when set.
+    speed_switch_armed: bool,
+    double_speed: bool,
+    /// The system clock is stopped by STOP until a button is pressed.
+    stopped: bool,
+    /// LY reads as 0x90 while tracing, not saved in states.
+    ly_stub: bool,
+    /// Read and write watchpoints, checked on every CPU access while there are any.
+    watchpoints: Vec<Watchpoint>,
+    /// The first watched access since the last `take_watch_hit`.
+    watch_hit: Option<WatchHit>,
+    /// M-cycles since power-on.
+    cycles: u64,
+}
+
+impl Memory {
+    pub fn new(
+        rom: Box<dyn RomSource>,
+        model: Model,
+        diag: Diagnostics,
+    ) -> Result<Self, CartridgeError> {
+        Ok(Self {
+            model,
+            rom: Cartridge::load(rom)?,
+            vram: vec![0; 0x2000],
+            wram_0: vec![0; 0x1000],
+            wram_n: vec![0; 0x1000],
+            oam: vec![0; 0xA0],
+            io_regs: IoRegs::new(),
+            hram: vec![0; 0x7F],
+            ie: 0,
+            diag,
+
+            timer: Timer::new(),
+            serial: Serial::new(),
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            dma: Dma::new(),
+            joypad: Joypad::new(),
+            speed_switch_armed: false,
+            double_speed: false,
+            stopped: false,
+            ly_stub: false,
+            watchpoints: Vec::new(),
+            watch_hit: None,
+            cycles: 0,
+        })
+    }
+
+    /// The scheduler: advances every component by one M-cycle. It runs once for every
+    /// M-cycle the CPU spends, so all components stay in lock-step with its memory accesses.
+    pub fn tick(&mut self) {
+        if self.stopped {
+            self.tick_stopped();
+            return;
+        }
+        self.cycles += 1;
+
+        let old_div = self.timer.counter();
+        let mut int = 0;
+        if self.timer.tick() {
+            int |= INT_TIMER;
+        }
+        let new_div = self.timer.counter();
+
+        if self.serial.tick(old_div, new_div) {
+            int |= INT_SERIAL;
+        }
+        if self.double_speed {
+            // The frame sequencer keeps its rate by following the next higher DIV bit
+            self.apu.tick(old_div >> 1, new_div >> 1);
+        } else {
+            self.apu.tick(old_div, new_div);
+        }
+
+        // The PPU isn't sped up, so it only gets half as many dots per M-cycle in double speed
+        let ppu_ints = self.ppu.tick(if self.double_speed { 2 } else { 4 });
+        if ppu_ints.vblank {
+            int |= INT_VBLANK;
+        }
+        if ppu_ints.stat {
+            int |= INT_STAT;
+        }
+
+        if let Some((src, idx)) = self.dma.tick() {
+            // DMA sources above 0xDFFF read from echo RAM
+            let src = if src >= 0xE000 { src - 0x2000 } else { src };
+            self.oam[idx] = self.read_word(src);
+        }
+
+        self.io_regs.int_f |= int;
+    }
+
+    /// An M-cycle with the system clock stopped, by STOP or a speed switch. Only
+    /// frames are still paced, everything else waits.
+    pub fn tick_stopped(&mut self) {
+        self.cycles += 1;
+        self.ppu.tick_stopped();
+    }
+
+    pub fn emit(&mut self, event: Event) {
+        self.diag.emit(event);
+    }
+
+    /// Presses or releases a button, which also wakes the system from STOP.
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set(button, pressed) {
+            self.io_regs.int_f |= INT_JOYPAD;
+            self.stopped = false;
+        }
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        self.rom.header()
+    }
+
+    pub fn rom_id(&self) -> [u8; ROM_ID_LEN] {
+        self.rom.id()
+    }
+
+    /// Returns whether the PPU completed a frame since the last call.
+    pub fn take_frame(&mut self) -> bool {
+        self.ppu.take_frame()
+    }
+
+    /// Returns the byte shifted out by the last serial transfer, if it hasn't been taken yet.
+    pub fn take_serial(&mut self) -> Option<u8> {
+        self.serial.take_output()
+    }
+
+    pub fn read_word(&mut self, addr: u16) -> u8 {
+        self.read_byte(addr, true)
+    }
+
+    /// Reads like `read_word`, but doesn't report accesses to unmapped or prohibited memory.
+    pub fn peek(&mut self, addr: u16) -> u8 {
+        self.read_byte(addr, false)
+    }
+
+    /// Makes LY always read 0x90, as CPU trace logs expect.
+    pub fn set_ly_stub(&mut self, stub: bool) {
+        self.ly_stub = stub;
+    }
+
+    /// The ROM bank mapped at `addr`, if it's in ROM at all.
+    pub fn rom_bank(&self, addr: u16) -> Option<u16> {
+        (addr < 0x8000).then(|| self.rom.rom_bank(addr as usize))
+    }
+
+    pub fn set_watchpoints(&mut self, watchpoints: Vec<Watchpoint>) {
+        self.watchpoints = watchpoints;
+        self.watch_hit = None;
+    }
+
+    /// Returns the first access hitting a watchpoint since the last call.
+    pub fn take_watch_hit(&mut self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    fn watch(&mut self, addr: u16, val: u8, access: Access) {
+        if self.watch_hit.is_none() && self.watchpoints.iter().any(|w| w.watches(addr, access)) {
+            self.watch_hit = Some(WatchHit { addr, val, access });
+        }
+    }
+
+    fn read_byte(&mut self, addr: u16, report: bool) -> u8 {
+        let addr = addr as usize;
+
+        match addr {
+            0x0000..=0x7FFF => self.rom.read(addr),
+            0x8000..=0x9FFF => self.vram[addr - 0x8000],
+            0xA000..=0xBFFF => self.rom.read(addr),
+            0xC000..=0xCFFF => self.wram_0[addr - 0xC000],
+            0xD000..=0xDFFF => self.wram_n[addr - 0xD000], // needs to be adjusted for potential CGB support
+            0xE000..=0xFDFF => {
+                let addr = (addr - 0xE000) % 0x1E00;
+                if addr >= 0x1000 {
+                    self.wram_n[addr - 0x1000]
+                } else {
+                    self.wram_0[addr]
+                }
+            }
+            0xFE00..=0xFE9F => {
+                if self.dma.is_active() {
+                    0xFF
+                } else {
+                    self.oam[addr - 0xFE00]
+                }
+            }
+            0xFEA0..=0xFEFF => {
+                if report {
+                    self.diag.emit(Event::ProhibitedRead { addr: addr as u16 });
+                }
+                0
+            } // use prohibited
+            0xFF00 => self.joypad.read(),
+            0xFF01..=0xFF02 => self.serial.read(addr),
+            0xFF04..=0xFF07 => self.timer.read(addr),
+            0xFF10..=0xFF3F => self.apu.read(addr),
+            0xFF46 => self.dma.source(),
+            0xFF44 if self.ly_stub => 0x90,
+            0xFF40..=0xFF4B => self.ppu.read(addr),
+            0xFF4D if self.model == Model::Cgb => {
+                0x7E | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8
+            }
+            0xFF03..=0xFF7F => match self.io_regs.read(addr) {
+                Some(val) => val,
+                None => {
+                    if report {
+                        self.diag.emit(Event::UnmappedIoRead { addr: addr as u16 });
+                    }
+                    0
+                }
+            },
+            0xFF80..=0xFFFE => self.hram[addr - 0xFF80],
+            0xFFFF => self.ie,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write_word(&mut self, addr: u16, val: u8) {
+        let addr = addr as usize;
+
+        match addr {
+            0x0000..=0x7FFF => self.rom.write(addr, val, &mut self.diag),
+            0x8000..=0x9FFF => self.vram[addr - 0x8000] = val,
+            0xA000..=0xBFFF => self.rom.write(addr, val, &mut self.diag),
+            0xC000..=0xCFFF => self.wram_0[addr - 0xC000] = val,
+            0xD000..=0xDFFF => self.wram_n[addr - 0xD000] = val, // needs to be adjusted for potential CGB support
+            0xE000..=0xFDFF => {
+                let addr = (addr - 0xE000) % 0x1E00;
+                if addr >= 0x1000 {
+                    self.wram_n[addr - 0x1000] = val;
+                } else {
+                    self.wram_0[addr] = val;
+                }
+            }
+            0xFE00..=0xFE9F => {
+                if !self.dma.is_active() {
+                    self.oam[addr - 0xFE00] = val;
+                }
+            }
+            0xFEA0..=0xFEFF => {
+                self.diag.emit(Event::ProhibitedWrite {
+                    addr: addr as u16,
+                    val,
+                });
+            } // use prohibited
+            0xFF00 => {
+                if self.joypad.write(val) {
+                    self.io_regs.int_f |= INT_JOYPAD;
+                }
+            }
+            0xFF01..=0xFF02 => self.serial.write(addr, val),
+            0xFF04..=0xFF07 => self.timer.write(addr, val),
+            0xFF10..=0xFF3F => self.apu.write(addr, val),
+            0xFF46 => self.dma.start(val),
+            0xFF40..=0xFF4B => self.ppu.write(addr, val),
+            0xFF4D if self.model == Model::Cgb => self.speed_switch_armed = val & 0x1 == 0x1,
+            0xFF03..=0xFF7F => self.io_regs.write(addr, val, &mut self.diag),
+            0xFF80..=0xFFFE => self.hram[addr - 0xFF80] = val,
+            0xFFFF => self.ie = val,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Snapshot for Memory {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.vram);
+        w.write_bytes(&self.wram_0);
+        w.write_bytes(&self.wram_n);
+        w.write_bytes(&self.oam);
+        w.write_bytes(&self.hram);
+        w.write_u8(self.model as u8);
+        w.write_u8(self.ie);
+        w.write_u64(self.cycles);
+        self.io_regs.save(w);
+        self.timer.save(w);
+        self.serial.save(w);
+        self.ppu.save(w);
+        self.apu.save(w);
+        self.dma.save(w);
+        self.joypad.save(w);
+        w.write_bool(self.speed_switch_armed);
+        w.write_bool(self.double_speed);
+        w.write_bool(self.stopped);
+        self.rom.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.read_bytes(&mut self.vram)?;
+        r.read_bytes(&mut self.wram_0)?;
+        r.read_bytes(&mut self.wram_n)?;
+        r.read_bytes(&mut self.oam)?;
+        r.read_bytes(&mut self.hram)?;
+        if r.read_u8()? != self.model as u8 {
+            return Err(StateError::Invalid);
+        }
+        self.ie = r.read_u8()?;
+        self.cycles = r.read_u64()?;
+        self.io_regs.load(r)?;
+        self.timer.load(r)?;
+        self.serial.load(r)?;
+        self.ppu.load(r)?;
+        self.apu.load(r)?;
+        self.dma.load(r)?;
+        self.joypad.load(r)?;
+        self.speed_switch_armed = r.read_bool()?;
+        self.double_speed = r.read_bool()?;
+        self.stopped = r.read_bool()?;
+        self.rom.load(r)
+    }
+}
+
+impl Bus for Memory {
+    fn read(&mut self, addr: u16) -> u8 {
+        let val = self.read_word(addr);
+        if !self.watchpoints.is_empty() {
+            self.watch(addr, val, Access::Read);
+        }
+        self.tick();
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        if !self.watchpoints.is_empty() {
+            self.watch(addr, val, Access::Write);
+        }
+        self.write_word(addr, val);
+        self.tick();
+    }
+
+    fn peek(&mut self, addr: u16) -> u8 {
+        Memory::peek(self, addr)
+    }
+
+    fn rom_bank(&self, addr: u16) -> Option<u16> {
+        Memory::rom_bank(self, addr)
+    }
+
+    fn tick(&mut self) {
+        Memory::tick(self);
+    }
+
+    fn tick_stopped(&mut self) {
+        Memory::tick_stopped(self);
+    }
+
+    /// Skips ahead without returning to the CPU for every M-cycle, stops at
+    /// finished frames and serial bytes so `Gameboy` can still report them.
+    /// Interrupts don't end STOP, only buttons do, and those are pressed between steps.
+    fn idle(&mut self) -> u32 {
+        let mut cycles = 0;
+        loop {
+            Memory::tick(self);
+            cycles += 1;
+            let wake = !self.stopped && self.pending_interrupts() != 0x0;
+            if wake || self.ppu.frame_ready() || self.serial.has_output() {
+                return cycles;
+            }
+        }
+    }
+
+    fn stop(&mut self) -> Stop {
+        if self.joypad.any_selected() {
+            return if self.pending_interrupts() == 0x0 {
+                Stop::Halt
+            } else {
+                Stop::Nop
+            };
+        }
+
+        // Both the speed switch and STOP mode reset DIV
+        self.timer.write(0xFF04, 0);
+        if self.speed_switch_armed {
+            self.speed_switch_armed = false;
+            self.double_speed = !self.double_speed;
+            Stop::SpeedSwitch
+        } else {
+            self.stopped = true;
+            Stop::Stopped
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.ie & self.io_regs.int_f & 0x1F
+    }
+
+    fn acknowledge_interrupt(&mut self, mask: u8) {
+        self.io_regs.int_f &= !mask;
+    }
+}
+
+pub struct IoRegs {
+    pub int_f: u8,
+}
+
+// TODO: Initialize these correctly
+impl IoRegs {
+    pub fn new() -> Self {
+        Self { int_f: 0 }
+    }
+
+    pub fn write(&mut self, addr: usize, val: u8, diag: &mut Diagnostics) {
+        match addr {
+            0xFF0F => self.int_f = val & 0x1F,
+            _ => diag.emit(Event::UnmappedIoWrite {
+                addr: addr as u16,
+                val,
+            }),
+        };
+    }
+
+    /// Returns `None` for unmapped registers, the caller decides whether to report that.
+    pub fn read(&self, addr: usize) -> Option<u8> {
+        match addr {
+            // The upper 3 bits are unused and always read as set
+            0xFF0F => Some(self.int_f | 0xE0),
+            _ => None,
+        }
+    }
+}
+
+impl Snapshot for IoRegs {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_u8(self.int_f);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.int_f = r.read_u8()? & 0x1F;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn memory(model: Model) -> Memory {
+        let rom = Box::new(testing::rom(0x00, 2));
+        Memory::new(rom, model, Diagnostics::default()).unwrap()
+    }
+
+    /// Ticks until one of `mask` is requested, returns the M-cycles it took.
+    fn ticks_until(mem: &mut Memory, mask: u8, max: u32) -> Option<u32> {
+        (1..=max).find(|_| {
+            mem.tick();
+            mem.io_regs.int_f & mask != 0x0
+        })
+    }
+
+    /// Ticks until channel 1 stops playing, returns the M-cycles it took.
+    fn ticks_until_silent(mem: &mut Memory, max: u32) -> Option<u32> {
+        (1..=max).find(|_| {
+            mem.tick();
+            mem.read_word(0xFF26) & 0x1 == 0x0
+        })
+    }
+
+    fn play_shortest_note(mem: &mut Memory) {
+        mem.write_word(0xFF12, 0xF0);
+        mem.write_word(0xFF11, 0x3F);
+        mem.write_word(0xFF14, 0xC0);
+    }
+
+    #[test]
+    fn timer_interrupts_on_reload() {
+        let mut mem = memory(Model::Dmg);
+        mem.write_word(0xFF05, 0xFE);
+        mem.write_word(0xFF07, 0x05);
+        // Two increments 4 M-cycles apart, then a cycle until the reload
+        assert_eq!(ticks_until(&mut mem, INT_TIMER, 100), Some(9));
+    }
+
+    #[test]
+    fn serial_is_clocked_by_div() {
+        let mut mem = memory(Model::Dmg);
+        mem.write_word(0xFF01, 0x42);
+        mem.write_word(0xFF02, 0x81);
+        assert_eq!(ticks_until(&mut mem, INT_SERIAL, 2000), Some(1024));
+        assert_eq!(mem.take_serial(), Some(0x42));
+
+        // Resetting DIV restarts the bit clock
+        let mut mem = memory(Model::Dmg);
+        mem.write_word(0xFF02, 0x81);
+        for _ in 0..100 {
+            mem.tick();
+        }
+        mem.write_word(0xFF04, 0);
+        assert_eq!(ticks_until(&mut mem, INT_SERIAL, 2000), Some(1024));
+    }
+
+    #[test]
+    fn frame_sequencer_follows_div_bit_4() {
+        let mut mem = memory(Model::Dmg);
+        play_shortest_note(&mut mem);
+        assert_eq!(ticks_until_silent(&mut mem, 0x4000), Some(0x800));
+    }
+
+    #[test]
+    fn frame_sequencer_follows_div_bit_5_in_double_speed() {
+        let mut mem = memory(Model::Cgb);
+        mem.double_speed = true;
+        play_shortest_note(&mut mem);
+        // DIV runs twice as fast, so the sequencer keeps its real-time rate
+        assert_eq!(ticks_until_silent(&mut mem, 0x4000), Some(0x1000));
+    }
+
+    #[test]
+    fn dma_reads_echo_ram_from_wram() {
+        let mut mem = memory(Model::Dmg);
+        for idx in 0..0xA0 {
+            mem.write_word(0xC000 + idx, idx as u8 ^ 0x5A);
+        }
+        mem.write_word(0xFF46, 0xE0);
+        for _ in 0..0xA1 {
+            mem.tick();
+        }
+        for idx in 0..0xA0 {
+            assert_eq!(mem.read_word(0xFE00 + idx), idx as u8 ^ 0x5A);
+        }
+    }
+
+    #[test]
+    fn oam_is_locked_during_dma() {
+        let mut mem = memory(Model::Dmg);
+        mem.write_word(0xFE00, 0x12);
+        mem.write_word(0xFF46, 0xC0);
+        mem.tick();
+        mem.tick();
+        assert_eq!(mem.read_word(0xFE00), 0xFF);
+        mem.write_word(0xFE01, 0x34);
+        for _ in 0..0xA0 {
+            mem.tick();
+        }
+        assert_eq!(mem.read_word(0xFE00), 0x00);
+        assert_eq!(mem.read_word(0xFE01), 0x00);
+    }
+
+    #[test]
+    fn idle_matches_single_ticks() {
+        let timer = |mem: &mut Memory| {
+            mem.write_word(0xFF05, 0xF0);
+            mem.write_word(0xFF07, 0x05);
+            mem.write_word(0xFFFF, INT_TIMER);
+        };
+        let mut idle = memory(Model::Dmg);
+        timer(&mut idle);
+        let mut ticked = memory(Model::Dmg);
+        timer(&mut ticked);
+
+        let cycles = idle.idle();
+        let mut ticks = 0;
+        while ticked.pending_interrupts() == 0x0 {
+            Bus::tick(&mut ticked);
+            ticks += 1;
+        }
+        // 16 increments, then a cycle until the reload
+        assert_eq!(cycles, 16 * 4 + 1);
+        assert_eq!(cycles, ticks);
+        assert_eq!(idle.cycles(), ticked.cycles());
+        assert_eq!(idle.read_word(0xFF04), ticked.read_word(0xFF04));
+        assert_eq!(idle.pending_interrupts(), INT_TIMER);
+    }
+
+    /// Lets DIV run to 0x12, then executes STOP.
+    fn stop(mem: &mut Memory) -> Stop {
+        for _ in 0..0x12 * 64 {
+            mem.tick();
+        }
+        Bus::stop(mem)
+    }
+
+    #[test]
+    fn stop_with_a_button_held() {
+        let mut mem = memory(Model::Dmg);
+        mem.write_word(0xFF00, 0x20);
+        mem.set_button(Button::Right, true);
+        assert_eq!(stop(&mut mem), Stop::Halt);
+        assert_eq!(mem.read_word(0xFF04), 0x12);
+
+        mem.write_word(0xFFFF, INT_TIMER);
+        mem.io_regs.int_f = INT_TIMER;
+        assert_eq!(stop(&mut mem), Stop::Nop);
+        assert!(!mem.is_stopped());
+
+        // Unselected buttons don't count
+        let mut mem = memory(Model::Dmg);
+        mem.write_word(0xFF00, 0x10);
+        mem.set_button(Button::Right, true);
+        assert_eq!(stop(&mut mem), Stop::Stopped);
+    }
+
+    #[test]
+    fn stop_stops_the_clock_until_a_button_is_pressed() {
+        let mut mem = memory(Model::Dmg);
+        mem.write_word(0xFF00, 0x20);
+        assert_eq!(stop(&mut mem), Stop::Stopped);
+        assert!(mem.is_stopped());
+        assert_eq!(mem.read_word(0xFF04), 0x00);
+
+        // Interrupts don't wake it, and nothing clocked by DIV runs
+        mem.write_word(0xFFFF, 0x1F);
+        mem.write_word(0xFF07, 0x05);
+        for _ in 0..0x1000 {
+            mem.tick();
+        }
+        assert_eq!(mem.cycles(), 0x12 * 64 + 0x1000);
+        assert_eq!(mem.read_word(0xFF04), 0x00);
+        assert_eq!(mem.read_word(0xFF05), 0x00);
+        assert!(mem.is_stopped());
+
+        mem.set_button(Button::Right, true);
+        assert!(!mem.is_stopped());
+        mem.tick();
+        assert_eq!(mem.timer.counter(), 4);
+    }
+
+    #[test]
+    fn stop_switches_speed_when_armed() {
+        let mut mem = memory(Model::Cgb);
+        mem.write_word(0xFF4D, 0x01);
+        assert_eq!(mem.read_word(0xFF4D), 0x7F);
+        assert_eq!(stop(&mut mem), Stop::SpeedSwitch);
+        assert_eq!(mem.read_word(0xFF4D), 0xFE);
+        assert!(!mem.is_stopped());
+
+        // DIV stays reset while the switch settles
+        for _ in 0..0x1000 {
+            Bus::tick_stopped(&mut mem);
+        }
+        assert_eq!(mem.timer.counter(), 0);
+        mem.tick();
+        assert_eq!(mem.timer.counter(), 4);
+
+        // And back
+        mem.write_word(0xFF4D, 0x01);
+        assert_eq!(stop(&mut mem), Stop::SpeedSwitch);
+        assert_eq!(mem.read_word(0xFF4D), 0x7E);
+
+        // KEY1 doesn't exist on a DMG
+        let mut mem = memory(Model::Dmg);
+        mem.write_word(0xFF4D, 0x01);
+        assert_eq!(stop(&mut mem), Stop::Stopped);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+use crate::CYCLES_PER_FRAME;
+
+const DOTS_PER_LINE: u16 = 456;
+const LINES_PER_FRAME: u8 = 154;
+const VBLANK_LINE: u8 = 144;
+const OAM_SCAN_DOTS: u16 = 80;
+const DRAWING_DOTS: u16 = 172;
+
+/// The PPU's registers and timing. Nothing is rendered yet, but LY, the STAT
+/// modes and the VBlank/STAT interrupts advance like on hardware.
+pub struct Ppu {
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    dot: u16,
+    /// Frame time passed while the LCD is off, which still paces frames.
+    off_cycles: u32,
+    stat_line: bool,
+    frame_ready: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+pub struct PpuInterrupts {
+    pub vblank: bool,
+    pub stat: bool,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self {
+            lcdc: 0x91,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0xFC,
+            obp0: 0xFF,
+            obp1: 0xFF,
+            wy: 0,
+            wx: 0,
+            dot: 0,
+            off_cycles: 0,
+            stat_line: false,
+            frame_ready: false,
+        }
+    }
+
+    /// Advances the PPU by one M-cycle, which is four dots, or two in CGB double speed.
+    pub fn tick(&mut self, dots: u16) -> PpuInterrupts {
+        let mut ints = PpuInterrupts {
+            vblank: false,
+            stat: false,
+        };
+
+        if !self.enabled() {
+            self.pace_frame(dots as u32);
+            return ints;
+        }
+
+        self.dot += dots;
+        if self.dot >= DOTS_PER_LINE {
+            self.dot -= DOTS_PER_LINE;
+            self.ly = (self.ly + 1) % LINES_PER_FRAME;
+            if self.ly == VBLANK_LINE {
+                ints.vblank = true;
+                self.frame_ready = true;
+            }
+        }
+
+        // The STAT interrupt fires on rising edges of the OR of all enabled sources
+        let stat_line = self.stat_line();
+        ints.stat = stat_line && !self.stat_line;
+        self.stat_line = stat_line;
+
+        ints
+    }
+
+    /// Nothing is shown while the system clock is stopped, but frames are still
+    /// paced like with the LCD off so the frontend keeps running.
+    pub fn tick_stopped(&mut self) {
+        self.pace_frame(4);
+    }
+
+    fn pace_frame(&mut self, cycles: u32) {
+        self.off_cycles += cycles;
+        if self.off_cycles >= CYCLES_PER_FRAME {
+            self.off_cycles -= CYCLES_PER_FRAME;
+            self.frame_ready = true;
+        }
+    }
+
+    pub fn frame_ready(&self) -> bool {
+        self.frame_ready
+    }
+
+    /// Returns whether a frame was completed since the last call.
+    pub fn take_frame(&mut self) -> bool {
+        core::mem::replace(&mut self.frame_ready, false)
+    }
+
+    pub fn mode(&self) -> Mode {
+        if !self.enabled() {
+            Mode::HBlank
+        } else if self.ly >= VBLANK_LINE {
+            Mode::VBlank
+        } else if self.dot < OAM_SCAN_DOTS {
+            Mode::OamScan
+        } else if self.dot < OAM_SCAN_DOTS + DRAWING_DOTS {
+            Mode::Drawing
+        } else {
+            Mode::HBlank
+        }
+    }
+
+    pub fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0xFF40 => {
+                let was_enabled = self.enabled();
+                self.lcdc = val;
+                if was_enabled && !self.enabled() {
+                    self.ly = 0;
+                    self.dot = 0;
+                    self.stat_line = false;
+                } else if !was_enabled && self.enabled() {
+                    self.off_cycles = 0;
+                }
+            }
+            0xFF41 => self.stat = val & 0x78,
+            0xFF42 => self.scy = val,
+            0xFF43 => self.scx = val,
+            0xFF44 => {} // read-only
+            0xFF45 => self.lyc = val,
+            0xFF47 => self.bgp = val,
+            0xFF48 => self.obp0 = val,
+            0xFF49 => self.obp1 = val,
+            0xFF4A => self.wy = val,
+            0xFF4B => self.wx = val,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => {
+                let coincidence = if self.enabled() && self.ly == self.lyc {
+                    0x04
+                } else {
+                    0x00
+                };
+                0x80 | self.stat | coincidence | self.mode() as u8
+            }
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => unreachable!(),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.lcdc & 0x80 == 0x80
+    }
+
+    fn stat_line(&self) -> bool {
+        let mode = self.mode();
+        (self.stat & 0x40 == 0x40 && self.ly == self.lyc)
+            || (self.stat & 0x20 == 0x20 && mode == Mode::OamScan)
+            || (self.stat & 0x10 == 0x10 && mode == Mode::VBlank)
+            || (self.stat & 0x08 == 0x08 && mode == Mode::HBlank)
+    }
+}
+
+impl Snapshot for Ppu {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_bytes(&[
+            self.lcdc, self.stat, self.scy, self.scx, self.ly, self.lyc, self.bgp, self.obp0,
+            self.obp1, self.wy, self.wx,
+        ]);
+        w.write_u16(self.dot);
+        w.write_u32(self.off_cycles);
+        w.write_bool(self.stat_line);
+        w.write_bool(self.frame_ready);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        let mut regs = [0; 11];
+        r.read_bytes(&mut regs)?;
+        let [lcdc, stat, scy, scx, ly, lyc, bgp, obp0, obp1, wy, wx] = regs;
+        if ly >= LINES_PER_FRAME {
+            return Err(StateError::Invalid);
+        }
+        self.lcdc = lcdc;
+        self.stat = stat & 0x78;
+        self.scy = scy;
+        self.scx = scx;
+        self.ly = ly;
+        self.lyc = lyc;
+        self.bgp = bgp;
+        self.obp0 = obp0;
+        self.obp1 = obp1;
+        self.wy = wy;
+        self.wx = wx;
+
+        self.dot = r.read_u16()?;
+        self.off_cycles = r.read_u32()?;
+        if self.dot >= DOTS_PER_LINE || self.off_cycles >= CYCLES_PER_FRAME {
+            return Err(StateError::Invalid);
+        }
+        self.stat_line = r.read_bool()?;
+        self.frame_ready = r.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+//! Rewinding through recent history.
+//!
+//! Only the newest snapshot is kept in full. Every older one is stored as the
+//! XOR of it and its successor, run-length encoded, which is tiny as most of
+//! the machine doesn't change between frames. Stepping back decodes the
+//! newest delta into the full snapshot, so memory use is one full state plus
+//! the deltas, and the oldest deltas are dropped once that exceeds the budget.
+
+use crate::Gameboy;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+#[derive(Copy, Clone, Debug)]
+pub struct RewindConfig {
+    /// Frames between two snapshots, i.e. how far a single step goes back.
+    pub interval: u32,
+    /// Bytes the full snapshot and all deltas may use together.
+    pub budget: usize,
+}
+
+impl Default for RewindConfig {
+    /// A snapshot per frame and 1 MiB, several seconds for most games on the host.
+    fn default() -> Self {
+        Self {
+            interval: 1,
+            budget: 1 << 20,
+        }
+    }
+}
+
+pub struct Rewind {
+    config: RewindConfig,
+    /// The newest snapshot.
+    current: Vec<u8>,
+    /// `deltas[i]` turns snapshot `i + 1` into snapshot `i`, the newest delta is at the back.
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+    /// Frames run since `current` was taken or loaded.
+    frames_since: u32,
+}
+
+impl Rewind {
+    pub fn new(config: RewindConfig) -> Self {
+        Self {
+            config,
+            current: Vec::new(),
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+            frames_since: 0,
+        }
+    }
+
+    /// Call once per emulated frame, takes a snapshot every `interval` frames.
+    pub fn push_frame(&mut self, gameboy: &Gameboy) {
+        self.frames_since += 1;
+        if self.current.is_empty() || self.frames_since >= self.config.interval {
+            self.capture(gameboy);
+        }
+    }
+
+    /// Takes a snapshot right away.
+    pub fn capture(&mut self, gameboy: &Gameboy) {
+        let state = gameboy.save_state();
+        if state.len() == self.current.len() {
+            let delta = encode_delta(&state, &self.current);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        } else {
+            self.deltas.clear();
+            self.delta_bytes = 0;
+        }
+        self.current = state;
+        self.frames_since = 0;
+
+        while self.used_bytes() > self.config.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.delta_bytes -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Loads the previous snapshot into `gameboy`, returns `false` once history is exhausted.
+    /// Emulation can resume from there, new snapshots then replace the undone future.
+    pub fn step_back(&mut self, gameboy: &mut Gameboy) -> bool {
+        // The newest snapshot is the present if nothing ran since it was taken
+        if self.frames_since == 0 && !self.pop() {
+            return false;
+        }
+        if self.current.is_empty() || gameboy.load_state(&self.current).is_err() {
+            return false;
+        }
+        self.frames_since = 0;
+        true
+    }
+
+    /// Number of snapshots that can be stepped back to.
+    pub fn len(&self) -> usize {
+        if self.current.is_empty() {
+            0
+        } else if self.frames_since == 0 {
+            // `current` is the present, not a step back
+            self.deltas.len()
+        } else {
+            self.deltas.len() + 1
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.current.len() + self.delta_bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.current.clear();
+        self.deltas.clear();
+        self.delta_bytes = 0;
+        self.frames_since = 0;
+    }
+
+    /// Replaces `current` with the snapshot before it, leaves it alone if there is none.
+    fn pop(&mut self) -> bool {
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                self.delta_bytes -= delta.len();
+                apply_delta(&mut self.current, &delta);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Encodes `old ^ new` as pairs of (unchanged run length, changed run length)
+/// followed by the XORed bytes of the changed run, all lengths as LEB128.
+fn encode_delta(new: &[u8], old: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < new.len() {
+        let same = new[pos..]
+            .iter()
+            .zip(&old[pos..])
+            .take_while(|(a, b)| a == b)
+            .count();
+        pos += same;
+        let changed = new[pos..]
+            .iter()
+            .zip(&old[pos..])
+            .take_while(|(a, b)| a != b)
+            .count();
+
+        write_leb128(&mut out, same);
+        write_leb128(&mut out, changed);
+        out.extend(
+            new[pos..pos + changed]
+                .iter()
+                .zip(&old[pos..])
+                .map(|(a, b)| a ^ b),
+        );
+        pos += changed;
+    }
+    out
+}
+
+fn apply_delta(state: &mut [u8], delta: &[u8]) {
+    let mut pos = 0;
+    let mut delta = delta;
+    while !delta.is_empty() {
+        pos += read_leb128(&mut delta);
+        let changed = read_leb128(&mut delta);
+        for (byte, xor) in state[pos..pos + changed].iter_mut().zip(&delta[..changed]) {
+            *byte ^= xor;
+        }
+        delta = &delta[changed..];
+        pos += changed;
+    }
+}
+
+fn write_leb128(out: &mut Vec<u8>, mut val: usize) {
+    loop {
+        let byte = (val & 0x7F) as u8;
+        val >>= 7;
+        if val == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_leb128(buf: &mut &[u8]) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[0];
+        *buf = &buf[1..];
+        val |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn leb128_round_trips() {
+        for &val in &[0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 0x12_3456, usize::MAX] {
+            let mut buf = Vec::new();
+            write_leb128(&mut buf, val);
+            let mut rest = &buf[..];
+            assert_eq!(read_leb128(&mut rest), val);
+            assert!(rest.is_empty());
+        }
+    }
+
+    #[test]
+    fn leb128_uses_7_bits_per_byte() {
+        let mut buf = Vec::new();
+        write_leb128(&mut buf, 0x7F);
+        write_leb128(&mut buf, 0x80);
+        write_leb128(&mut buf, 300);
+        assert_eq!(buf, [0x7F, 0x80, 0x01, 0xAC, 0x02]);
+    }
+
+    #[test]
+    fn delta_turns_new_into_old() {
+        let old: Vec<u8> = (0..=255).collect();
+        let mut new = old.clone();
+        new[0] = 0xFF;
+        new[100..110].iter_mut().for_each(|byte| *byte = 0);
+        new[255] ^= 1;
+
+        let delta = encode_delta(&new, &old);
+        let mut state = new.clone();
+        apply_delta(&mut state, &delta);
+        assert_eq!(state, old);
+    }
+
+    #[test]
+    fn delta_of_identical_states_is_one_run() {
+        let state = [0x42; 1000];
+        let delta = encode_delta(&state, &state);
+        // 1000 unchanged bytes, 0 changed ones
+        assert_eq!(delta, [0xE8, 0x07, 0x00]);
+
+        let mut copy = state;
+        apply_delta(&mut copy, &delta);
+        assert_eq!(copy, state);
+    }
+
+    #[test]
+    fn delta_of_empty_states_is_empty() {
+        assert!(encode_delta(&[], &[]).is_empty());
+    }
+
+    /// A machine that increments A forever, so every frame has a different state.
+    fn counter() -> Gameboy {
+        // loop: INC A; JR loop
+        testing::gameboy(&[0x3C, 0x18, 0xFD])
+    }
+
+    #[test]
+    fn steps_back_through_every_snapshot() {
+        let mut gameboy = counter();
+        let mut rewind = Rewind::new(RewindConfig::default());
+        let mut states = Vec::new();
+        for _ in 0..4 {
+            gameboy.run_frame();
+            rewind.push_frame(&gameboy);
+            states.push(gameboy.save_state());
+        }
+        assert_eq!(rewind.len(), 3);
+
+        // Nothing ran since the last snapshot, so the first step goes to the one before
+        for state in states.iter().rev().skip(1) {
+            assert!(rewind.step_back(&mut gameboy));
+            assert_eq!(&gameboy.save_state(), state);
+        }
+        assert_eq!(rewind.len(), 0);
+        assert!(!rewind.step_back(&mut gameboy));
+        assert_eq!(gameboy.save_state(), states[0]);
+    }
+
+    #[test]
+    fn first_step_back_returns_to_the_newest_snapshot_after_running() {
+        let mut gameboy = counter();
+        let mut rewind = Rewind::new(RewindConfig {
+            interval: 2,
+            ..RewindConfig::default()
+        });
+        gameboy.run_frame();
+        rewind.push_frame(&gameboy);
+        let snapshot = gameboy.save_state();
+        gameboy.run_frame();
+        rewind.push_frame(&gameboy);
+        assert_eq!(rewind.len(), 1);
+
+        assert!(rewind.step_back(&mut gameboy));
+        assert_eq!(gameboy.save_state(), snapshot);
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn failed_step_back_keeps_the_snapshot() {
+        let mut gameboy = counter();
+        let mut rewind = Rewind::new(RewindConfig::default());
+        gameboy.run_frame();
+        rewind.capture(&gameboy);
+        let used = rewind.used_bytes();
+        assert_eq!(rewind.len(), 0);
+
+        assert!(!rewind.step_back(&mut gameboy));
+        assert_eq!(rewind.used_bytes(), used);
+
+        // Once the machine moved on, the snapshot is still there to go back to
+        gameboy.run_frame();
+        rewind.push_frame(&gameboy);
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn budget_drops_the_oldest_deltas() {
+        let mut gameboy = counter();
+        let mut rewind = Rewind::new(RewindConfig::default());
+        rewind.capture(&gameboy);
+        let full = rewind.used_bytes();
+        rewind.config.budget = full + 1;
+        for _ in 0..8 {
+            gameboy.run_frame();
+            rewind.push_frame(&gameboy);
+            assert!(rewind.used_bytes() <= rewind.config.budget);
+        }
+        assert_eq!(rewind.len(), 0);
+    }
+}
@@ -0,0 +1,233 @@
+//! Where the cartridge ROM comes from.
+//!
+//! The emulator never needs the whole ROM at once, only single bytes, so a ROM
+//! can live in flash, in an owned buffer, or on a block device that is paged
+//! in on demand.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub trait RomSource {
+    fn len(&self) -> usize;
+
+    /// Reads the byte at `offset`, which is always below [`len`](Self::len).
+    fn read(&mut self, offset: usize) -> u8;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A ROM in flash or any other memory that outlives the emulator.
+impl RomSource for &'static [u8] {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn read(&mut self, offset: usize) -> u8 {
+        self[offset]
+    }
+}
+
+/// A ROM loaded into RAM, e.g. read from a file on the host.
+impl RomSource for Vec<u8> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn read(&mut self, offset: usize) -> u8 {
+        self[offset]
+    }
+}
+
+impl RomSource for Box<[u8]> {
+    fn len(&self) -> usize {
+        <[u8]>::len(self)
+    }
+
+    fn read(&mut self, offset: usize) -> u8 {
+        self[offset]
+    }
+}
+
+/// Storage that can only be read in fixed-size blocks, like an SD card.
+pub trait BlockDevice {
+    const BLOCK_SIZE: usize;
+
+    /// Fills `buf`, which is `BLOCK_SIZE` bytes long, with block `idx`.
+    fn read_block(&mut self, idx: usize, buf: &mut [u8]);
+}
+
+/// A ROM of `len` bytes stored from the first block of a [`BlockDevice`] on,
+/// with the most recently used blocks cached in RAM.
+pub struct PagedRom<D: BlockDevice> {
+    device: D,
+    len: usize,
+    pages: Vec<Page>,
+    max_pages: usize,
+    /// The page the last read hit, consecutive reads mostly hit it again.
+    last: usize,
+    /// Counts page switches, to stamp pages with when they were last used.
+    clock: u64,
+}
+
+struct Page {
+    idx: usize,
+    data: Box<[u8]>,
+    used: u64,
+}
+
+impl<D: BlockDevice> PagedRom<D> {
+    /// Caches up to `pages` blocks, at least one.
+    pub fn new(device: D, len: usize, pages: usize) -> Self {
+        Self {
+            device,
+            len,
+            pages: Vec::with_capacity(pages.max(1)),
+            max_pages: pages.max(1),
+            last: 0,
+            clock: 0,
+        }
+    }
+
+    /// Finds block `idx` in the cache, reading it in place of the least recently
+    /// used one on a miss, and returns its slot.
+    fn switch(&mut self, idx: usize) -> usize {
+        self.clock += 1;
+        let slot = match self.pages.iter().position(|page| page.idx == idx) {
+            Some(slot) => slot,
+            None => {
+                let slot = if self.pages.len() < self.max_pages {
+                    self.pages.push(Page {
+                        idx,
+                        data: vec![0; D::BLOCK_SIZE].into_boxed_slice(),
+                        used: 0,
+                    });
+                    self.pages.len() - 1
+                } else {
+                    (0..self.pages.len())
+                        .min_by_key(|&slot| self.pages[slot].used)
+                        .unwrap()
+                };
+                let page = &mut self.pages[slot];
+                page.idx = idx;
+                self.device.read_block(idx, &mut page.data);
+                slot
+            }
+        };
+        self.pages[slot].used = self.clock;
+        slot
+    }
+}
+
+impl<D: BlockDevice> RomSource for PagedRom<D> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn read(&mut self, offset: usize) -> u8 {
+        let idx = offset / D::BLOCK_SIZE;
+        // The last page is always the most recently used, so it needs no new stamp
+        if !matches!(self.pages.get(self.last), Some(page) if page.idx == idx) {
+            self.last = self.switch(idx);
+        }
+        self.pages[self.last].data[offset % D::BLOCK_SIZE]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Diagnostics;
+    use crate::{testing, Gameboy};
+    use alloc::rc::Rc;
+    use core::cell::RefCell;
+
+    /// A device holding `data`, logging which blocks are read.
+    struct Device {
+        data: Vec<u8>,
+        reads: Rc<RefCell<Vec<usize>>>,
+    }
+
+    impl BlockDevice for Device {
+        const BLOCK_SIZE: usize = 0x100;
+
+        fn read_block(&mut self, idx: usize, buf: &mut [u8]) {
+            self.reads.borrow_mut().push(idx);
+            buf.copy_from_slice(&self.data[idx * Self::BLOCK_SIZE..][..Self::BLOCK_SIZE]);
+        }
+    }
+
+    fn paged(data: Vec<u8>, pages: usize) -> (PagedRom<Device>, Rc<RefCell<Vec<usize>>>) {
+        let reads = Rc::new(RefCell::new(Vec::new()));
+        let len = data.len();
+        let device = Device {
+            data,
+            reads: reads.clone(),
+        };
+        (PagedRom::new(device, len, pages), reads)
+    }
+
+    #[test]
+    fn reads_across_blocks() {
+        let data: Vec<u8> = (0..0x400).map(|i| (i ^ (i >> 8)) as u8).collect();
+        let (mut rom, reads) = paged(data.clone(), 2);
+        assert_eq!(rom.len(), 0x400);
+        for (offset, &val) in data.iter().enumerate().take(0x310).skip(0xF0) {
+            assert_eq!(rom.read(offset), val, "{:04X}", offset);
+        }
+        // Each block is read once, when the reads cross into it
+        assert_eq!(*reads.borrow(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let (mut rom, reads) = paged(vec![0; 0x400], 2);
+        for offset in [0x000, 0x100, 0x000, 0x200, 0x000, 0x100] {
+            rom.read(offset);
+        }
+        // Block 1 went first as block 0 was used since, then block 2
+        assert_eq!(*reads.borrow(), [0, 1, 2, 1]);
+
+        // Reads within a block don't count as uses of the others
+        let (mut rom, reads) = paged(vec![0; 0x400], 2);
+        for offset in [0x000, 0x100, 0x101, 0x1FF, 0x200, 0x000] {
+            rom.read(offset);
+        }
+        assert_eq!(*reads.borrow(), [0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn caches_at_least_one_block() {
+        let (mut rom, reads) = paged(vec![0; 0x400], 0);
+        for offset in [0x000, 0x001, 0x100, 0x000] {
+            rom.read(offset);
+        }
+        assert_eq!(*reads.borrow(), [0, 1, 0]);
+    }
+
+    #[test]
+    fn switches_banks() {
+        let mut data = testing::rom(0x01, 4);
+        for bank in 1..4 {
+            data[bank * 0x4000] = bank as u8;
+            data[bank * 0x4000 + 0x3FFF] = 0x10 + bank as u8;
+        }
+        let (rom, reads) = paged(data, 4);
+        let mut gameboy = Gameboy::new(rom, Diagnostics::default()).unwrap();
+
+        for bank in [2, 3, 1, 2] {
+            gameboy.poke(0x2000, bank);
+            assert_eq!(gameboy.peek(0x4000), bank);
+            assert_eq!(gameboy.peek(0x7FFF), 0x10 + bank);
+        }
+        // Banks are only read in when they're not cached
+        reads.borrow_mut().clear();
+        gameboy.poke(0x2000, 3);
+        gameboy.peek(0x4000);
+        gameboy.poke(0x2000, 2);
+        gameboy.peek(0x4000);
+        assert_eq!(*reads.borrow(), [0xC0]);
+    }
+}
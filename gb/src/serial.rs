@@ -0,0 +1,146 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+/// SB/SC. No link partner is ever attached, so every transfer shifts in 1s.
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    /// Bits left to shift in the current transfer.
+    bits: u8,
+    out: u8,
+    output: Option<u8>,
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            bits: 0,
+            out: 0,
+            output: None,
+        }
+    }
+
+    /// Advances the port by one M-cycle given the divider before and after the cycle,
+    /// returns `true` when the serial interrupt is requested.
+    pub fn tick(&mut self, old_div: u16, new_div: u16) -> bool {
+        // The internal clock runs at 8192 Hz, shifting on falling edges of divider bit 8
+        if self.bits == 0 || old_div & 0x100 == 0 || new_div & 0x100 != 0 {
+            return false;
+        }
+
+        self.sb = (self.sb << 1) | 0x1;
+        self.bits -= 1;
+        if self.bits == 0 {
+            self.sc &= 0x7F;
+            self.output = Some(self.out);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns the byte shifted out by the last transfer, if it hasn't been taken yet.
+    pub fn has_output(&self) -> bool {
+        self.output.is_some()
+    }
+
+    pub fn take_output(&mut self) -> Option<u8> {
+        self.output.take()
+    }
+
+    pub fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0xFF01 => self.sb = val,
+            0xFF02 => {
+                self.sc = val;
+                // Transfers on the external clock never complete without a partner
+                if val & 0x81 == 0x81 {
+                    self.bits = 8;
+                    self.out = self.sb;
+                } else {
+                    self.bits = 0;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0xFF01 => self.sb,
+            0xFF02 => self.sc | 0x7E,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Snapshot for Serial {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_u8(self.sb);
+        w.write_u8(self.sc);
+        w.write_u8(self.bits);
+        w.write_u8(self.out);
+        w.write_bool(self.output.is_some());
+        w.write_u8(self.output.unwrap_or(0));
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.sb = r.read_u8()?;
+        self.sc = r.read_u8()?;
+        self.bits = r.read_u8()?;
+        if self.bits > 8 {
+            return Err(StateError::Invalid);
+        }
+        self.out = r.read_u8()?;
+        let has_output = r.read_bool()?;
+        let output = r.read_u8()?;
+        self.output = if has_output { Some(output) } else { None };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ticks with a divider running like the timer's, returns the M-cycles
+    /// until the serial interrupt, if it came within `max`.
+    fn run(serial: &mut Serial, max: u32) -> Option<u32> {
+        let mut div: u16 = 0;
+        for cycle in 1..=max {
+            let old = div;
+            div = div.wrapping_add(4);
+            if serial.tick(old, div) {
+                return Some(cycle);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn internal_clock_shifts_at_8192_hz() {
+        let mut serial = Serial::new();
+        serial.write(0xFF01, 0x42);
+        serial.write(0xFF02, 0x81);
+        assert_eq!(serial.read(0xFF02), 0xFF);
+
+        // 8 bits, each on a falling edge of divider bit 8, every 128 M-cycles
+        assert_eq!(run(&mut serial, 2000), Some(8 * 128));
+        assert_eq!(serial.take_output(), Some(0x42));
+        assert_eq!(serial.take_output(), None);
+        // Nobody's on the other end, so 1s were shifted in
+        assert_eq!(serial.read(0xFF01), 0xFF);
+        assert_eq!(serial.read(0xFF02), 0x7F);
+    }
+
+    #[test]
+    fn external_clock_never_completes() {
+        let mut serial = Serial::new();
+        serial.write(0xFF01, 0x42);
+        serial.write(0xFF02, 0x80);
+        assert_eq!(run(&mut serial, 0x4000), None);
+        assert!(!serial.has_output());
+        assert_eq!(serial.read(0xFF01), 0x42);
+    }
+}
@@ -0,0 +1,166 @@
+//! Save-state format.
+//!
+//! A state is a header followed by every component's state in a fixed order:
+//!
+//! | Offset | Size | Content                                         |
+//! |--------|------|-------------------------------------------------|
+//! | 0      | 4    | Magic, `GBSS`                                   |
+//! | 4      | 2    | Format version                                  |
+//! | 6      | 19   | ROM title, header checksum and global checksum  |
+//! | 25     | ...  | CPU, memory, IO and cartridge state             |
+//!
+//! All multi-byte values are little-endian. States only load into a `Gameboy`
+//! running the ROM they were taken from, and only with the same format version.
+
+use alloc::vec::Vec;
+
+pub const MAGIC: [u8; 4] = *b"GBSS";
+pub const VERSION: u16 = 1;
+
+/// Length of the ROM identification in the header.
+pub const ROM_ID_LEN: usize = 19;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The data doesn't start with [`MAGIC`].
+    BadMagic,
+    /// The state was written by a different format version.
+    UnsupportedVersion(u16),
+    /// The state was taken from a different ROM.
+    RomMismatch,
+    /// The data ends early or has trailing bytes.
+    BadLength,
+    /// A field holds a value no emulator state can have.
+    Invalid,
+}
+
+/// Implemented by every component that is part of a save state.
+pub(crate) trait Snapshot {
+    fn save(&self, w: &mut StateWriter);
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+}
+
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    pub fn write_u8(&mut self, val: u8) {
+        self.buf.push(val);
+    }
+
+    pub fn write_bool(&mut self, val: bool) {
+        self.write_u8(val as u8);
+    }
+
+    pub fn write_u16(&mut self, val: u16) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, val: u32) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.buf.extend_from_slice(&val.to_le_bytes());
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct StateReader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, StateError> {
+        let mut bytes = [0; 1];
+        self.read_bytes(&mut bytes)?;
+        Ok(bytes[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, StateError> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, StateError> {
+        let mut bytes = [0; 2];
+        self.read_bytes(&mut bytes)?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, StateError> {
+        let mut bytes = [0; 4];
+        self.read_bytes(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        self.read_bytes(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self, out: &mut [u8]) -> Result<(), StateError> {
+        if self.buf.len() < out.len() {
+            return Err(StateError::BadLength);
+        }
+        let (bytes, rest) = self.buf.split_at(out.len());
+        out.copy_from_slice(bytes);
+        self.buf = rest;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+}
+
+pub(crate) fn write_header(w: &mut StateWriter, rom_id: &[u8; ROM_ID_LEN]) {
+    w.write_bytes(&MAGIC);
+    w.write_u16(VERSION);
+    w.write_bytes(rom_id);
+}
+
+pub(crate) fn read_header(
+    r: &mut StateReader,
+    rom_id: &[u8; ROM_ID_LEN],
+) -> Result<(), StateError> {
+    let mut magic = [0; 4];
+    r.read_bytes(&mut magic)?;
+    if magic != MAGIC {
+        return Err(StateError::BadMagic);
+    }
+
+    let version = r.read_u16()?;
+    if version != VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+
+    let mut id = [0; ROM_ID_LEN];
+    r.read_bytes(&mut id)?;
+    if &id != rom_id {
+        return Err(StateError::RomMismatch);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,302 @@
+//! Symbol files in the `.sym` format written by rgblink and read by no$gmb.
+//!
+//! Every line names one address, `BANK:ADDR label`, both in hex, e.g.
+//! `01:4000 Main.loop`. Comments start with `;`. Lines that don't parse are
+//! skipped, so files with extensions other tools added still load.
+//!
+//! The same address names different code depending on which ROM bank is mapped
+//! there, so lookups take the bank mapped at the address, see
+//! [`Gameboy::rom_bank`]. Only ROM banks are tracked,
+//! outside of ROM a symbol's bank is ignored.
+
+use crate::debugger::{parse_number, Breakpoint, ParseError};
+use crate::Gameboy;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub bank: u16,
+    pub addr: u16,
+    pub name: String,
+}
+
+/// A symbol and how far past it an address is, formats as e.g. `Main+$12`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Location<'a> {
+    pub name: &'a str,
+    pub offset: u16,
+}
+
+impl fmt::Display for Location<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)?;
+        if self.offset != 0 {
+            write!(f, "+${:X}", self.offset)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    /// Sorted by address.
+    symbols: Vec<Symbol>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut symbols: Vec<Symbol> = text.lines().filter_map(parse_line).collect();
+        symbols.sort_by_key(|sym| sym.addr);
+        Self { symbols }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|sym| sym.name == name)
+    }
+
+    /// The symbol right at `addr`, with `bank` the ROM bank mapped there.
+    pub fn label(&self, bank: Option<u16>, addr: u16) -> Option<&str> {
+        let start = self.symbols.partition_point(|sym| sym.addr < addr);
+        self.symbols[start..]
+            .iter()
+            .take_while(|sym| sym.addr == addr)
+            .find(|sym| in_bank(sym, bank))
+            .map(|sym| sym.name.as_str())
+    }
+
+    /// The closest symbol at or before `addr` in the same memory region, with
+    /// `bank` the ROM bank mapped there.
+    pub fn nearest(&self, bank: Option<u16>, addr: u16) -> Option<Location<'_>> {
+        let region = region_start(addr);
+        let end = self.symbols.partition_point(|sym| sym.addr <= addr);
+        self.symbols[..end]
+            .iter()
+            .rev()
+            .take_while(|sym| sym.addr >= region)
+            .find(|sym| in_bank(sym, bank))
+            .map(|sym| Location {
+                name: &sym.name,
+                offset: addr - sym.addr,
+            })
+    }
+
+    /// Parses an address given as a label or a number. A label in a ROM bank
+    /// that `gameboy` doesn't have mapped is refused, its address shows
+    /// another bank.
+    pub fn parse_addr(&self, s: &str, gameboy: &Gameboy) -> Result<u16, ParseError> {
+        match self.find(s.trim()) {
+            Some(sym) if in_bank(sym, gameboy.rom_bank(sym.addr)) => Ok(sym.addr),
+            Some(sym) => Err(ParseError::UnmappedBank(sym.bank)),
+            None => parse_number(s),
+        }
+    }
+
+    /// Parses a breakpoint like [`Breakpoint`]'s `FromStr`, but also takes a
+    /// label as its location, e.g. `Main.loop if A == 3`. Labels in ROM get
+    /// their bank as the breakpoint's.
+    pub fn parse_breakpoint(&self, s: &str) -> Result<Breakpoint, ParseError> {
+        let (location, condition) = match s.split_once(" if ") {
+            Some((location, condition)) => (location, Some(condition)),
+            None => (s, None),
+        };
+        let sym = match self.find(location.trim()) {
+            Some(sym) => sym,
+            None => return s.parse(),
+        };
+
+        let mut breakpoint = Breakpoint::new(sym.addr);
+        if sym.addr < 0x8000 {
+            breakpoint = breakpoint.with_bank(sym.bank);
+        }
+        if let Some(condition) = condition {
+            breakpoint = breakpoint.with_condition(condition.parse()?);
+        }
+        Ok(breakpoint)
+    }
+}
+
+fn parse_line(line: &str) -> Option<Symbol> {
+    let line = match line.split_once(';') {
+        Some((line, _comment)) => line,
+        None => line,
+    };
+    let mut parts = line.split_whitespace();
+    let (bank, addr) = parts.next()?.split_once(':')?;
+    let name = parts.next()?;
+    Some(Symbol {
+        bank: u16::from_str_radix(bank, 16).ok()?,
+        addr: u16::from_str_radix(addr, 16).ok()?,
+        name: name.into(),
+    })
+}
+
+// Not `is_none_or`, which older compilers than 1.82 lack
+#[allow(clippy::unnecessary_map_or)]
+fn in_bank(sym: &Symbol, bank: Option<u16>) -> bool {
+    sym.addr >= 0x8000 || bank.map_or(true, |bank| bank == sym.bank)
+}
+
+/// Where the memory region `addr` is in starts, symbols before that belong to
+/// something else.
+fn region_start(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x3FFF => 0x0000,
+        0x4000..=0x7FFF => 0x4000,
+        0x8000..=0x9FFF => 0x8000,
+        0xA000..=0xBFFF => 0xA000,
+        0xC000..=0xCFFF => 0xC000,
+        0xD000..=0xDFFF => 0xD000,
+        0xFF80..=0xFFFE => 0xFF80,
+        _ => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::{Cmp, Condition, Reg};
+    use crate::diagnostics::Diagnostics;
+    use crate::testing;
+    use alloc::string::ToString;
+
+    const SYM: &str = "\
+; File generated by rgblink
+00:0150 Start
+00:0158 Start.loop ; a local label
+01:4000 BankOne
+02:4000 BankTwo
+02:4010 BankTwo.end
+00:c000 wCounter
+01:d000 wBuffer
+ff:ff80 hTemp
+not a symbol
+00:zzzz Broken
+";
+
+    fn symbols() -> Symbols {
+        Symbols::parse(SYM)
+    }
+
+    /// An MBC1 cartridge with 4 banks, bank 1 is mapped at power on.
+    fn gameboy() -> Gameboy {
+        Gameboy::new(testing::rom(0x01, 4), Diagnostics::default()).unwrap()
+    }
+
+    #[test]
+    fn parses_sym_files() {
+        let symbols = symbols();
+        let names: Vec<&str> = symbols.iter().map(|sym| sym.name.as_str()).collect();
+        // Sorted by address, the bank order in the file kept
+        assert_eq!(
+            names,
+            [
+                "Start",
+                "Start.loop",
+                "BankOne",
+                "BankTwo",
+                "BankTwo.end",
+                "wCounter",
+                "wBuffer",
+                "hTemp"
+            ]
+        );
+        let sym = symbols.find("BankTwo.end").unwrap();
+        assert_eq!((sym.bank, sym.addr), (2, 0x4010));
+        assert!(symbols.find("Broken").is_none());
+        assert!(Symbols::parse("; nothing\n").is_empty());
+    }
+
+    #[test]
+    fn labels_match_the_mapped_bank() {
+        let symbols = symbols();
+        assert_eq!(symbols.label(Some(0), 0x0150), Some("Start"));
+        assert_eq!(symbols.label(Some(1), 0x4000), Some("BankOne"));
+        assert_eq!(symbols.label(Some(2), 0x4000), Some("BankTwo"));
+        assert_eq!(symbols.label(Some(3), 0x4000), None);
+        // Without a known bank any symbol matches
+        assert_eq!(symbols.label(None, 0x4000), Some("BankOne"));
+        assert_eq!(symbols.label(Some(0), 0x0151), None);
+        // Banks outside of ROM aren't tracked
+        assert_eq!(symbols.label(None, 0xD000), Some("wBuffer"));
+        assert_eq!(symbols.label(None, 0xFF80), Some("hTemp"));
+    }
+
+    #[test]
+    fn nearest_stays_in_region_and_bank() {
+        let symbols = symbols();
+        let nearest = |bank, addr| symbols.nearest(bank, addr).map(|loc| loc.to_string());
+        assert_eq!(nearest(Some(0), 0x0150).as_deref(), Some("Start"));
+        assert_eq!(nearest(Some(0), 0x0157).as_deref(), Some("Start+$7"));
+        assert_eq!(
+            nearest(Some(0), 0x3FFF).as_deref(),
+            Some("Start.loop+$3EA7")
+        );
+        assert_eq!(nearest(Some(2), 0x4012).as_deref(), Some("BankTwo.end+$2"));
+        assert_eq!(nearest(Some(1), 0x4012).as_deref(), Some("BankOne+$12"));
+        // Symbols don't reach into other memory regions
+        assert_eq!(nearest(Some(3), 0x4012), None);
+        assert_eq!(nearest(None, 0xCFFF).as_deref(), Some("wCounter+$FFF"));
+        assert_eq!(nearest(None, 0xFE00), None);
+        assert_eq!(nearest(Some(0), 0x0100), None);
+    }
+
+    #[test]
+    fn parses_labels_as_addresses() {
+        let symbols = symbols();
+        let gameboy = gameboy();
+        assert_eq!(symbols.parse_addr("Start.loop", &gameboy), Ok(0x0158));
+        assert_eq!(symbols.parse_addr(" BankOne ", &gameboy), Ok(0x4000));
+        assert_eq!(symbols.parse_addr("wBuffer", &gameboy), Ok(0xD000));
+        assert_eq!(symbols.parse_addr("$C000", &gameboy), Ok(0xC000));
+        assert_eq!(
+            symbols.parse_addr("BankTwo", &gameboy),
+            Err(ParseError::UnmappedBank(2))
+        );
+        assert_eq!(
+            symbols.parse_addr("Nowhere", &gameboy),
+            Err(ParseError::InvalidNumber)
+        );
+    }
+
+    #[test]
+    fn parses_labels_as_breakpoints() {
+        let symbols = symbols();
+        assert_eq!(
+            symbols.parse_breakpoint("BankTwo if A == 3"),
+            Ok(Breakpoint::new(0x4000)
+                .with_bank(2)
+                .with_condition(Condition {
+                    reg: Reg::A,
+                    cmp: Cmp::Eq,
+                    value: 3,
+                }))
+        );
+        // Breakpoints outside of ROM aren't banked
+        assert_eq!(
+            symbols.parse_breakpoint("wBuffer"),
+            Ok(Breakpoint::new(0xD000))
+        );
+        assert_eq!(
+            symbols.parse_breakpoint("01:4000"),
+            Ok(Breakpoint::new(0x4000).with_bank(1))
+        );
+        assert_eq!(
+            symbols.parse_breakpoint("Start if Q == 1"),
+            Err(ParseError::UnknownRegister)
+        );
+    }
+}
@@ -0,0 +1,40 @@
+//! ROMs and machines for the unit tests.
+
+use crate::diagnostics::Diagnostics;
+use crate::{Gameboy, Model};
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// A ROM of `banks` 16 KiB banks with a valid header for `cartridge_type`
+/// and NOPs everywhere else.
+pub(crate) fn rom(cartridge_type: u8, banks: usize) -> Vec<u8> {
+    let mut rom = vec![0; banks * 0x4000];
+    rom[0x0134..0x0138].copy_from_slice(b"TEST");
+    rom[0x0147] = cartridge_type;
+    rom[0x0148] = (banks / 2).trailing_zeros() as u8;
+    fix_header_checksum(&mut rom);
+    rom
+}
+
+/// Recomputes the header checksum after the header was changed.
+pub(crate) fn fix_header_checksum(rom: &mut [u8]) {
+    rom[0x014D] = rom[0x0134..=0x014C]
+        .iter()
+        .fold(0u8, |x, &byte| x.wrapping_sub(byte).wrapping_sub(1));
+}
+
+/// Where [`gameboy`] puts the code, the entry point jumps there.
+pub(crate) const CODE: u16 = 0x0150;
+
+/// A DMG running `code` at [`CODE`] on a 32 KiB ROM-only cartridge, stopped
+/// at the entry point.
+pub(crate) fn gameboy(code: &[u8]) -> Gameboy {
+    gameboy_with_model(code, Model::Dmg)
+}
+
+pub(crate) fn gameboy_with_model(code: &[u8], model: Model) -> Gameboy {
+    let mut rom = rom(0x00, 2);
+    rom[0x0100..0x0103].copy_from_slice(&[0xC3, CODE as u8, (CODE >> 8) as u8]);
+    rom[CODE as usize..CODE as usize + code.len()].copy_from_slice(code);
+    Gameboy::with_model(rom, model, Diagnostics::default()).unwrap()
+}
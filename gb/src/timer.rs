@@ -0,0 +1,204 @@
+use crate::state::{Snapshot, StateError, StateReader, StateWriter};
+
+/// DIV/TIMA/TMA/TAC. DIV is the upper byte of a 16-bit counter incremented every
+/// T-cycle, and TIMA counts falling edges of the counter bit selected by TAC.
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    reloading: bool,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reloading: false,
+        }
+    }
+
+    /// The internal 16-bit divider, whose upper byte is DIV.
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    /// Advances the timer by one M-cycle, returns `true` when the timer interrupt is requested.
+    pub fn tick(&mut self) -> bool {
+        // TIMA reads 0 for one M-cycle after overflowing before TMA gets loaded
+        let interrupt = self.reloading;
+        if self.reloading {
+            self.tima = self.tma;
+            self.reloading = false;
+        }
+
+        let input = self.input();
+        self.counter = self.counter.wrapping_add(4);
+        if input && !self.input() {
+            self.increment();
+        }
+
+        interrupt
+    }
+
+    pub fn write(&mut self, addr: usize, val: u8) {
+        match addr {
+            0xFF04 => {
+                let input = self.input();
+                self.counter = 0;
+                if input {
+                    self.increment();
+                }
+            }
+            0xFF05 => {
+                // Writing during the reload delay cancels the reload
+                self.tima = val;
+                self.reloading = false;
+            }
+            0xFF06 => self.tma = val,
+            0xFF07 => {
+                let input = self.input();
+                self.tac = val & 0x7;
+                if input && !self.input() {
+                    self.increment();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn read(&self, addr: usize) -> u8 {
+        match addr {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => self.tac | 0xF8,
+            _ => unreachable!(),
+        }
+    }
+
+    fn input(&self) -> bool {
+        let bit = match self.tac & 0x3 {
+            0x0 => 9,
+            0x1 => 3,
+            0x2 => 5,
+            0x3 => 7,
+            _ => unreachable!(),
+        };
+        self.tac & 0x4 == 0x4 && self.counter & (1 << bit) != 0
+    }
+
+    fn increment(&mut self) {
+        let (res, overflow) = self.tima.overflowing_add(1);
+        self.tima = res;
+        self.reloading = overflow;
+    }
+}
+
+impl Snapshot for Timer {
+    fn save(&self, w: &mut StateWriter) {
+        w.write_u16(self.counter);
+        w.write_u8(self.tima);
+        w.write_u8(self.tma);
+        w.write_u8(self.tac);
+        w.write_bool(self.reloading);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.counter = r.read_u16()?;
+        self.tima = r.read_u8()?;
+        self.tma = r.read_u8()?;
+        self.tac = r.read_u8()? & 0x7;
+        self.reloading = r.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A timer counting every 16 T-cycles, i.e. on every 4th M-cycle from reset.
+    fn fast_timer(tima: u8, tma: u8) -> Timer {
+        let mut timer = Timer::new();
+        timer.write(0xFF05, tima);
+        timer.write(0xFF06, tma);
+        timer.write(0xFF07, 0x05);
+        timer
+    }
+
+    #[test]
+    fn div_is_the_upper_counter_byte() {
+        let mut timer = Timer::new();
+        for _ in 0..64 {
+            timer.tick();
+        }
+        assert_eq!(timer.read(0xFF04), 0x01);
+        timer.write(0xFF04, 0x55);
+        assert_eq!(timer.read(0xFF04), 0x00);
+        assert_eq!(timer.counter(), 0);
+    }
+
+    #[test]
+    fn tima_counts_at_the_selected_rate() {
+        for (tac, m_cycles) in [(0x04, 256), (0x05, 4), (0x06, 16), (0x07, 64)] {
+            let mut timer = Timer::new();
+            timer.write(0xFF07, tac);
+            for _ in 0..m_cycles - 1 {
+                timer.tick();
+            }
+            assert_eq!(timer.read(0xFF05), 0, "TAC {:02X}", tac);
+            timer.tick();
+            assert_eq!(timer.read(0xFF05), 1, "TAC {:02X}", tac);
+        }
+    }
+
+    #[test]
+    fn overflow_reloads_a_cycle_later() {
+        let mut timer = fast_timer(0xFF, 0xAB);
+        for _ in 0..3 {
+            assert!(!timer.tick());
+        }
+        // Overflows, TIMA reads 0 for one M-cycle
+        assert!(!timer.tick());
+        assert_eq!(timer.read(0xFF05), 0x00);
+        // Then TMA is loaded and the interrupt requested
+        assert!(timer.tick());
+        assert_eq!(timer.read(0xFF05), 0xAB);
+        assert!(!timer.tick());
+    }
+
+    #[test]
+    fn writing_tima_cancels_the_reload() {
+        let mut timer = fast_timer(0xFF, 0xAB);
+        for _ in 0..4 {
+            timer.tick();
+        }
+        timer.write(0xFF05, 0x12);
+        assert!(!timer.tick());
+        assert_eq!(timer.read(0xFF05), 0x12);
+    }
+
+    #[test]
+    fn falling_edges_from_writes_count() {
+        // Resetting DIV while the selected bit is set is a falling edge
+        let mut timer = fast_timer(0x00, 0x00);
+        for _ in 0..2 {
+            timer.tick();
+        }
+        timer.write(0xFF04, 0);
+        assert_eq!(timer.read(0xFF05), 1);
+
+        // So is disabling the timer
+        let mut timer = fast_timer(0x00, 0x00);
+        for _ in 0..2 {
+            timer.tick();
+        }
+        timer.write(0xFF07, 0x01);
+        assert_eq!(timer.read(0xFF05), 1);
+        assert_eq!(timer.read(0xFF07), 0xF9);
+    }
+}
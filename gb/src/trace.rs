@@ -0,0 +1,99 @@
+//! CPU traces in the [Gameboy Doctor](https://github.com/robert/gameboy-doctor) format.
+//!
+//! Every instruction is logged before it executes as
+//! `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`,
+//! so a trace of a test ROM can be diffed line by line against a reference log.
+//! The reference logs were taken with LY stuck at 0x90, so it reads as that
+//! while tracing through a [`Gameboy`](crate::Gameboy).
+
+use crate::cpu::Registers;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::fmt;
+
+/// The state right before an instruction executes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceLine {
+    pub regs: Registers,
+    /// The 4 bytes starting at PC.
+    pub pcmem: [u8; 4],
+    /// The ROM bank mapped at PC, not part of the logged line.
+    pub bank: Option<u16>,
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let r = &self.regs;
+        write!(
+            f,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc,
+            self.pcmem[0], self.pcmem[1], self.pcmem[2], self.pcmem[3]
+        )
+    }
+}
+
+pub trait TraceSink {
+    fn trace(&mut self, line: &TraceLine);
+}
+
+/// Shared sinks let the owner look at the trace while the CPU still holds the sink.
+impl<S: TraceSink> TraceSink for Rc<RefCell<S>> {
+    fn trace(&mut self, line: &TraceLine) {
+        self.borrow_mut().trace(line);
+    }
+}
+
+/// Writes one line per instruction into any [`fmt::Write`], e.g. a `String` or
+/// a USART transmitter.
+pub struct WriteTraceSink<W: fmt::Write> {
+    writer: W,
+}
+
+impl<W: fmt::Write> WriteTraceSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: fmt::Write> TraceSink for WriteTraceSink<W> {
+    fn trace(&mut self, line: &TraceLine) {
+        let _ = writeln!(self.writer, "{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use alloc::string::String;
+
+    #[test]
+    fn traces_before_every_instruction() {
+        // LD A, $42; LDH A, (LY); INC B
+        let mut gameboy = testing::gameboy(&[0x3E, 0x42, 0xF0, 0x44, 0x04]);
+        let sink = Rc::new(RefCell::new(WriteTraceSink::new(String::new())));
+        gameboy.start_trace(sink.clone());
+        for _ in 0..5 {
+            gameboy.step_instruction();
+        }
+        gameboy.stop_trace();
+        gameboy.step_instruction();
+
+        let sink = Rc::try_unwrap(sink).ok().unwrap().into_inner();
+        assert_eq!(
+            sink.into_inner(),
+            "\
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:C3,50,01,00
+A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:3E,42,F0,44
+A:42 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:F0,44,04,00
+A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0154 PCMEM:04,00,00,00
+A:90 F:10 B:01 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0155 PCMEM:00,00,00,00
+"
+        );
+    }
+}
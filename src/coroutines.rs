@@ -1,47 +0,0 @@
-use core::future::Future;
-use core::pin::Pin;
-use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
-
-pub fn create_waker() -> Waker {
-    fn raw(ptr: *const ()) -> RawWaker {
-        RawWaker::new(ptr, &RawWakerVTable::new(raw, drop, drop, drop))
-    }
-    unsafe { Waker::from_raw(raw(core::ptr::null())) }
-}
-
-pub async fn yield_now() {
-    /// Yield implementation
-    struct YieldNow {
-        yielded: bool,
-    }
-
-    impl Future for YieldNow {
-        type Output = ();
-
-        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
-            if self.yielded {
-                return Poll::Ready(());
-            }
-
-            self.yielded = true;
-            cx.waker().wake_by_ref();
-            Poll::Pending
-        }
-    }
-
-    YieldNow { yielded: false }.await
-}
-
-#[macro_export]
-macro_rules! pin_mut {
-    ($($x:ident),* $(,)?) => { $(
-        // Move the value to ensure that it is owned
-        let mut $x = $x;
-        // Shadow the original binding so that it can't be directly accessed
-        // ever again.
-        #[allow(unused_mut)]
-        let mut $x = unsafe {
-            core::pin::Pin::new_unchecked(&mut $x)
-        };
-    )* }
-}